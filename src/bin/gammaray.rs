@@ -29,14 +29,11 @@ pub fn main() {
                 .build(),
         core::Mat::translation(&core::Vec::new(-25.0, 0.0, -50.0)),
         75.0);
+    let library = material::MaterialLibrary::studio();
     let s4 = geom::Sphere::new(
-        material::Material::disney()
-                .base_color(core::Vec::new(1.0, 1.0, 1.0))
-                .specular_trans(1.0)
-                .roughness(0.2)
-                .ior(1.8)
-                .metallic(0.0)
-                .build(),
+        library.build_with("glass", &[
+                ("roughness", material::PresetValue::Number(0.2)),
+                ("ior", material::PresetValue::Number(1.8))]).unwrap(),
         core::Mat::translation(&core::Vec::new(6.0, -10.0, -90.0)),
         4.0);
 
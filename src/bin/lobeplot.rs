@@ -0,0 +1,116 @@
+extern crate gammaray;
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::render;
+
+use std::sync::Arc;
+
+const USAGE: &str = "\
+Usage: lobeplot [key=value]...
+Plots a Disney material's f(), pdf(), and a histogram of its sampled directions to an EXR file.
+
+Plot options:
+  theta=DEGREES     angle of the incoming direction from the normal (default 45)
+  mode=camera|light direction that the incoming vector points (default camera)
+  height=PIXELS     height of the image; the width is twice the height (default 256)
+  samples=COUNT     number of directions to sample for the histogram (default 1000000)
+  lobe=INDEX        plot only the lobe with the given index instead of the whole material
+  out=PATH          output file (default lobe.exr)
+
+Material options:
+  base_color=R,G,B  roughness  anisotropic  ior  metallic  specular_trans  specular_tint
+  sheen  sheen_tint  clearcoat  clearcoat_gloss  subsurface  diff_trans  flatness
+  thin=true|false  thin_film_thickness  thin_film_ior";
+
+fn parse_f32(key: &str, value: &str) -> Result<f32, String> {
+    value.parse::<f32>().map_err(|_| format!("{} must be a number, not \"{}\"", key, value))
+}
+
+fn parse_vec(key: &str, value: &str) -> Result<core::Vec, String> {
+    let components = value.split(',')
+            .map(|c| parse_f32(key, c))
+            .collect::<Result<Vec<f32>, String>>()?;
+    match components.len() {
+        1 => Ok(components[0] * &core::Vec::one()),
+        3 => Ok(core::Vec::new(components[0], components[1], components[2])),
+        _ => Err(format!("{} must have one or three components", key))
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut plot = render::LobePlot::new(45.0);
+    let mut builder = material::Material::disney();
+    let mut lobe_index = None;
+    let mut out = String::from("lobe.exr");
+
+    for arg in std::env::args().skip(1) {
+        let mut split = arg.splitn(2, '=');
+        let key = split.next().unwrap();
+        let value = split.next().ok_or_else(|| format!("expected key=value, not \"{}\"", arg))?;
+        match key {
+            "theta" => plot.set_theta(parse_f32(key, value)?),
+            "mode" => plot.camera_to_light = match value {
+                "camera" => true,
+                "light" => false,
+                _ => return Err(format!("mode must be camera or light, not \"{}\"", value))
+            },
+            "height" => plot.height = value.parse::<usize>()
+                    .map_err(|_| String::from("height must be a positive integer"))?,
+            "samples" => plot.num_samples = value.parse::<usize>()
+                    .map_err(|_| String::from("samples must be a positive integer"))?,
+            "lobe" => lobe_index = Some(value.parse::<usize>()
+                    .map_err(|_| String::from("lobe must be an index"))?),
+            "out" => out = String::from(value),
+            "base_color" => { builder.base_color(parse_vec(key, value)?); },
+            "roughness" => { builder.roughness(parse_f32(key, value)?); },
+            "anisotropic" => { builder.anisotropic(parse_f32(key, value)?); },
+            "ior" => { builder.ior(parse_f32(key, value)?); },
+            "metallic" => { builder.metallic(parse_f32(key, value)?); },
+            "specular_trans" => { builder.specular_trans(parse_f32(key, value)?); },
+            "specular_tint" => { builder.specular_tint(parse_f32(key, value)?); },
+            "sheen" => { builder.sheen(parse_f32(key, value)?); },
+            "sheen_tint" => { builder.sheen_tint(parse_f32(key, value)?); },
+            "clearcoat" => { builder.clearcoat(parse_f32(key, value)?); },
+            "clearcoat_gloss" => { builder.clearcoat_gloss(parse_f32(key, value)?); },
+            "subsurface" => { builder.subsurface(parse_f32(key, value)?); },
+            "diff_trans" => { builder.diff_trans(parse_f32(key, value)?); },
+            "flatness" => { builder.flatness(parse_f32(key, value)?); },
+            "thin" => { builder.thin(value == "true"); },
+            "thin_film_thickness" => { builder.thin_film_thickness(parse_f32(key, value)?); },
+            "thin_film_ior" => { builder.thin_film_ior(parse_f32(key, value)?); },
+            _ => return Err(format!("unknown option \"{}\"", key))
+        }
+    }
+    if plot.height == 0 || plot.num_samples == 0 {
+        return Err(String::from("height and samples must be positive"));
+    }
+
+    let material = Arc::new(builder.build());
+    let lobes = material::Material::shared_lobes(&material, &geom::SurfaceProperties::zero());
+    for (i, lobe) in lobes.iter().enumerate() {
+        println!("Lobe {}: {}", i, &**lobe);
+    }
+
+    let mut rng = core::new_xor_shift_rng();
+    let film = match lobe_index {
+        Some(i) => {
+            let lobe = lobes.get(i).ok_or_else(|| format!("there's no lobe {}", i))?;
+            plot.lobe(&**lobe, &mut rng)
+        },
+        None => plot.material(&material, &mut rng)
+    };
+
+    let mut writer = render::ExrWriter::new(&out);
+    writer.update(&film);
+    writer.write();
+    println!("Wrote {}", out);
+    Ok(())
+}
+
+pub fn main() {
+    if let Err(message) = run() {
+        eprintln!("Error: {}\n\n{}", message, USAGE);
+        std::process::exit(1);
+    }
+}
@@ -15,12 +15,16 @@ pub use core::quat::Quat;
 
 mod random;
 pub use core::random::new_xor_shift_rng;
+pub use core::random::hash_to_unit;
 pub use core::random::AreaSampleDisk;
 pub use core::random::CosineSampleHemisphere;
 pub use core::random::CumulativeDistribution;
+pub use core::random::PiecewiseConstant1D;
+pub use core::random::PiecewiseConstant2D;
 pub use core::random::UniformSampleBarycentric;
 pub use core::random::UniformSampleSphere;
 pub use core::random::UniformSampleCone;
+pub use core::random::UniformSampleSphericalTriangle;
 
 mod ray;
 pub use core::ray::Ray;
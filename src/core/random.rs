@@ -1,3 +1,4 @@
+use core::math;
 use core::vector;
 
 use std;
@@ -19,6 +20,27 @@ pub fn new_xor_shift_rng() -> rand::XorShiftRng {
             thread_rng.next_u32()])
 }
 
+/**
+ * Hashes the given values into a number in [0, 1). The same values always give the same result,
+ * so this can stand in for a random sample in places that must make the same choice each time
+ * they see the same input, e.g. stochastic alpha testing during ray traversal.
+ *
+ * The mixing function is the finalizer from SplitMix64.
+ */
+pub fn hash_to_unit(values: &[f32]) -> f32 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for x in values {
+        h ^= x.to_bits() as u64;
+        h = h.wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+    }
+
+    // Use the top 24 bits so that the result is exactly representable and strictly less than 1.
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
 /**
  * Samples a unit disk, ensuring that the samples are uniformally distributed
  * throughout the area of the disk.
@@ -174,6 +196,10 @@ pub struct UniformSampleCone {
 }
 
 impl UniformSampleCone {
+    pub fn new(half_angle: f32) -> UniformSampleCone {
+        UniformSampleCone {half_angle}
+    }
+
     /**
      * Returns the probability that any solid angle already inside the given
      * cone was sampled uniformly from the cone. The cone is defined by the
@@ -229,6 +255,113 @@ impl IndependentSample<vector::Vec> for UniformSampleCone {
     }
 }
 
+/**
+ * Samples a spherical triangle uniformly with respect to solid angle. The triangle is given by
+ * the three unit vectors at its corners.
+ *
+ * Taken from Arvo, "Stratified Sampling of Spherical Triangles" (SIGGRAPH 1995), with the
+ * numerical fixes from PBRT 4e section 6.5.4.
+ */
+pub struct UniformSampleSphericalTriangle {
+    a: vector::Vec,
+    b: vector::Vec,
+    c: vector::Vec,
+}
+
+impl UniformSampleSphericalTriangle {
+    pub fn new(a: vector::Vec, b: vector::Vec, c: vector::Vec) -> UniformSampleSphericalTriangle {
+        UniformSampleSphericalTriangle {a, b, c}
+    }
+
+    /**
+     * Returns the solid angle subtended by the spherical triangle, using the formula from
+     * Van Oosterom and Strackee, "The Solid Angle of a Plane Triangle" (1983).
+     * The pdf of any direction inside the triangle is the inverse of this value.
+     */
+    pub fn solid_angle(&self) -> f32 {
+        let (a, b, c) = (&self.a, &self.b, &self.c);
+        let numer = f32::abs(a.dot(&b.cross(c)));
+        let denom = 1.0 + a.dot(b) + a.dot(c) + b.dot(c);
+        f32::abs(2.0 * f32::atan2(numer, denom))
+    }
+}
+
+/**
+ * Computes the angle between two unit vectors in a way that's accurate for nearly-parallel and
+ * nearly-opposite vectors.
+ */
+fn angle_between(v1: &vector::Vec, v2: &vector::Vec) -> f32 {
+    if v1.dot(v2) < 0.0 {
+        std::f32::consts::PI - 2.0 * f32::asin(f32::min(1.0, 0.5 * (v1 + v2).magnitude()))
+    }
+    else {
+        2.0 * f32::asin(f32::min(1.0, 0.5 * (v2 - v1).magnitude()))
+    }
+}
+
+/**
+ * Returns the component of v that is orthogonal to the unit vector w.
+ */
+fn gram_schmidt(v: &vector::Vec, w: &vector::Vec) -> vector::Vec {
+    v - &(v.dot(w) * w)
+}
+
+impl Sample<vector::Vec> for UniformSampleSphericalTriangle {
+    fn sample<R>(&mut self, rng: &mut R) -> vector::Vec where R: Rng {
+        self.ind_sample(rng)
+    }
+}
+
+impl IndependentSample<vector::Vec> for UniformSampleSphericalTriangle {
+    fn ind_sample<R>(&self, rng: &mut R) -> vector::Vec where R: Rng {
+        let (a, b, c) = (&self.a, &self.b, &self.c);
+
+        // Compute the normals of the planes through each edge of the triangle.
+        let n_ab = a.cross(b);
+        let n_bc = b.cross(c);
+        let n_ca = c.cross(a);
+        if n_ab.is_exactly_zero() || n_bc.is_exactly_zero() || n_ca.is_exactly_zero() {
+            // Degenerate triangle.
+            return *a;
+        }
+        let n_ab = n_ab.normalized();
+        let n_bc = n_bc.normalized();
+        let n_ca = n_ca.normalized();
+
+        // Spherical angles at each vertex; the triangle's area is their excess over Pi.
+        let alpha = angle_between(&n_ab, &-&n_ca);
+        let beta = angle_between(&n_bc, &-&n_ab);
+        let gamma = angle_between(&n_ca, &-&n_bc);
+
+        // Uniformly sample a sub-triangle area, and find the vertex c' that produces it.
+        let (u0, u1) = (rng.next_f32(), rng.next_f32());
+        let area_pi = alpha + beta + gamma;
+        let sub_area_pi = math::lerp(std::f32::consts::PI, area_pi, u0);
+        let (sin_alpha, cos_alpha) = (f32::sin(alpha), f32::cos(alpha));
+        let sin_phi = f32::sin(sub_area_pi) * cos_alpha - f32::cos(sub_area_pi) * sin_alpha;
+        let cos_phi = f32::cos(sub_area_pi) * cos_alpha + f32::sin(sub_area_pi) * sin_alpha;
+        let k1 = cos_phi + cos_alpha;
+        let k2 = sin_phi - sin_alpha * a.dot(b);
+        let cos_b = math::clamp(
+                (k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha) /
+                ((k2 * sin_phi + k1 * cos_phi) * sin_alpha),
+                -1.0, 1.0);
+        let sin_b = f32::sqrt(f32::max(0.0, 1.0 - cos_b * cos_b));
+        let c_prime = &(cos_b * a) + &(sin_b * &gram_schmidt(c, a).normalized());
+
+        // Uniformly sample the arc between b and c'.
+        let cos_theta = 1.0 - u1 * (1.0 - c_prime.dot(b));
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let dir = &(cos_theta * b) + &(sin_theta * &gram_schmidt(&c_prime, b).normalized());
+        if dir.is_finite() {
+            dir.normalized()
+        }
+        else {
+            *a
+        }
+    }
+}
+
 pub struct CumulativeDistribution {
     cdf: std::vec::Vec<f32>,
 }
@@ -272,3 +405,115 @@ impl IndependentSample<(f32, f32)> for UniformSampleBarycentric {
         (1.0 - sqrt_a, b * sqrt_a)
     }
 }
+
+/// A piecewise-constant distribution over [0, 1), defined by tabulated non-negative function
+/// values, which can be sampled continuously. See PBRT 3e p. 758.
+pub struct PiecewiseConstant1D {
+    func: std::vec::Vec<f32>,
+    cdf: std::vec::Vec<f32>,
+    func_int: f32,
+}
+
+impl PiecewiseConstant1D {
+    pub fn new(func: std::vec::Vec<f32>) -> PiecewiseConstant1D {
+        debug_assert!(!func.is_empty());
+        let n = func.len();
+
+        // Integrate the step function, then normalize. A function that's zero everywhere
+        // degenerates into a uniform distribution.
+        let mut cdf = std::vec::Vec::<f32>::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            let prev = cdf[i];
+            cdf.push(prev + func[i] / n as f32);
+        }
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if func_int == 0.0 { i as f32 / n as f32 } else { *c / func_int };
+        }
+
+        PiecewiseConstant1D {func, cdf, func_int}
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// The integral of the function over [0, 1).
+    pub fn integral(&self) -> f32 {
+        self.func_int
+    }
+
+    /// Maps a uniform random number to a point in [0, 1). Returns the point, its pdf, and the
+    /// index of the segment containing it.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        // Find the last cdf entry that is less than or equal to u.
+        let n = self.count();
+        let offset = match self.cdf.binary_search_by(|x| x.partial_cmp(&u).unwrap()) {
+            Ok(x) => x,
+            Err(x) => x - 1,
+        };
+        let offset = math::clamp(offset, 0, n - 1);
+
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        let x = f32::min((offset as f32 + du) / n as f32, 1.0 - f32::EPSILON);
+        (x, self.pdf_segment(offset), offset)
+    }
+
+    /// Returns the pdf of sampling the given point in [0, 1).
+    pub fn pdf(&self, x: f32) -> f32 {
+        let n = self.count();
+        let offset = math::clamp((x * n as f32) as usize, 0, n - 1);
+        self.pdf_segment(offset)
+    }
+
+    fn pdf_segment(&self, offset: usize) -> f32 {
+        if self.func_int == 0.0 {
+            1.0
+        }
+        else {
+            self.func[offset] / self.func_int
+        }
+    }
+}
+
+/// A piecewise-constant distribution over [0, 1)^2, sampled by first choosing v from the
+/// marginal distribution and then u from the conditional distribution. See PBRT 3e p. 761.
+pub struct PiecewiseConstant2D {
+    conditional: std::vec::Vec<PiecewiseConstant1D>,
+    marginal: PiecewiseConstant1D,
+}
+
+impl PiecewiseConstant2D {
+    /// Creates a distribution from function values stored in row-major order, i.e. the value
+    /// at (u, v) is func[v * nu + u].
+    pub fn new(func: &[f32], nu: usize, nv: usize) -> PiecewiseConstant2D {
+        debug_assert!(func.len() == nu * nv);
+        let conditional: std::vec::Vec<PiecewiseConstant1D> = func.chunks(nu)
+                .map(|row| PiecewiseConstant1D::new(row.to_vec()))
+                .collect();
+        let marginal = PiecewiseConstant1D::new(
+                conditional.iter().map(|c| c.integral()).collect());
+        PiecewiseConstant2D {conditional, marginal}
+    }
+
+    /// The integral of the function over [0, 1)^2.
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// Maps two uniform random numbers to a point in [0, 1)^2. Returns the point and its pdf.
+    pub fn sample_continuous(&self, u0: f32, u1: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, offset) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[offset].sample_continuous(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// Returns the pdf of sampling the given point in [0, 1)^2.
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let nv = self.conditional.len();
+        let offset = math::clamp((v * nv as f32) as usize, 0, nv - 1);
+        self.conditional[offset].pdf(u) * self.marginal.pdf(v)
+    }
+}
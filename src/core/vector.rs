@@ -76,6 +76,10 @@ impl Vec {
         Self::new(f32::sqrt(self.x), f32::sqrt(self.y), f32::sqrt(self.z))
     }
 
+    pub fn exp(&self) -> Vec {
+        Self::new(f32::exp(self.x), f32::exp(self.y), f32::exp(self.z))
+    }
+
     /**
      * Determines whether a vec's magnitude is zero, within a small epsilon.
      */
@@ -110,6 +110,7 @@ pub enum Intersection {
         dist: f32,
         surface_props: prim::SurfaceProperties,
         prim_index: usize,
+        component_index: usize,
     },
     NoHit
 }
@@ -122,14 +123,21 @@ pub struct LightSample {
     pub dir_pdf: f32,
 }
 
+/// A point on a light sampled as seen from a reference point. The pdf is with respect to solid
+/// angle at the reference point, and includes the probability of choosing the light.
+pub struct DirectLightSample {
+    pub point: core::Vec,
+    pub surface_props: prim::SurfaceProperties,
+    pub prim_index: usize,
+    pub component_index: usize,
+    pub pdf: f32,
+}
+
 impl Intersection {
-    pub fn hit(dist: f32, surface_props: prim::SurfaceProperties, prim_index: usize) -> Intersection
+    pub fn hit(dist: f32, surface_props: prim::SurfaceProperties, prim_index: usize,
+        component_index: usize) -> Intersection
     {
-        Intersection::Hit {
-            dist: dist,
-            surface_props: surface_props,
-            prim_index: prim_index
-        }
+        Intersection::Hit {dist, surface_props, prim_index, component_index}
     }
 
     pub fn no_hit() -> Intersection {
@@ -139,6 +147,9 @@ impl Intersection {
 
 const MAX_NODES_TO_VISIT: usize = 64; // This is the value used in PBRT; it should be sufficient.
 
+/// The most masked-out hits to skip on a single component before giving up on the ray.
+const MAX_MASKED_HITS: usize = 16;
+
 struct VisitStack {
     storage: [usize; MAX_NODES_TO_VISIT],
     cursor: usize
@@ -176,6 +187,9 @@ pub struct Bvh {
     components: std::vec::Vec<(usize, usize)>,
     nodes: BvhLinearNodeArena,
     light_indices: std::vec::Vec<usize>,
+    light_groups: std::vec::Vec<String>,
+    /// Index into light_groups for each prim that has a light.
+    prim_light_groups: std::vec::Vec<Option<usize>>,
 }
 
 impl Bvh {
@@ -369,6 +383,21 @@ impl Bvh {
             }
         }
 
+        // Collect the light groups in sorted order so that outputs are stable, and cache the group
+        // of each prim.
+        let mut light_groups = std::vec::Vec::<String>::new();
+        for &i in &lights {
+            if let Some(group) = prims[i].material().light_group() {
+                light_groups.push(String::from(group));
+            }
+        }
+        light_groups.sort();
+        light_groups.dedup();
+        let prim_light_groups: std::vec::Vec<Option<usize>> = prims.iter().map(|prim| {
+            prim.material().light_group().map(
+                    |group| light_groups.binary_search_by(|x| x.as_str().cmp(group)).unwrap())
+        }).collect();
+
         ordered_components.shrink_to_fit();
         nodes.shrink_to_fit();
         lights.shrink_to_fit();
@@ -378,9 +407,45 @@ impl Bvh {
             components: ordered_components,
             nodes: nodes,
             light_indices: lights,
+            light_groups,
+            prim_light_groups,
         }
     }
 
+    /// Intersects the ray with a component of a prim, skipping hits on masked-out parts of the
+    /// prim (see Prim::opacity). A hit is solid with probability equal to the prim's opacity
+    /// there; otherwise the ray continues past it and may hit the component again, e.g. on the
+    /// far side of a sphere. Instead of drawing a random number, the decision hashes the ray and
+    /// the hit, so that tracing the same ray always finds the same surface.
+    fn intersect_component(&self, ray: &core::Ray, prim_index: usize, component_index: usize)
+        -> (f32, prim::SurfaceProperties)
+    {
+        let prim = &self.prims[prim_index];
+        let mut current_ray = ray.clone();
+        let mut offset = 0.0;
+        for _ in 0..MAX_MASKED_HITS {
+            let (dist, surface_props) = prim.intersect_world(&current_ray, component_index);
+            if dist == 0.0 {
+                return (0.0, surface_props);
+            }
+
+            let total_dist = offset + dist;
+            let opacity = prim.opacity(&surface_props);
+            if opacity >= 1.0 || (opacity > 0.0 && core::hash_to_unit(&[
+                    ray.origin.x, ray.origin.y, ray.origin.z,
+                    ray.direction.x, ray.direction.y, ray.direction.z,
+                    total_dist, prim_index as f32, component_index as f32]) < opacity)
+            {
+                return (total_dist, surface_props);
+            }
+
+            current_ray = core::Ray::new(current_ray.at(dist), ray.direction).nudge();
+            offset = (&current_ray.origin - &ray.origin).magnitude() / ray.direction.magnitude();
+        }
+
+        (0.0, prim::SurfaceProperties::zero())
+    }
+
     /// Naive intersection for debugging purposes.
     pub fn intersect_naive(&self, ray: &core::Ray) -> Intersection {
         let mut closest_dist = std::f32::MAX;
@@ -388,9 +453,9 @@ impl Bvh {
         for prim_index in 0..self.prims.len() {
             let prim = &self.prims[prim_index];
             for i in 0..prim.num_components() {
-                let (dist, surface_props) = prim.intersect_world(&ray, i);
+                let (dist, surface_props) = self.intersect_component(ray, prim_index, i);
                 if dist != 0.0 && dist < closest_dist {
-                    closest = Intersection::hit(dist, surface_props, prim_index);
+                    closest = Intersection::hit(dist, surface_props, prim_index, i);
                     closest_dist = dist;
                 }
             }
@@ -403,6 +468,8 @@ impl Bvh {
     // NOTE: The ray should be unit-length to ensure that the right computation is provided,
     // although non-unit-length should work in theory if all the shapes are returning
     // parametric distances.
+    // Hits on masked-out parts of prims (see Prim::opacity) are skipped, so the ray continues
+    // through them to the surfaces behind.
     pub fn intersect(&self, ray: &core::Ray) -> Intersection {
         let mut closest_dist = std::f32::MAX;
         let mut closest: Intersection = Intersection::no_hit();
@@ -420,10 +487,11 @@ impl Bvh {
                     // Intersect ray with components in leaf.
                     for i in node.offset..(node.offset + node.num_components) {
                         let (prim_index, component_index) = self.components[i];
-                        let prim = &self.prims[prim_index];
-                        let (dist, surface_props) = prim.intersect_world(&ray, component_index);
+                        let (dist, surface_props) =
+                                self.intersect_component(ray, prim_index, component_index);
                         if dist != 0.0 && dist < closest_dist {
-                            closest = Intersection::hit(dist, surface_props, prim_index);
+                            closest = Intersection::hit(
+                                    dist, surface_props, prim_index, component_index);
                             closest_dist = dist;
                         }
                     }
@@ -457,6 +525,7 @@ impl Bvh {
 
     // Determines whether the target point is visible from the start point, i.e. unoccluded.
     // Accounts for some numerical instability at both start and end points.
+    // Masked-out surfaces don't occlude, the same as in intersect.
     pub fn visibility(&self, start: &core::Vec, target: &core::Vec) -> bool {
         // Points are too close. Skip testing and just say they're invisible.
         if start.is_close(&target, 1e-3) {
@@ -467,7 +536,7 @@ impl Bvh {
         let ray = core::Ray::new(start.clone(), (target - start).normalized()).nudge();
         let target_dist = (target - &ray.origin).magnitude();
 
-        if let Intersection::Hit {dist, surface_props: _, prim_index: _} = self.intersect(&ray) {
+        if let Intersection::Hit {dist, ..} = self.intersect(&ray) {
             if dist < (target_dist - 1e-3) {
                 return false;
             }
@@ -494,6 +563,77 @@ impl Bvh {
             dir_pdf: dir_pdf,
         }
     }
+
+    // Samples a random point on a light in the scene as seen from the reference point, and
+    // returns a sample whose pdf is with respect to solid angle at the reference point.
+    pub fn sample_light_from(&self, reference: &core::Vec, rng: &mut rand::XorShiftRng)
+        -> DirectLightSample
+    {
+        debug_assert!(!self.light_indices.is_empty());
+        let range = Range::new(0, self.light_indices.len());
+        let r = range.ind_sample(rng);
+        let idx = self.light_indices[r];
+        let (point, surface_props, component_index, pdf) =
+                self.prims[idx].sample_world_from(reference, rng);
+
+        DirectLightSample {
+            point,
+            surface_props,
+            prim_index: idx,
+            component_index,
+            pdf: pdf / (self.light_indices.len() as f32),
+        }
+    }
+
+    // Returns the pdf, with respect to solid angle at the reference point, that
+    // sample_light_from would choose the given point on the given light prim component.
+    pub fn pdf_light_from(&self,
+        reference: &core::Vec,
+        prim_index: usize,
+        component_index: usize,
+        point: &core::Vec,
+        surface_props: &prim::SurfaceProperties) -> f32
+    {
+        let prim = &self.prims[prim_index];
+        if !prim.material().has_light() {
+            return 0.0;
+        }
+
+        prim.pdf_world_from(reference, point, surface_props, component_index) /
+                (self.light_indices.len() as f32)
+    }
+
+    // Returns the radiance leaving a point sampled on a light prim in the given direction. Rays
+    // pass through masked-out parts of prims (see Prim::opacity), so only the opacity's share of
+    // the light's emission is ever seen there; sampled points are weighted the same way.
+    pub fn sampled_light_world(&self,
+        prim_index: usize,
+        outgoing_world: &core::Vec,
+        surface_props: &prim::SurfaceProperties) -> core::Vec
+    {
+        let prim = &self.prims[prim_index];
+        let emission = prim.material().light_world(outgoing_world, surface_props);
+        if emission.is_exactly_zero() {
+            return emission;
+        }
+
+        &emission * prim.opacity(surface_props)
+    }
+
+    pub fn num_lights(&self) -> usize {
+        self.light_indices.len()
+    }
+
+    /// The names of all light groups in the scene, in sorted order.
+    pub fn light_groups(&self) -> &[String] {
+        &self.light_groups
+    }
+
+    /// Returns the index into light_groups of the given prim's light, or None if the prim has no
+    /// light or its light isn't in a group.
+    pub fn light_group(&self, prim_index: usize) -> Option<usize> {
+        self.prim_light_groups[prim_index]
+    }
 }
 
 impl Index<usize> for Bvh {
@@ -0,0 +1,120 @@
+use geom::prim;
+
+use core;
+use material;
+
+use rand;
+use rand::Rng;
+
+/// A straight fiber of constant radius, for hair and fur. Like PBRT's flat curves, it's
+/// intersected as a ribbon that always faces the ray, which is much cheaper than a true cylinder
+/// and indistinguishable at the widths of hair.
+///
+/// The shading frame is curve-aligned, as the hair lobe expects: the tangent runs along the fiber
+/// from start to end, and the normal faces the ray. The u texture coordinate runs from 0 to 1
+/// along the fiber, and the v coordinate from 0 to 1 across its width, in the direction of the
+/// binormal, so that the hit's offset from the center of the fiber is h = -1 + 2v.
+///
+/// Points sampled on the curve, e.g. for lights, are sampled on the surface of the cylinder.
+pub struct Curve {
+    mat: material::Material,
+    start: core::Vec,
+    /// The unit direction from start to end.
+    axis: core::Vec,
+    length: f32,
+    radius: f32,
+}
+
+impl Curve {
+    pub fn new(material: material::Material, start: core::Vec, end: core::Vec, radius: f32)
+        -> Curve
+    {
+        let length = (&end - &start).magnitude();
+        let mut material = material;
+        material.set_surface_area(core::TWO_PI * radius * length);
+        Curve {
+            mat: material,
+            start,
+            axis: (&end - &start).normalized(),
+            length,
+            radius,
+        }
+    }
+}
+
+impl prim::Prim for Curve {
+    fn display_color(&self) -> &core::Vec {
+        self.mat.display_color()
+    }
+
+    fn material(&self) -> &material::Material {
+        &self.mat
+    }
+
+    fn bbox_world(&self, _: usize) -> core::BBox {
+        let end = &self.start + &(self.length * &self.axis);
+        core::BBox {
+            min: core::Vec::new(
+                f32::min(self.start.x, end.x) - self.radius,
+                f32::min(self.start.y, end.y) - self.radius,
+                f32::min(self.start.z, end.z) - self.radius),
+            max: core::Vec::new(
+                f32::max(self.start.x, end.x) + self.radius,
+                f32::max(self.start.y, end.y) + self.radius,
+                f32::max(self.start.z, end.z) + self.radius)
+        }
+    }
+
+    fn intersect_world(&self, ray: &core::Ray, _: usize) -> (f32, prim::SurfaceProperties) {
+        // Find the closest approach between the ray and the axis. The ribbon lies in the plane
+        // through the axis that faces the ray, which the ray crosses at its closest approach.
+        let to_origin = &ray.origin - &self.start;
+        let d = &ray.direction;
+        let d_dot_axis = d.dot(&self.axis);
+        let denom = d.dot(d) - d_dot_axis * d_dot_axis;
+        if core::is_nearly_zero(denom) {
+            // The ray runs along the fiber.
+            return (0.0, prim::SurfaceProperties::zero());
+        }
+        let axis_dot_origin = self.axis.dot(&to_origin);
+        let t = (d_dot_axis * axis_dot_origin - d.dot(&to_origin)) / denom;
+        let s = axis_dot_origin + t * d_dot_axis;
+        if !core::is_positive(t) || s < 0.0 || s > self.length {
+            return (0.0, prim::SurfaceProperties::zero());
+        }
+
+        let pt = ray.at(t);
+        let offset = &pt - &(&self.start + &(s * &self.axis));
+        if offset.magnitude() >= self.radius {
+            return (0.0, prim::SurfaceProperties::zero());
+        }
+
+        let tangent = self.axis;
+        let normal = (&(d_dot_axis * &self.axis) - d).normalized();
+        let binormal = normal.cross(&tangent);
+        let h = core::clamp(offset.dot(&binormal) / self.radius, -1.0, 1.0);
+        let uv = core::Vec::new(s / self.length, 0.5 * (1.0 + h), 0.0);
+        (t, prim::SurfaceProperties::new(pt, pt, normal, tangent, binormal, normal, uv))
+    }
+
+    fn sample_world(&self, rng: &mut rand::XorShiftRng)
+            -> (core::Vec, prim::SurfaceProperties, f32)
+    {
+        let s = self.length * rng.next_f32();
+        let phi = core::TWO_PI * rng.next_f32();
+        let (x, y) = self.axis.coord_system();
+        let normal = &(f32::cos(phi) * &x) + &(f32::sin(phi) * &y);
+        let pt = &(&self.start + &(s * &self.axis)) + &(self.radius * &normal);
+
+        let tangent = self.axis;
+        let binormal = normal.cross(&tangent);
+        let uv = core::Vec::new(s / self.length, 0.5, 0.0);
+        let surface_props = prim::SurfaceProperties::new(
+                pt, pt, normal, tangent, binormal, normal, uv);
+        (pt, surface_props, 1.0 / self.surface_area())
+    }
+
+    fn surface_area(&self) -> f32 {
+        core::TWO_PI * self.radius * self.length
+    }
+}
@@ -45,6 +45,29 @@ impl Tri {
         let edge2 = b - c;
         0.5 * edge1.cross(&edge2).magnitude()
     }
+
+    /// Returns a sampler for the spherical triangle formed by projecting the triangle onto the
+    /// unit sphere around the reference point.
+    fn spherical_triangle(&self, vertices: &[core::Vec], reference: &core::Vec)
+        -> core::UniformSampleSphericalTriangle
+    {
+        core::UniformSampleSphericalTriangle::new(
+                (&vertices[self.a] - reference).normalized(),
+                (&vertices[self.b] - reference).normalized(),
+                (&vertices[self.c] - reference).normalized())
+    }
+}
+
+/// Spherical triangles subtending less than this solid angle are sampled by area instead, since
+/// spherical triangle sampling becomes numerically unstable for them; the difference in variance
+/// is negligible at such small angles.
+const MIN_SPHERICAL_SAMPLE_SOLID_ANGLE: f32 = 3e-4;
+/// Spherical triangles subtending more than this solid angle (nearly a hemisphere) are also
+/// sampled by area, because they're likely to be degenerate as seen from the reference point.
+const MAX_SPHERICAL_SAMPLE_SOLID_ANGLE: f32 = 6.22;
+
+fn use_spherical_sampling(solid_angle: f32) -> bool {
+    (MIN_SPHERICAL_SAMPLE_SOLID_ANGLE..=MAX_SPHERICAL_SAMPLE_SOLID_ANGLE).contains(&solid_angle)
 }
 
 pub struct Mesh {
@@ -55,6 +78,7 @@ pub struct Mesh {
     tris: std::vec::Vec<Tri>,
     area: f32,
     area_dist: core::CumulativeDistribution,
+    xform: core::Xform,
 }
 
 impl Mesh {
@@ -175,6 +199,8 @@ impl Mesh {
         tris.shrink_to_fit();
         area_cdf.shrink_to_fit();
 
+        let mut material = material;
+        material.set_surface_area(total_area);
         let mesh = Mesh {
             mat: material,
             vertices: vertices,
@@ -182,7 +208,8 @@ impl Mesh {
             uvs: uvs,
             tris: tris,
             area: total_area,
-            area_dist: core::CumulativeDistribution::new(area_cdf)
+            area_dist: core::CumulativeDistribution::new(area_cdf),
+            xform,
         };
         Ok(mesh)
     }
@@ -219,24 +246,35 @@ impl Mesh {
         // Compute the derivative dpos/du. See PBRT 3e p. 158.
         // pos = pos_0 + u * dpos/du + v * dpos/dv
         // Note: I'm not computing dpdv here because there's no need for it yet.
-        let uv_det = uv1.x * uv2.y + uv1.y * uv2.x;
-        let (tangent, binormal) = if uv_det == 0.0 {
+        let uv_det = uv1.x * uv2.y - uv1.y * uv2.x;
+        let dpdu = if uv_det == 0.0 {
+            core::Vec::zero()
+        }
+        else {
+            &(&(uv2.y * &edge1) - &(uv1.y * &edge2)) / uv_det
+        };
+        let binormal = normal.cross(&dpdu);
+        let (tangent, binormal) = if binormal.magnitude() <= 1e-4 * dpdu.magnitude() {
             // Just use an arbitrary coordinate system for tangent and binormal if we can't
-            // compute analytically.
+            // compute analytically, or if dpdu is parallel to the normal.
             normal.coord_system()
         }
         else {
             // Compute tangent and binormal analytically.
             // i × j = k, k × i = j
             // normal × dpdu = binormal, binormal × normal = tangent
-            let inv_uv_det = 1.0 / uv_det;
-            let dpdu = &(&(uv2.y * &uv1) - &(uv1.y * &uv2)) * inv_uv_det;
-            let binormal = normal.cross(&dpdu).normalized();
+            let binormal = binormal.normalized();
             let tangent = binormal.cross(&normal);
             (tangent, binormal)
         };
 
-        prim::SurfaceProperties::new(normal, tangent, binormal, geom_normal)
+        // Position and texture coordinates from barycentric coordinates.
+        let position = &(&(u * a) + &(v * b)) + &(w * c);
+        let uv = &(&(u * at) + &(v * bt)) + &(w * ct);
+
+        prim::SurfaceProperties::new(
+                position, self.xform.untransform(&position), normal, tangent, binormal,
+                geom_normal, uv)
     } 
 }
 
@@ -325,4 +363,63 @@ impl prim::Prim for Mesh {
         let pdf = 1.0 / self.area;
         (pt, surface_props, pdf)
     }
+
+    fn surface_area(&self) -> f32 {
+        self.area
+    }
+
+    /// Chooses a triangle with respect to area, and then samples the triangle with respect to
+    /// the solid angle it subtends from the reference point.
+    fn sample_world_from(&self, reference: &core::Vec, rng: &mut rand::XorShiftRng)
+            -> (core::Vec, prim::SurfaceProperties, usize, f32)
+    {
+        let tri_index = self.area_dist.ind_sample(rng);
+        let tri = &self.tris[tri_index];
+        let tri_prob = tri.area(&self.vertices) / self.area;
+
+        let spherical_triangle = tri.spherical_triangle(&self.vertices, reference);
+        let solid_angle = spherical_triangle.solid_angle();
+        if !use_spherical_sampling(solid_angle) {
+            let a = &self.vertices[tri.a];
+            let b = &self.vertices[tri.b];
+            let c = &self.vertices[tri.c];
+
+            let uniform_sample_barycentric = core::UniformSampleBarycentric {};
+            let (u, v) = uniform_sample_barycentric.ind_sample(rng);
+            let w = 1.0 - u - v;
+            let pt = &(&(u * a) + &(v * b)) + &(w * c);
+
+            let surface_props = self.compute_surface_props(tri, u, v, w);
+            let pdf = prim::area_pdf_to_solid_angle(
+                    1.0 / self.area, reference, &pt, &surface_props.geom_normal);
+            return (pt, surface_props, tri_index, pdf);
+        }
+
+        // Find the point on the triangle in the sampled direction. This can fail due to numerical
+        // error right at the triangle's edges, in which case the sample is discarded.
+        let dir = spherical_triangle.ind_sample(rng);
+        let ray = core::Ray::new(*reference, dir);
+        let (dist, surface_props) = self.intersect_world(&ray, tri_index);
+        if dist == 0.0 {
+            return (core::Vec::zero(), prim::SurfaceProperties::zero(), tri_index, 0.0);
+        }
+
+        (ray.at(dist), surface_props, tri_index, tri_prob / solid_angle)
+    }
+
+    fn pdf_world_from(&self, reference: &core::Vec, point: &core::Vec,
+        surface_props: &prim::SurfaceProperties, component: usize) -> f32
+    {
+        let tri = &self.tris[component];
+        let tri_prob = tri.area(&self.vertices) / self.area;
+
+        let solid_angle = tri.spherical_triangle(&self.vertices, reference).solid_angle();
+        if use_spherical_sampling(solid_angle) {
+            tri_prob / solid_angle
+        }
+        else {
+            prim::area_pdf_to_solid_angle(
+                    1.0 / self.area, reference, point, &surface_props.geom_normal)
+        }
+    }
 }
\ No newline at end of file
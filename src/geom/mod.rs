@@ -1,6 +1,9 @@
 mod bvh;
 pub use geom::bvh::{Bvh, Intersection};
 
+mod curve;
+pub use geom::curve::Curve;
+
 mod mesh;
 pub use geom::mesh::Mesh;
 
@@ -2,7 +2,6 @@ use core;
 use material;
 
 use rand;
-use rand::distributions::IndependentSample;
 
 pub trait Prim : Sync + Send {
     fn num_components(&self) -> usize {
@@ -24,56 +23,123 @@ pub trait Prim : Sync + Send {
      * although it is recommended.
      */
     fn intersect_world(&self, ray: &core::Ray, component: usize) -> (f32, SurfaceProperties);
+    /**
+     * Returns the opacity in [0, 1] of the prim at a point returned by intersect_world.
+     * Acceleration structures treat a hit as solid with probability equal to the opacity, and
+     * otherwise continue the ray through it, and the emission of points sampled on lights is
+     * weighted by it to match. The default implementation uses the opacity mask of the prim's
+     * material.
+     */
+    fn opacity(&self, surface_props: &SurfaceProperties) -> f32 {
+        self.material().opacity(surface_props)
+    }
     /**
      * Sample a random point in world space on the prim, with respect to the area of the prim.
      * Returns the position, surface properties, and pdf at the sampled point.
      */
     fn sample_world(&self, rng: &mut rand::XorShiftRng) -> (core::Vec, SurfaceProperties, f32);
+    /**
+     * Returns the total surface area of the prim in world space.
+     */
+    fn surface_area(&self) -> f32;
+    /**
+     * Sample a random point in world space on the prim, as seen from the given reference point.
+     * Returns the position, surface properties, component containing the point, and pdf at the
+     * sampled point. Unlike sample_world, the pdf is with respect to solid angle at the reference
+     * point, so that it can be combined with BSDF pdfs for multiple importance sampling.
+     * The default implementation samples with respect to area and converts the pdf. The default
+     * implementation also reports component 0, so prims with multiple components must override
+     * both this and pdf_world_from.
+     */
+    fn sample_world_from(&self, reference: &core::Vec, rng: &mut rand::XorShiftRng)
+        -> (core::Vec, SurfaceProperties, usize, f32)
+    {
+        let (point, surface_props, area_pdf) = self.sample_world(rng);
+        let pdf = area_pdf_to_solid_angle(
+                area_pdf, reference, &point, &surface_props.geom_normal);
+        (point, surface_props, 0, pdf)
+    }
+    /**
+     * Returns the pdf, with respect to solid angle at the reference point, that
+     * sample_world_from would choose the given point on the given component of the prim. The
+     * point is assumed to be visible from the reference point.
+     */
+    fn pdf_world_from(&self, reference: &core::Vec, point: &core::Vec,
+        surface_props: &SurfaceProperties, _component: usize) -> f32
+    {
+        area_pdf_to_solid_angle(
+                1.0 / self.surface_area(), reference, point, &surface_props.geom_normal)
+    }
     /**
      * Sample a random ray starting from a random point on the prim.
      * Returns the ray, surface properties at the origin, the pdf of the origin position, and the
-     * pdf of the ray direction.
+     * pdf of the ray direction. The direction is importance-sampled according to the emission
+     * profile of the prim's light, if any.
      */
     fn sample_ray_world(&self, rng: &mut rand::XorShiftRng)
         -> (core::Ray, SurfaceProperties, f32, f32)
     {
         let (point, surface_props, point_pdf) = self.sample_world(rng);
-
-        let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: false};
-        let dir = cosine_sample_hemis.ind_sample(rng);
-        let dir_pdf = core::CosineSampleHemisphere::pdf(&dir);
-
-        let (tangent, binormal) = surface_props.geom_normal.coord_system();
-        let dir_world = dir.local_to_world(&tangent, &binormal, &surface_props.geom_normal);
-
+        let (dir_world, dir_pdf) = self.material().sample_light_dir_world(&surface_props, rng);
         let light_ray = core::Ray::new(point, dir_world);
         (light_ray, surface_props, point_pdf, dir_pdf)
     }
 }
 
+/// Converts a pdf with respect to area at a point on a surface with the given normal into a pdf
+/// with respect to solid angle at the reference point.
+pub fn area_pdf_to_solid_angle(
+    area_pdf: f32, reference: &core::Vec, point: &core::Vec, normal: &core::Vec) -> f32
+{
+    let to_point = point - reference;
+    let dist2 = to_point.dot(&to_point);
+    if dist2 == 0.0 {
+        return 0.0;
+    }
+
+    let abs_cos_theta = f32::abs(normal.dot(&to_point)) / f32::sqrt(dist2);
+    if abs_cos_theta == 0.0 {
+        0.0
+    }
+    else {
+        area_pdf * dist2 / abs_cos_theta
+    }
+}
+
 /// Properties of the prim surface at the point of an intersection.
 /// The coordinate system formed by normal, tangent, and binormal should satisfy the condition
 /// tangent × binormal = normal.
+/// The texture coordinates are stored in the x and y components of uv; z is unused.
+/// The position is in world space, and the object position is the same point in the space of
+/// the prim before its transform was applied.
+/// The incoming direction is the world-space direction, facing away from the surface, from which
+/// the material is being seen, i.e. toward the camera end of the path even on paths traced from
+/// lights; it's zero where that isn't known, e.g. at a fresh intersection.
+#[derive(Clone)]
 pub struct SurfaceProperties {
+    pub position: core::Vec,
+    pub object_position: core::Vec,
     pub normal: core::Vec,
     pub tangent: core::Vec,
     pub binormal: core::Vec,
     pub geom_normal: core::Vec,
+    pub uv: core::Vec,
+    pub incoming: core::Vec,
 }
 
 impl SurfaceProperties {
-    pub fn new(normal: core::Vec, tangent: core::Vec, binormal: core::Vec, geom_normal: core::Vec)
+    pub fn new(position: core::Vec, object_position: core::Vec, normal: core::Vec,
+        tangent: core::Vec, binormal: core::Vec, geom_normal: core::Vec, uv: core::Vec)
         -> SurfaceProperties
     {
         SurfaceProperties {
-            normal: normal,
-            tangent: tangent,
-            binormal: binormal,
-            geom_normal: geom_normal
+            position, object_position, normal, tangent, binormal, geom_normal, uv,
+            incoming: core::Vec::zero(),
         }
     }
 
     pub fn zero() -> SurfaceProperties {
-        Self::new(core::Vec::zero(), core::Vec::zero(), core::Vec::zero(), core::Vec::zero())
+        Self::new(core::Vec::zero(), core::Vec::zero(), core::Vec::zero(), core::Vec::zero(),
+                core::Vec::zero(), core::Vec::zero(), core::Vec::zero())
     }
 }
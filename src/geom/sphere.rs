@@ -11,15 +11,20 @@ pub struct Sphere {
     mat: material::Material,
     radius: f32,
     origin: core::Vec,
+    xform: core::Xform,
 }
 
 impl Sphere {
     pub fn new(material: material::Material, xf_mat: core::Mat, radius: f32) -> Sphere
     {
+        let mut material = material;
+        material.set_surface_area(4.0 * std::f32::consts::PI * radius * radius);
+        let xform = core::Xform::new(xf_mat);
         Sphere {
             mat: material,
             radius: radius,
-            origin: core::Xform::new(xf_mat).transform(&core::Vec::zero()),
+            origin: xform.transform(&core::Vec::zero()),
+            xform,
         }
     }
 }
@@ -28,21 +33,55 @@ impl Sphere {
     fn compute_surface_props(&self, pt: &core::Vec) -> prim::SurfaceProperties {
         // Example: normal = (1, 0, 0)
         //          tangent = (0, 0, -1)
-        //          binormal: (0, -1, 0)
+        //          binormal: (0, 1, 0)
+        // The tangent and binormal follow the directions of increasing u and v, respectively, so
+        // that normal and bump maps line up with textures, which aren't mirrored.
         let normal = (pt - &self.origin).normalized();
+
+        // The u-coordinate is the azimuth around the y-axis, and the v-coordinate is the
+        // inclination from the bottom (v = 0) to the top (v = 1).
+        let phi = f32::atan2(normal.x, normal.z);
+        let theta = f32::acos(core::clamp(normal.y, -1.0, 1.0));
+        let uv = core::Vec::new(
+                0.5 + phi / core::TWO_PI, 1.0 - theta * std::f32::consts::FRAC_1_PI, 0.0);
+
         if core::is_nearly_zero(normal.x) && core::is_nearly_zero(normal.z) {
             // Singularity at top or bottom.
             let tangent = core::Vec::x_axis();
             let binormal = normal.cross(&tangent);
-            prim::SurfaceProperties::new(normal, tangent, binormal, normal)
+            prim::SurfaceProperties::new(
+                    *pt, self.xform.untransform(pt), normal, tangent, binormal, normal, uv)
         }
         else {
             // Normal point.
-            let tangent = core::Vec::new(-normal.z, 0.0, normal.x).normalized();
+            let tangent = core::Vec::new(normal.z, 0.0, -normal.x).normalized();
             let binormal = normal.cross(&tangent);
-            prim::SurfaceProperties::new(normal, tangent, binormal, normal)
+            prim::SurfaceProperties::new(
+                    *pt, self.xform.untransform(pt), normal, tangent, binormal, normal, uv)
         }
     }
+
+    /// Computes the cone of directions subtended by the sphere as seen from the reference point.
+    /// Returns the cone axis (pointing toward the sphere's origin), the distance to the origin,
+    /// and the cosine of the cone's half-angle. Returns None if the reference point is inside the
+    /// sphere or the cone is too narrow to be represented, in which case callers should fall back
+    /// to sampling by area.
+    fn visible_cone(&self, reference: &core::Vec) -> Option<(core::Vec, f32, f32)> {
+        let to_origin = &self.origin - reference;
+        let dist2 = to_origin.dot(&to_origin);
+        let radius2 = self.radius * self.radius;
+        if dist2 <= radius2 {
+            return None;
+        }
+
+        let cos_theta_max = f32::sqrt(f32::max(0.0, 1.0 - radius2 / dist2));
+        if cos_theta_max >= 1.0 {
+            return None;
+        }
+
+        let dist = f32::sqrt(dist2);
+        Some((&to_origin / dist, dist, cos_theta_max))
+    }
 }
 
 impl prim::Prim for Sphere {
@@ -106,7 +145,59 @@ impl prim::Prim for Sphere {
         let uniform_sample_sphere = core::UniformSampleSphere {};
         let pt = &self.origin + &(&uniform_sample_sphere.ind_sample(rng) * self.radius);
         let surface_props = self.compute_surface_props(&pt);
-        let pdf = 1.0 / (4.0 * std::f32::consts::PI * self.radius * self.radius);
+        let pdf = 1.0 / self.surface_area();
         (pt, surface_props, pdf)
     }
+
+    fn surface_area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius * self.radius
+    }
+
+    /// Samples the cone of directions subtended by the sphere, so that only the side facing the
+    /// reference point is sampled. See PBRT 3e p. 840.
+    fn sample_world_from(&self, reference: &core::Vec, rng: &mut rand::XorShiftRng)
+            -> (core::Vec, prim::SurfaceProperties, usize, f32)
+    {
+        match self.visible_cone(reference) {
+            Some((axis, dist, cos_theta_max)) => {
+                let half_angle = f32::acos(cos_theta_max);
+                let uniform_sample_cone = core::UniformSampleCone::new(half_angle);
+                let dir_local = uniform_sample_cone.ind_sample(rng);
+                let (tangent, binormal) = axis.coord_system();
+                let dir = dir_local.local_to_world(&tangent, &binormal, &axis);
+
+                // Find the near intersection along the sampled direction. Directions at the edge
+                // of the cone graze the sphere, so clamp the discriminant to avoid NaNs.
+                let cos_theta = dir_local.cos_theta();
+                let sin2_theta = f32::max(0.0, 1.0 - cos_theta * cos_theta);
+                let discriminant = self.radius * self.radius - dist * dist * sin2_theta;
+                let t = dist * cos_theta - f32::sqrt(f32::max(0.0, discriminant));
+
+                let pt = reference + &(t * &dir);
+                let surface_props = self.compute_surface_props(&pt);
+                let pdf = core::UniformSampleCone::pdf_internal(half_angle);
+                (pt, surface_props, 0, pdf)
+            },
+            None => {
+                let (pt, surface_props, area_pdf) = self.sample_world(rng);
+                let pdf = prim::area_pdf_to_solid_angle(
+                        area_pdf, reference, &pt, &surface_props.geom_normal);
+                (pt, surface_props, 0, pdf)
+            }
+        }
+    }
+
+    fn pdf_world_from(&self, reference: &core::Vec, point: &core::Vec,
+        surface_props: &prim::SurfaceProperties, _: usize) -> f32
+    {
+        match self.visible_cone(reference) {
+            Some((_, _, cos_theta_max)) => {
+                core::UniformSampleCone::pdf_internal(f32::acos(cos_theta_max))
+            },
+            None => {
+                prim::area_pdf_to_solid_angle(
+                        1.0 / self.surface_area(), reference, point, &surface_props.geom_normal)
+            }
+        }
+    }
 }
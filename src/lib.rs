@@ -3,6 +3,7 @@ extern crate rand;
 extern crate rayon;
 extern crate byteorder;
 extern crate wavefront_obj;
+extern crate image;
 pub mod core;
 pub mod geom;
 pub mod material;
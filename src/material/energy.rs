@@ -0,0 +1,120 @@
+use core;
+
+use std;
+use rand;
+use rand::SeedableRng;
+
+/// The directional albedo of a family of lobes, tabulated over the cosine of the incoming angle,
+/// the microfacet roughness alpha, and optionally the relative index of refraction. Used to
+/// compensate for the energy that single-scattering microfacet models lose at high roughness.
+///
+/// Cosines are sampled at the midpoints of num_cos equal intervals of [0, 1], and alphas at
+/// num_alpha evenly-spaced points from 0 to 1. Relative indices of refraction are spaced evenly
+/// in log space from 1 / max_eta to max_eta, so an odd num_eta includes an eta of exactly one.
+pub struct AlbedoTable {
+    num_cos: usize,
+    num_alpha: usize,
+    num_eta: usize,
+    max_eta: f32,
+    /// Directional albedos, indexed by eta, then alpha, then cosine.
+    albedos: std::vec::Vec<f32>,
+    /// Cosine-weighted hemispherical averages of the albedo, indexed by eta, then alpha.
+    averages: std::vec::Vec<f32>,
+}
+
+impl AlbedoTable {
+    /// Builds a table by estimating the albedo at each entry. The estimate function is given the
+    /// cosine, alpha, relative eta, and a random number generator, and should average the
+    /// sampling weights of the lobe over the given number of samples. The generator is seeded
+    /// identically each time, so the table doesn't vary between runs.
+    pub fn build<F>(num_cos: usize, num_alpha: usize, num_eta: usize, max_eta: f32, estimate: F)
+        -> AlbedoTable
+        where F: Fn(f32, f32, f32, &mut rand::XorShiftRng) -> f32
+    {
+        let mut rng = rand::XorShiftRng::from_seed(
+                [0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb]);
+        let mut albedos = std::vec::Vec::with_capacity(num_eta * num_alpha * num_cos);
+        let mut averages = std::vec::Vec::with_capacity(num_eta * num_alpha);
+        for eta_index in 0..num_eta {
+            let eta = AlbedoTable::eta_at(eta_index, num_eta, max_eta);
+            for alpha_index in 0..num_alpha {
+                let alpha = alpha_index as f32 / (num_alpha - 1) as f32;
+                let mut average = 0.0;
+                for cos_index in 0..num_cos {
+                    let cos_theta = (cos_index as f32 + 0.5) / num_cos as f32;
+                    let albedo = core::clamp_unit(estimate(cos_theta, alpha, eta, &mut rng));
+                    albedos.push(albedo);
+                    average += 2.0 * albedo * cos_theta / num_cos as f32;
+                }
+                averages.push(average);
+            }
+        }
+
+        AlbedoTable {num_cos, num_alpha, num_eta, max_eta, albedos, averages}
+    }
+
+    fn eta_at(index: usize, num_eta: usize, max_eta: f32) -> f32 {
+        if num_eta == 1 {
+            1.0
+        }
+        else {
+            let t = index as f32 / (num_eta - 1) as f32;
+            f32::exp(core::lerp(-max_eta.ln(), max_eta.ln(), t))
+        }
+    }
+
+    /// Returns the lower grid index and the interpolation weight toward the next index for a
+    /// continuous index x into an axis with n entries.
+    fn lerp_index(x: f32, n: usize) -> (usize, f32) {
+        if n == 1 {
+            return (0, 0.0);
+        }
+        let x = core::clamp(x, 0.0, (n - 1) as f32);
+        let i = usize::min(x as usize, n - 2);
+        (i, x - i as f32)
+    }
+
+    fn eta_index(&self, eta: f32) -> (usize, f32) {
+        let x = if self.num_eta == 1 {
+            0.0
+        }
+        else {
+            let ln_max = self.max_eta.ln();
+            (eta.ln() + ln_max) / (2.0 * ln_max) * (self.num_eta - 1) as f32
+        };
+        AlbedoTable::lerp_index(x, self.num_eta)
+    }
+
+    fn alpha_index(&self, alpha: f32) -> (usize, f32) {
+        AlbedoTable::lerp_index(alpha * (self.num_alpha - 1) as f32, self.num_alpha)
+    }
+
+    /// The directional albedo for the given incoming cosine, alpha, and relative eta.
+    pub fn albedo(&self, cos_theta: f32, alpha: f32, eta: f32) -> f32 {
+        let (e, et) = self.eta_index(eta);
+        let (a, at) = self.alpha_index(alpha);
+        let (c, ct) = AlbedoTable::lerp_index(
+                f32::abs(cos_theta) * self.num_cos as f32 - 0.5, self.num_cos);
+
+        let lookup = |e: usize, a: usize| {
+            let row = &self.albedos[(e * self.num_alpha + a) * self.num_cos..];
+            core::lerp(row[c], row[usize::min(c + 1, self.num_cos - 1)], ct)
+        };
+        let lookup_eta = |e: usize| {
+            core::lerp(lookup(e, a), lookup(e, usize::min(a + 1, self.num_alpha - 1)), at)
+        };
+        core::lerp(lookup_eta(e), lookup_eta(usize::min(e + 1, self.num_eta - 1)), et)
+    }
+
+    /// The cosine-weighted average of the directional albedo over the hemisphere.
+    pub fn average(&self, alpha: f32, eta: f32) -> f32 {
+        let (e, et) = self.eta_index(eta);
+        let (a, at) = self.alpha_index(alpha);
+
+        let lookup = |e: usize| {
+            let row = &self.averages[e * self.num_alpha..];
+            core::lerp(row[a], row[usize::min(a + 1, self.num_alpha - 1)], at)
+        };
+        core::lerp(lookup(e), lookup(usize::min(e + 1, self.num_eta - 1)), et)
+    }
+}
@@ -0,0 +1,399 @@
+use material::lobes;
+use material::util;
+
+use core;
+
+use std;
+use std::fmt;
+use std::fmt::Display;
+use rand;
+use rand::Rng;
+
+// Note: the hair scattering model in this file is taken from PBRT, 3rd edition, which
+// implements the model from Chiang et al., "A Practical and Controllable Hair and Fur Model for
+// Production Path Tracing" (EGSR 2016), building on d'Eon et al. (EGSR 2011).
+// See http://www.pbrt.org/hair.pdf for the derivation.
+
+/// The number of scattering events modeled explicitly (R, TT, and TRT). Higher-order scattering
+/// is lumped into one additional term with a uniform azimuthal distribution.
+const P_MAX: usize = 3;
+
+/// The absorption coefficients of eumelanin and pheomelanin, per unit concentration.
+const EUMELANIN_SIGMA_A: [f32; 3] = [0.419, 0.697, 1.37];
+const PHEOMELANIN_SIGMA_A: [f32; 3] = [0.187, 0.4, 1.05];
+
+fn safe_sqrt(x: f32) -> f32 {
+    f32::sqrt(f32::max(0.0, x))
+}
+
+fn safe_asin(x: f32) -> f32 {
+    f32::asin(core::clamp(x, -1.0, 1.0))
+}
+
+/// The modified Bessel function of the first kind, of order zero.
+fn i0(x: f32) -> f32 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f32;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-f32::ln(2.0 * std::f32::consts::PI) + f32::ln(1.0 / x) + 1.0 / (8.0 * x))
+    }
+    else {
+        f32::ln(i0(x))
+    }
+}
+
+/// The longitudinal scattering function, with variance v.
+fn mp(cos_theta_i: f32, cos_theta_o: f32, sin_theta_i: f32, sin_theta_o: f32, v: f32) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // Use the log form to avoid overflow for low roughness.
+        f32::exp(log_i0(a) - b - 1.0 / v + std::f32::consts::LN_2 + f32::ln(1.0 / (2.0 * v)))
+    }
+    else {
+        (f32::exp(-b) * i0(a)) / (f32::sinh(1.0 / v) * 2.0 * v)
+    }
+}
+
+/// The attenuation of each scattering order, from Fresnel reflection and from absorption T along
+/// each pass through the fiber.
+fn ap(cos_theta_o: f32, eta: f32, h: f32, t: &core::Vec) -> [core::Vec; P_MAX + 1] {
+    let cos_gamma_o = safe_sqrt(1.0 - h * h);
+    let cos_theta = cos_theta_o * cos_gamma_o;
+    let f = util::fresnel_dielectric(cos_theta, eta);
+
+    let mut ap = [core::Vec::zero(); P_MAX + 1];
+    ap[0] = core::Vec::new(f, f, f);
+    ap[1] = ((1.0 - f) * (1.0 - f)) * t;
+    for p in 2..P_MAX {
+        ap[p] = &ap[p - 1].comp_mult(t) * f;
+    }
+    let tf = t * f;
+    ap[P_MAX] = ap[P_MAX - 1].comp_mult(&tf.comp_div(&(&core::Vec::one() - &tf)));
+    ap
+}
+
+/// The net azimuthal deflection of scattering order p.
+fn phi(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    let p = p as f32;
+    2.0 * p * gamma_t - 2.0 * gamma_o + p * std::f32::consts::PI
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let x = f32::abs(x);
+    let e = f32::exp(-x / s);
+    e / (s * (1.0 + e) * (1.0 + e))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + f32::exp(-x / s))
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * f32::ln(1.0 / (u * k + logistic_cdf(a, s)) - 1.0);
+    core::clamp(x, a, b)
+}
+
+/// The azimuthal scattering function.
+fn np(phi_diff: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+    // Remap to [-pi, pi].
+    while dphi > std::f32::consts::PI {
+        dphi -= 2.0 * std::f32::consts::PI;
+    }
+    while dphi < -std::f32::consts::PI {
+        dphi += 2.0 * std::f32::consts::PI;
+    }
+    trimmed_logistic(dphi, s, -std::f32::consts::PI, std::f32::consts::PI)
+}
+
+/// Scattering from a hair or fur fiber, modeled as a rough dielectric cylinder with an absorbing
+/// interior and tilted cuticle scales.
+///
+/// The lobe expects a curve-aligned shading frame: the tangent runs along the fiber, and the
+/// normal faces the viewer across the fiber's width. The offset h in [-1, 1] is where the ray hit
+/// across the width of the fiber, with zero at the center.
+pub struct HairLobe {
+    h: f32,
+    gamma_o: f32,
+    eta: f32,
+    sigma_a: core::Vec,
+    beta_m: f32,
+    beta_n: f32,
+    /// Longitudinal variance for each scattering order.
+    v: [f32; P_MAX + 1],
+    /// Logistic scale factor for the azimuthal distribution.
+    s: f32,
+    /// Sines and cosines of alpha, 2 alpha, and 4 alpha, for tilting each scattering order.
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+}
+
+impl HairLobe {
+    /// Creates a hair lobe. The longitudinal roughness beta_m and azimuthal roughness beta_n are
+    /// in [0, 1], and alpha is the tilt of the cuticle scales in degrees (about 2 for human hair).
+    /// The absorption coefficient sigma_a is per unit of fiber diameter; see
+    /// sigma_a_from_melanin and sigma_a_from_color.
+    pub fn new(h: f32, eta: f32, sigma_a: core::Vec, beta_m: f32, beta_n: f32, alpha: f32)
+        -> HairLobe
+    {
+        let h = core::clamp(h, -1.0, 1.0);
+        let beta_m = core::clamp(beta_m, 0.01, 1.0);
+        let beta_n = core::clamp(beta_n, 0.01, 1.0);
+
+        // Compute the longitudinal variance from beta_m.
+        let v0 = 0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20);
+        let v0 = v0 * v0;
+        let mut v = [0.0; P_MAX + 1];
+        v[0] = v0;
+        v[1] = 0.25 * v0;
+        for x in v.iter_mut().skip(2) {
+            *x = 4.0 * v0;
+        }
+
+        // Compute the azimuthal logistic scale factor from beta_n.
+        const SQRT_PI_OVER_8: f32 = 0.626_657_1;
+        let s = SQRT_PI_OVER_8 *
+                (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+
+        // Compute the tilts for the cuticle scales.
+        let mut sin_2k_alpha = [0.0; 3];
+        let mut cos_2k_alpha = [0.0; 3];
+        sin_2k_alpha[0] = f32::sin(alpha.to_radians());
+        cos_2k_alpha[0] = safe_sqrt(1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]);
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1] * cos_2k_alpha[i - 1] -
+                    sin_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+        }
+
+        HairLobe {
+            h,
+            gamma_o: safe_asin(h),
+            eta,
+            sigma_a,
+            beta_m,
+            beta_n,
+            v,
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// Computes the absorption coefficient from the concentrations of eumelanin (which makes hair
+    /// brown or black) and pheomelanin (which makes hair red or yellow). Blond hair has a
+    /// eumelanin concentration around 0.3, brown around 1.3, and black around 8.
+    pub fn sigma_a_from_melanin(eumelanin: f32, pheomelanin: f32) -> core::Vec {
+        let mut sigma_a = core::Vec::zero();
+        for c in 0..3 {
+            sigma_a[c] = eumelanin * EUMELANIN_SIGMA_A[c] + pheomelanin * PHEOMELANIN_SIGMA_A[c];
+        }
+        sigma_a
+    }
+
+    /// Computes the absorption coefficient that gives approximately the given multiple-scattering
+    /// color for a mass of fibers with the given azimuthal roughness.
+    pub fn sigma_a_from_color(color: &core::Vec, beta_n: f32) -> core::Vec {
+        let denom = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3) +
+                5.574 * beta_n.powi(4) + 0.245 * beta_n.powi(5);
+        let mut sigma_a = core::Vec::zero();
+        for c in 0..3 {
+            let x = f32::ln(core::clamp(color[c], 1e-4, 1.0)) / denom;
+            sigma_a[c] = x * x;
+        }
+        sigma_a
+    }
+
+    /// Returns the sine and cosine of theta_o, tilted by the cuticle scales for order p.
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin_theta_op, cos_theta_op) = match p {
+            0 => (sin_theta_o * self.cos_2k_alpha[1] - cos_theta_o * self.sin_2k_alpha[1],
+                    cos_theta_o * self.cos_2k_alpha[1] + sin_theta_o * self.sin_2k_alpha[1]),
+            1 => (sin_theta_o * self.cos_2k_alpha[0] + cos_theta_o * self.sin_2k_alpha[0],
+                    cos_theta_o * self.cos_2k_alpha[0] - sin_theta_o * self.sin_2k_alpha[0]),
+            2 => (sin_theta_o * self.cos_2k_alpha[2] + cos_theta_o * self.sin_2k_alpha[2],
+                    cos_theta_o * self.cos_2k_alpha[2] - sin_theta_o * self.sin_2k_alpha[2]),
+            _ => (sin_theta_o, cos_theta_o)
+        };
+        (sin_theta_op, f32::abs(cos_theta_op))
+    }
+
+    /// Returns the transmittance of a path through the fiber and the refracted azimuthal angle
+    /// gamma_t, as seen from the direction with the given longitudinal angle.
+    fn transmittance(&self, sin_theta_o: f32, cos_theta_o: f32) -> (core::Vec, f32) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+
+        let etap = f32::sqrt(self.eta * self.eta - sin_theta_o * sin_theta_o) / cos_theta_o;
+        let sin_gamma_t = self.h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let gamma_t = safe_asin(sin_gamma_t);
+
+        let t = (&self.sigma_a * (-2.0 * cos_gamma_t / cos_theta_t)).exp();
+        (t, gamma_t)
+    }
+
+    /// The probability of sampling each scattering order, in proportion to its attenuation.
+    fn ap_pdf(&self, sin_theta_o: f32, cos_theta_o: f32) -> [f32; P_MAX + 1] {
+        let (t, _) = self.transmittance(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, &t);
+        let sum: f32 = ap.iter().map(|x| x.luminance()).sum();
+
+        let mut ap_pdf = [0.0; P_MAX + 1];
+        for p in 0..(P_MAX + 1) {
+            ap_pdf[p] = if sum > 0.0 { ap[p].luminance() / sum } else { 0.0 };
+        }
+        ap_pdf
+    }
+
+    /// The scattering function, which gives the radiance scattered toward the camera direction i
+    /// per unit of irradiance from the light direction o. Unlike a BSDF, it already accounts for
+    /// the cosine at the light direction.
+    fn scattering(&self, i: &core::Vec, o: &core::Vec) -> core::Vec {
+        let (sin_theta_i, cos_theta_i, phi_i) = HairLobe::angles(i);
+        let (sin_theta_o, cos_theta_o, phi_o) = HairLobe::angles(o);
+        let (t, gamma_t) = self.transmittance(sin_theta_i, cos_theta_i);
+        let ap = ap(cos_theta_i, self.eta, self.h, &t);
+        let phi_diff = phi_o - phi_i;
+
+        let mut f_sum = core::Vec::zero();
+        for (p, ap_p) in ap.iter().enumerate().take(P_MAX) {
+            let (sin_theta_ip, cos_theta_ip) = self.tilt(p, sin_theta_i, cos_theta_i);
+            let weight = mp(cos_theta_o, cos_theta_ip, sin_theta_o, sin_theta_ip, self.v[p]) *
+                    np(phi_diff, p, self.s, self.gamma_o, gamma_t);
+            f_sum = &f_sum + &(ap_p * weight);
+        }
+
+        // Higher-order scattering is spread uniformly in azimuth.
+        let weight = mp(cos_theta_o, cos_theta_i, sin_theta_o, sin_theta_i, self.v[P_MAX]) /
+                (2.0 * std::f32::consts::PI);
+        f_sum = &f_sum + &(&ap[P_MAX] * weight);
+
+        f_sum
+    }
+
+    /// Returns the sine and cosine of the longitudinal angle and the azimuthal angle of a
+    /// direction in the fiber's frame.
+    fn angles(w: &core::Vec) -> (f32, f32, f32) {
+        let sin_theta = w.x;
+        let cos_theta = safe_sqrt(1.0 - sin_theta * sin_theta);
+        (sin_theta, cos_theta, f32::atan2(w.z, w.y))
+    }
+}
+
+impl lobes::Lobe for HairLobe {
+    fn f(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
+        // Divide out the cosine factor that integrators apply at the light direction. The result
+        // isn't symmetric, so when tracing from lights, evaluate the adjoint.
+        let (camera_dir, light_dir) = if camera_to_light { (i, o) } else { (o, i) };
+        let abs_cos_theta = light_dir.abs_cos_theta();
+        if abs_cos_theta > 0.0 {
+            &self.scattering(camera_dir, light_dir) / abs_cos_theta
+        }
+        else {
+            core::Vec::zero()
+        }
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        let (sin_theta_i, cos_theta_i, phi_i) = HairLobe::angles(i);
+        let (sin_theta_o, cos_theta_o, phi_o) = HairLobe::angles(o);
+        let (_, gamma_t) = self.transmittance(sin_theta_i, cos_theta_i);
+        let ap_pdf = self.ap_pdf(sin_theta_i, cos_theta_i);
+        let phi_diff = phi_o - phi_i;
+
+        let mut pdf = 0.0;
+        for (p, ap_pdf_p) in ap_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_theta_ip, cos_theta_ip) = self.tilt(p, sin_theta_i, cos_theta_i);
+            pdf += mp(cos_theta_o, cos_theta_ip, sin_theta_o, sin_theta_ip, self.v[p]) *
+                    ap_pdf_p * np(phi_diff, p, self.s, self.gamma_o, gamma_t);
+        }
+        pdf += mp(cos_theta_o, cos_theta_i, sin_theta_o, sin_theta_i, self.v[P_MAX]) *
+                ap_pdf[P_MAX] / (2.0 * std::f32::consts::PI);
+        pdf
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> lobes::LobeSample
+    {
+        let (sin_theta_i, cos_theta_i, phi_i) = HairLobe::angles(i);
+
+        // Choose a scattering order in proportion to its attenuation.
+        let ap_pdf = self.ap_pdf(sin_theta_i, cos_theta_i);
+        let mut u = rng.next_f32();
+        let mut p = 0;
+        while p < P_MAX {
+            if u < ap_pdf[p] {
+                break;
+            }
+            u -= ap_pdf[p];
+            p += 1;
+        }
+
+        // Sample the longitudinal scattering function for the order.
+        let (sin_theta_ip, cos_theta_ip) = self.tilt(p, sin_theta_i, cos_theta_i);
+        let u_m = f32::max(rng.next_f32(), 1e-5);
+        let cos_theta = 1.0 + self.v[p] * f32::ln(u_m + (1.0 - u_m) * f32::exp(-2.0 / self.v[p]));
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = f32::cos(2.0 * std::f32::consts::PI * rng.next_f32());
+        let sin_theta_o = -cos_theta * sin_theta_ip + sin_theta * cos_phi * cos_theta_ip;
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+
+        // Sample the azimuthal scattering function for the order.
+        let (_, gamma_t) = self.transmittance(sin_theta_i, cos_theta_i);
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(
+                    rng.next_f32(), self.s, -std::f32::consts::PI, std::f32::consts::PI)
+        }
+        else {
+            2.0 * std::f32::consts::PI * rng.next_f32()
+        };
+
+        let phi_o = phi_i + dphi;
+        let o = core::Vec::new(
+                sin_theta_o, cos_theta_o * f32::cos(phi_o), cos_theta_o * f32::sin(phi_o));
+        lobes::LobeSample {
+            result: self.f(i, &o, camera_to_light),
+            outgoing: o,
+            pdf: self.pdf(i, &o)
+        }
+    }
+
+    fn kind(&self) -> lobes::LobeKind {
+        lobes::LobeKind::LOBE_GLOSSY | lobes::LobeKind::LOBE_REFLECTION |
+                lobes::LobeKind::LOBE_TRANSMISSION
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let (sin_theta_i, cos_theta_i, _) = HairLobe::angles(i);
+        let (t, _) = self.transmittance(sin_theta_i, cos_theta_i);
+        ap(cos_theta_i, self.eta, self.h, &t).iter().map(|x| x.luminance()).sum()
+    }
+}
+
+impl Display for HairLobe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HairLobe(h={}, eta={}, sigma_a={}, beta_m={}, beta_n={})",
+                self.h, self.eta, self.sigma_a, self.beta_m, self.beta_n)
+    }
+}
@@ -0,0 +1,215 @@
+use core;
+
+use std;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// A goniometric intensity profile loaded from an IES LM-63 photometric data file.
+/// Only type C photometry (the type used by virtually all architectural fixtures) is supported.
+/// The profile is normalized so that its peak intensity is one.
+///
+/// The profile's vertical axis (vertical angle 0, the nadir of the fixture) is aligned with the
+/// +z axis of the local frame in which it's evaluated, and horizontal angle 0 is aligned with +x.
+pub struct IesProfile {
+    vertical_angles: std::vec::Vec<f32>,
+    horizontal_angles: std::vec::Vec<f32>,
+    /// Normalized intensities, stored as candela[h * vertical_angles.len() + v].
+    candela: std::vec::Vec<f32>,
+}
+
+const PHOTOMETRIC_TYPE_C: i32 = 1;
+
+impl IesProfile {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<IesProfile, String> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(reason) => {
+                return Err(format!("Couldn't open IES file: {}", reason));
+            }
+        };
+
+        // IES files are nominally ASCII, but are often saved with Latin-1 characters in their
+        // keywords, so don't require valid UTF-8.
+        let mut bytes = std::vec::Vec::<u8>::new();
+        if let Err(reason) = file.read_to_end(&mut bytes) {
+            return Err(format!("Couldn't read IES file: {}", reason));
+        }
+
+        IesProfile::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    pub fn parse(contents: &str) -> Result<IesProfile, String> {
+        // Skip the header and keywords, up to and including the TILT line.
+        let mut lines = contents.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) => {
+                    let line = line.trim();
+                    if let Some(stripped) = line.strip_prefix("TILT=") {
+                        break stripped.trim().to_string();
+                    }
+                },
+                None => {
+                    return Err("IES parse error: missing TILT line".to_string());
+                }
+            }
+        };
+
+        // The rest of the file is a stream of numbers separated by whitespace or commas.
+        let rest: std::vec::Vec<&str> = lines.collect();
+        let mut numbers = std::vec::Vec::<f32>::new();
+        for token in rest.join(" ").split(|c: char| c.is_whitespace() || c == ',') {
+            if token.is_empty() {
+                continue;
+            }
+            match token.parse::<f32>() {
+                Ok(x) => numbers.push(x),
+                Err(_) => {
+                    return Err(format!("IES parse error: invalid number '{}'", token));
+                }
+            }
+        }
+        let mut cursor = numbers.into_iter();
+        let mut next = |what: &str| -> Result<f32, String> {
+            match cursor.next() {
+                Some(x) => Ok(x),
+                None => Err(format!("IES parse error: unexpected end of file reading {}", what))
+            }
+        };
+
+        // TILT=INCLUDE embeds the lamp-to-luminaire geometry and the tilt angles and factors.
+        // Tilt only matters for lamps whose output depends on their orientation, so skip it.
+        if tilt == "INCLUDE" {
+            next("lamp-to-luminaire geometry")?;
+            let num_pairs = next("tilt angle count")? as usize;
+            for _ in 0..(2 * num_pairs) {
+                next("tilt data")?;
+            }
+        }
+
+        next("lamp count")?;
+        next("lumens per lamp")?;
+        let multiplier = next("candela multiplier")?;
+        let num_vertical = next("vertical angle count")? as usize;
+        let num_horizontal = next("horizontal angle count")? as usize;
+        let photometric_type = next("photometric type")? as i32;
+        next("units type")?;
+        next("width")?;
+        next("length")?;
+        next("height")?;
+        next("ballast factor")?;
+        next("ballast-lamp photometric factor")?;
+        next("input watts")?;
+
+        if photometric_type != PHOTOMETRIC_TYPE_C {
+            return Err(format!(
+                    "IES profiles with photometric type {} are not supported", photometric_type));
+        }
+        if num_vertical == 0 || num_horizontal == 0 {
+            return Err("IES parse error: profile has no angles".to_string());
+        }
+
+        let mut vertical_angles = std::vec::Vec::<f32>::with_capacity(num_vertical);
+        for _ in 0..num_vertical {
+            vertical_angles.push(next("vertical angles")?);
+        }
+        let mut horizontal_angles = std::vec::Vec::<f32>::with_capacity(num_horizontal);
+        for _ in 0..num_horizontal {
+            horizontal_angles.push(next("horizontal angles")?);
+        }
+        let mut candela = std::vec::Vec::<f32>::with_capacity(num_vertical * num_horizontal);
+        for _ in 0..(num_vertical * num_horizontal) {
+            candela.push(multiplier * next("candela values")?);
+        }
+
+        let max_candela = candela.iter().fold(0.0, |acc: f32, &x| f32::max(acc, x));
+        if max_candela <= 0.0 {
+            return Err("IES profile doesn't emit any light".to_string());
+        }
+        for c in &mut candela {
+            *c /= max_candela;
+        }
+
+        Ok(IesProfile {vertical_angles, horizontal_angles, candela})
+    }
+
+    /// Finds the segment of the sorted angle list containing the given angle. Returns the index
+    /// of the segment's first angle, and the interpolation parameter within the segment.
+    /// Returns None if the angle is outside the list.
+    fn find_segment(angles: &[f32], angle: f32) -> Option<(usize, f32)> {
+        let last = angles.len() - 1;
+        if angle < angles[0] || angle > angles[last] {
+            None
+        }
+        else if last == 0 {
+            Some((0, 0.0))
+        }
+        else {
+            let mut i = 0;
+            while i < last - 1 && angles[i + 1] < angle {
+                i += 1;
+            }
+            let width = angles[i + 1] - angles[i];
+            let t = if width > 0.0 { (angle - angles[i]) / width } else { 0.0 };
+            Some((i, t))
+        }
+    }
+
+    /// Maps a horizontal angle in [0, 360) degrees into the range covered by the profile, using
+    /// the symmetry implied by the last horizontal angle.
+    fn fold_horizontal(&self, h: f32) -> f32 {
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if last <= 0.0 {
+            // Laterally symmetric; only one plane is given.
+            0.0
+        }
+        else if last <= 90.0 {
+            // Symmetric in each quadrant.
+            let h = if h > 180.0 { 360.0 - h } else { h };
+            if h > 90.0 { 180.0 - h } else { h }
+        }
+        else if last <= 180.0 {
+            // Symmetric about the 0-180 degree plane.
+            if h > 180.0 { 360.0 - h } else { h }
+        }
+        else {
+            h
+        }
+    }
+
+    fn candela_at(&self, h: usize, v: usize) -> f32 {
+        self.candela[h * self.vertical_angles.len() + v]
+    }
+
+    /// Evaluates the normalized intensity in the given direction in the profile's local frame.
+    pub fn eval(&self, dir: &core::Vec) -> f32 {
+        let len = dir.magnitude();
+        if len == 0.0 {
+            return 0.0;
+        }
+
+        let v = f32::acos(core::clamp(dir.z / len, -1.0, 1.0)).to_degrees();
+        let mut h = f32::atan2(dir.y, dir.x).to_degrees();
+        if h < 0.0 {
+            h += 360.0;
+        }
+        let h = self.fold_horizontal(h);
+
+        let (vi, vt) = match IesProfile::find_segment(&self.vertical_angles, v) {
+            Some(x) => x,
+            None => return 0.0
+        };
+        let (hi, ht) = match IesProfile::find_segment(&self.horizontal_angles, h) {
+            Some(x) => x,
+            None => return 0.0
+        };
+
+        // Bilinearly interpolate the four surrounding samples.
+        let vi1 = usize::min(vi + 1, self.vertical_angles.len() - 1);
+        let hi1 = usize::min(hi + 1, self.horizontal_angles.len() - 1);
+        let lower = core::lerp(self.candela_at(hi, vi), self.candela_at(hi, vi1), vt);
+        let upper = core::lerp(self.candela_at(hi1, vi), self.candela_at(hi1, vi1), vt);
+        core::lerp(lower, upper, ht)
+    }
+}
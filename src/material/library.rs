@@ -0,0 +1,864 @@
+use material::material::{Material, SurfaceBuilder};
+use material::nodes::ShaderNode;
+use material::textures;
+use material::util;
+
+use core;
+
+use std;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Presets and nodes can refer to other presets and nodes, but not indefinitely; this catches
+/// presets that are their own bases and graphs with cycles.
+const MAX_REFERENCE_DEPTH: usize = 64;
+
+/// The value of a preset parameter. In a library file, a value is written as `true` or `false`, a
+/// single number, three numbers separated by whitespace (a color), or a single word (a name, e.g.
+/// of a metal, another preset, a shader node, or a file). Color and number parameters can be
+/// given the name of a shader node instead, which varies the parameter over the surface.
+#[derive(Clone)]
+pub enum PresetValue {
+    Number(f32),
+    Color(core::Vec),
+    Bool(bool),
+    Name(String),
+}
+
+impl PresetValue {
+    fn parse(text: &str) -> Result<PresetValue, String> {
+        let tokens: std::vec::Vec<&str> = text.split_whitespace().collect();
+        match tokens.len() {
+            0 => return Err(String::from("missing value")),
+            1 => match tokens[0] {
+                "true" => return Ok(PresetValue::Bool(true)),
+                "false" => return Ok(PresetValue::Bool(false)),
+                _ => {}
+            },
+            _ => {}
+        }
+
+        let numbers = tokens.iter()
+                .map(|token| token.parse::<f32>())
+                .collect::<Result<std::vec::Vec<f32>, _>>();
+        match numbers {
+            Ok(ref numbers) if numbers.len() == 1 => Ok(PresetValue::Number(numbers[0])),
+            Ok(ref numbers) if numbers.len() == 3 => {
+                Ok(PresetValue::Color(core::Vec::new(numbers[0], numbers[1], numbers[2])))
+            },
+            Ok(_) => Err(format!("'{}' should have one or three numbers", text)),
+            Err(_) if tokens.len() == 1 => Ok(PresetValue::Name(String::from(tokens[0]))),
+            Err(_) => Err(format!("invalid value '{}'", text))
+        }
+    }
+
+    fn number(&self, key: &str) -> Result<f32, String> {
+        match *self {
+            PresetValue::Number(x) => Ok(x),
+            _ => Err(format!("{} should be a number", key))
+        }
+    }
+
+    /// Single numbers are accepted as gray colors.
+    fn color(&self, key: &str) -> Result<core::Vec, String> {
+        match *self {
+            PresetValue::Number(x) => Ok(x * &core::Vec::one()),
+            PresetValue::Color(c) => Ok(c),
+            _ => Err(format!("{} should be a color", key))
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<bool, String> {
+        match *self {
+            PresetValue::Bool(b) => Ok(b),
+            _ => Err(format!("{} should be true or false", key))
+        }
+    }
+
+    fn name(&self, key: &str) -> Result<&str, String> {
+        match *self {
+            PresetValue::Name(ref name) => Ok(name),
+            _ => Err(format!("{} should be a name", key))
+        }
+    }
+}
+
+/// Looks up a shader node by name.
+type NodeResolver<'a> = &'a dyn Fn(&str) -> Result<Arc<ShaderNode>, String>;
+
+/// Loads an image texture from a file, with the given wrap mode and sRGB decoding.
+type TextureLoader<'a> =
+        &'a dyn Fn(&str, textures::WrapMode, bool) -> Result<Arc<dyn textures::Texture>, String>;
+
+/// Returns the shader node that a parameter refers to, or None if it's a constant.
+fn node_texture(value: &PresetValue, node: NodeResolver)
+    -> Result<Option<Arc<dyn textures::Texture>>, String>
+{
+    match *value {
+        PresetValue::Name(ref name) => Ok(Some(node(name)?)),
+        _ => Ok(None)
+    }
+}
+
+/// Returns the node for an input of another node, which is either a constant or a named node.
+fn node_input(value: &PresetValue, key: &str, node: NodeResolver)
+    -> Result<Arc<ShaderNode>, String>
+{
+    match *value {
+        PresetValue::Name(ref name) => node(name),
+        PresetValue::Bool(_) => Err(format!("{} should be a color, number, or node", key)),
+        _ => Ok(Arc::new(ShaderNode::Constant(value.color(key)?))),
+    }
+}
+
+impl fmt::Display for PresetValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PresetValue::Number(x) => write!(f, "{}", x),
+            PresetValue::Color(ref c) => write!(f, "{} {} {}", c.x, c.y, c.z),
+            PresetValue::Bool(b) => write!(f, "{}", b),
+            PresetValue::Name(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The material builder that a preset's parameters are passed to. The parameters have the same
+/// names as the builder's setters, e.g. `roughness` or `base_color`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PresetKind {
+    /// `Material::disney()`.
+    Disney,
+    /// `Material::conductor()`. The `preset` parameter names one of the metals in
+    /// `ConductorFresnel`, e.g. `gold`.
+    Conductor,
+    /// `Material::matte()`.
+    Matte,
+    /// `Material::layered()`. The required `base` parameter names another preset in the library,
+    /// which the coating is layered over.
+    Layered,
+}
+
+impl PresetKind {
+    fn parse(text: &str) -> Result<PresetKind, String> {
+        match text {
+            "disney" => Ok(PresetKind::Disney),
+            "conductor" => Ok(PresetKind::Conductor),
+            "matte" => Ok(PresetKind::Matte),
+            "layered" => Ok(PresetKind::Layered),
+            _ => Err(format!("unknown material type '{}'", text))
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            PresetKind::Disney => "disney",
+            PresetKind::Conductor => "conductor",
+            PresetKind::Matte => "matte",
+            PresetKind::Layered => "layered",
+        }
+    }
+}
+
+/// A recipe for a material: the builder to use and the parameters to pass to it, in order.
+/// Parameters that aren't set keep the builder's defaults.
+#[derive(Clone)]
+pub struct MaterialPreset {
+    kind: PresetKind,
+    params: std::vec::Vec<(String, PresetValue)>,
+}
+
+impl MaterialPreset {
+    pub fn new(kind: PresetKind) -> MaterialPreset {
+        MaterialPreset {kind, params: vec![]}
+    }
+
+    pub fn kind(&self) -> PresetKind {
+        self.kind
+    }
+
+    /// Sets a parameter, replacing any previous value. Invalid names and values are reported when
+    /// the preset is built.
+    pub fn set(&mut self, key: &str, value: PresetValue) -> &mut Self {
+        match self.params.iter_mut().find(|param| param.0 == key) {
+            Some(param) => param.1 = value,
+            None => self.params.push((String::from(key), value)),
+        }
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PresetValue> {
+        self.params.iter().find(|param| param.0 == key).map(|param| &param.1)
+    }
+
+    /// Builds the material, looking up the base of a layered material and the shader nodes bound
+    /// to parameters with the given functions.
+    fn build(&self, base: &dyn Fn(&str) -> Result<Arc<Material>, String>, node: NodeResolver)
+        -> Result<Material, String>
+    {
+        match self.kind {
+            PresetKind::Disney => {
+                let mut builder = Material::disney();
+                for (key, value) in &self.params {
+                    let key = key.as_str();
+                    let texture = node_texture(value, node)?;
+                    match (key, texture) {
+                        ("base_color", Some(t)) => { builder.base_color_texture(t); },
+                        ("base_color", None) => { builder.base_color(value.color(key)?); },
+                        ("roughness", Some(t)) => { builder.roughness_texture(t); },
+                        ("roughness", None) => { builder.roughness(value.number(key)?); },
+                        ("anisotropic", Some(t)) => { builder.anisotropic_texture(t); },
+                        ("anisotropic", None) => { builder.anisotropic(value.number(key)?); },
+                        ("ior", _) => { builder.ior(value.number(key)?); },
+                        ("metallic", Some(t)) => { builder.metallic_texture(t); },
+                        ("metallic", None) => { builder.metallic(value.number(key)?); },
+                        ("specular_trans", Some(t)) => { builder.specular_trans_texture(t); },
+                        ("specular_trans", None) => {
+                            builder.specular_trans(value.number(key)?);
+                        },
+                        ("specular_tint", Some(t)) => { builder.specular_tint_texture(t); },
+                        ("specular_tint", None) => { builder.specular_tint(value.number(key)?); },
+                        ("sheen", Some(t)) => { builder.sheen_texture(t); },
+                        ("sheen", None) => { builder.sheen(value.number(key)?); },
+                        ("sheen_tint", Some(t)) => { builder.sheen_tint_texture(t); },
+                        ("sheen_tint", None) => { builder.sheen_tint(value.number(key)?); },
+                        ("clearcoat", Some(t)) => { builder.clearcoat_texture(t); },
+                        ("clearcoat", None) => { builder.clearcoat(value.number(key)?); },
+                        ("clearcoat_gloss", Some(t)) => { builder.clearcoat_gloss_texture(t); },
+                        ("clearcoat_gloss", None) => {
+                            builder.clearcoat_gloss(value.number(key)?);
+                        },
+                        ("subsurface", Some(t)) => { builder.subsurface_texture(t); },
+                        ("subsurface", None) => { builder.subsurface(value.number(key)?); },
+                        ("subsurface_radius", _) => {
+                            builder.subsurface_radius(value.color(key)?);
+                        },
+                        ("thin", _) => { builder.thin(value.boolean(key)?); },
+                        ("diff_trans", Some(t)) => { builder.diff_trans_texture(t); },
+                        ("diff_trans", None) => { builder.diff_trans(value.number(key)?); },
+                        ("flatness", Some(t)) => { builder.flatness_texture(t); },
+                        ("flatness", None) => { builder.flatness(value.number(key)?); },
+                        ("thin_film_thickness", Some(t)) => {
+                            builder.thin_film_thickness_texture(t);
+                        },
+                        ("thin_film_thickness", None) => {
+                            builder.thin_film_thickness(value.number(key)?);
+                        },
+                        ("thin_film_ior", _) => { builder.thin_film_ior(value.number(key)?); },
+                        ("opacity", Some(t)) => { builder.opacity_texture(t); },
+                        ("opacity", None) => { builder.opacity(value.number(key)?); },
+                        ("emission", Some(t)) => {
+                            builder.emission(core::Vec::one()).emission_texture(t);
+                        },
+                        ("emission", None) => { builder.emission(value.color(key)?); },
+                        ("emission_intensity", _) => {
+                            builder.emission_intensity(value.number(key)?);
+                        },
+                        _ => return Err(unknown_param(self.kind.name(), key))
+                    }
+                }
+                Ok(builder.build())
+            },
+            PresetKind::Conductor => {
+                let mut builder = Material::conductor();
+                for (key, value) in &self.params {
+                    let key = key.as_str();
+                    match key {
+                        "preset" => { builder.preset(conductor_preset(value.name(key)?)?); },
+                        "eta" => { builder.eta(value.color(key)?); },
+                        "k" => { builder.k(value.color(key)?); },
+                        "roughness" => { builder.roughness(value.number(key)?); },
+                        "anisotropic" => { builder.anisotropic(value.number(key)?); },
+                        "thin_film_thickness" => match node_texture(value, node)? {
+                            Some(t) => { builder.thin_film_thickness_texture(t); },
+                            None => { builder.thin_film_thickness(value.number(key)?); },
+                        },
+                        "thin_film_ior" => { builder.thin_film_ior(value.number(key)?); },
+                        _ => return Err(unknown_param(self.kind.name(), key))
+                    }
+                }
+                Ok(builder.build())
+            },
+            PresetKind::Matte => {
+                let mut builder = Material::matte();
+                for (key, value) in &self.params {
+                    let key = key.as_str();
+                    let texture = node_texture(value, node)?;
+                    match (key, texture) {
+                        ("color", Some(t)) => { builder.color_texture(t); },
+                        ("color", None) => { builder.color(value.color(key)?); },
+                        ("sigma", Some(t)) => { builder.sigma_texture(t); },
+                        ("sigma", None) => { builder.sigma(value.number(key)?); },
+                        ("opacity", Some(t)) => { builder.opacity_texture(t); },
+                        ("opacity", None) => { builder.opacity(value.number(key)?); },
+                        _ => return Err(unknown_param(self.kind.name(), key))
+                    }
+                }
+                Ok(builder.build())
+            },
+            PresetKind::Layered => {
+                let base = match self.get("base") {
+                    Some(value) => base(value.name("base")?)?,
+                    None => return Err(String::from("layered materials need a base")),
+                };
+                let mut builder = Material::layered(base);
+                for (key, value) in &self.params {
+                    let key = key.as_str();
+                    match key {
+                        "base" => {},
+                        "roughness" => { builder.roughness(value.number(key)?); },
+                        "ior" => { builder.ior(value.number(key)?); },
+                        "tint" => { builder.tint(value.color(key)?); },
+                        _ => return Err(unknown_param(self.kind.name(), key))
+                    }
+                }
+                Ok(builder.build())
+            },
+        }
+    }
+}
+
+fn unknown_param(kind: &str, key: &str) -> String {
+    format!("{} has no parameter '{}'", kind, key)
+}
+
+fn conductor_preset(name: &str) -> Result<util::ConductorFresnel, String> {
+    match name {
+        "gold" => Ok(util::ConductorFresnel::gold()),
+        "silver" => Ok(util::ConductorFresnel::silver()),
+        "copper" => Ok(util::ConductorFresnel::copper()),
+        "aluminum" => Ok(util::ConductorFresnel::aluminum()),
+        "chrome" => Ok(util::ConductorFresnel::chrome()),
+        _ => Err(format!("unknown metal '{}'", name))
+    }
+}
+
+/// The kind of a shader node in a library. The parameters of each kind are listed below; inputs
+/// can be constants or the names of other nodes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    /// `ShaderNode::Constant`, with a `value`.
+    Constant,
+    /// `ShaderNode::Texture`, looking up an image `file`, which is decoded from sRGB unless `srgb`
+    /// is false and wraps according to `wrap` (`repeat`, `mirror`, or `clamp`). An optional `uv`
+    /// input gives the texture coordinates. Relative paths are relative to the working directory.
+    Texture,
+    /// `ShaderNode::Normal`.
+    Normal,
+    /// `ShaderNode::Uv`.
+    Uv,
+    /// `ShaderNode::Position`.
+    Position,
+    /// `ShaderNode::FacingRatio`.
+    FacingRatio,
+    /// `ShaderNode::Fresnel`, with an `ior` that defaults to 1.5.
+    Fresnel,
+    /// `ShaderNode::Mix` of the inputs `a` and `b` by the input `t`.
+    Mix,
+    /// `ShaderNode::Multiply` of the inputs `a` and `b`.
+    Multiply,
+    /// `ShaderNode::Remap` of the `input` from `from_min` and `from_max` (defaulting to 0 and 1) to
+    /// `to_min` and `to_max` (also defaulting to 0 and 1).
+    Remap,
+    /// `ShaderNode::Clamp` of the `input` to `min` and `max`, which default to 0 and 1.
+    Clamp,
+}
+
+impl NodeKind {
+    fn parse(text: &str) -> Option<NodeKind> {
+        match text {
+            "constant" => Some(NodeKind::Constant),
+            "texture" => Some(NodeKind::Texture),
+            "normal" => Some(NodeKind::Normal),
+            "uv" => Some(NodeKind::Uv),
+            "position" => Some(NodeKind::Position),
+            "facing_ratio" => Some(NodeKind::FacingRatio),
+            "fresnel" => Some(NodeKind::Fresnel),
+            "mix" => Some(NodeKind::Mix),
+            "multiply" => Some(NodeKind::Multiply),
+            "remap" => Some(NodeKind::Remap),
+            "clamp" => Some(NodeKind::Clamp),
+            _ => None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            NodeKind::Constant => "constant",
+            NodeKind::Texture => "texture",
+            NodeKind::Normal => "normal",
+            NodeKind::Uv => "uv",
+            NodeKind::Position => "position",
+            NodeKind::FacingRatio => "facing_ratio",
+            NodeKind::Fresnel => "fresnel",
+            NodeKind::Mix => "mix",
+            NodeKind::Multiply => "multiply",
+            NodeKind::Remap => "remap",
+            NodeKind::Clamp => "clamp",
+        }
+    }
+
+    fn params(&self) -> &'static [&'static str] {
+        match *self {
+            NodeKind::Constant => &["value"],
+            NodeKind::Texture => &["file", "srgb", "wrap", "uv"],
+            NodeKind::Normal | NodeKind::Uv | NodeKind::Position | NodeKind::FacingRatio => &[],
+            NodeKind::Fresnel => &["ior"],
+            NodeKind::Mix => &["a", "b", "t"],
+            NodeKind::Multiply => &["a", "b"],
+            NodeKind::Remap => &["input", "from_min", "from_max", "to_min", "to_max"],
+            NodeKind::Clamp => &["input", "min", "max"],
+        }
+    }
+}
+
+/// A recipe for a shader node: its kind and parameters, where inputs from other nodes are given by
+/// name.
+#[derive(Clone)]
+pub struct NodePreset {
+    kind: NodeKind,
+    params: std::vec::Vec<(String, PresetValue)>,
+}
+
+impl NodePreset {
+    pub fn new(kind: NodeKind) -> NodePreset {
+        NodePreset {kind, params: vec![]}
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Sets a parameter, replacing any previous value. Invalid names and values are reported when
+    /// the node is built.
+    pub fn set(&mut self, key: &str, value: PresetValue) -> &mut Self {
+        match self.params.iter_mut().find(|param| param.0 == key) {
+            Some(param) => param.1 = value,
+            None => self.params.push((String::from(key), value)),
+        }
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&PresetValue> {
+        self.params.iter().find(|param| param.0 == key).map(|param| &param.1)
+    }
+
+    /// Builds the node, looking up its inputs and loading its texture with the given functions.
+    fn build(&self, node: NodeResolver, texture: TextureLoader) -> Result<ShaderNode, String>
+    {
+        for (key, _) in &self.params {
+            if !self.kind.params().contains(&key.as_str()) {
+                return Err(unknown_param(self.kind.name(), key));
+            }
+        }
+        let input = |key: &str| match self.get(key) {
+            Some(value) => node_input(value, key, node),
+            None => Err(format!("{} needs an input '{}'", self.kind.name(), key)),
+        };
+        let number = |key: &str, default: f32| match self.get(key) {
+            Some(value) => value.number(key),
+            None => Ok(default),
+        };
+
+        match self.kind {
+            NodeKind::Constant => match self.get("value") {
+                Some(value) => Ok(ShaderNode::Constant(value.color("value")?)),
+                None => Err(String::from("constant needs a value")),
+            },
+            NodeKind::Texture => {
+                let file = match self.get("file") {
+                    Some(value) => value.name("file")?,
+                    None => return Err(String::from("texture needs a file")),
+                };
+                let srgb = match self.get("srgb") {
+                    Some(value) => value.boolean("srgb")?,
+                    None => true,
+                };
+                let wrap = match self.get("wrap") {
+                    Some(value) => match value.name("wrap")? {
+                        "repeat" => textures::WrapMode::Repeat,
+                        "mirror" => textures::WrapMode::Mirror,
+                        "clamp" => textures::WrapMode::Clamp,
+                        other => return Err(format!("unknown wrap mode '{}'", other)),
+                    },
+                    None => textures::WrapMode::Repeat,
+                };
+                let uv = match self.get("uv") {
+                    Some(value) => Some(node_input(value, "uv", node)?),
+                    None => None,
+                };
+                Ok(ShaderNode::Texture(texture(file, wrap, srgb)?, uv))
+            },
+            NodeKind::Normal => Ok(ShaderNode::Normal),
+            NodeKind::Uv => Ok(ShaderNode::Uv),
+            NodeKind::Position => Ok(ShaderNode::Position),
+            NodeKind::FacingRatio => Ok(ShaderNode::FacingRatio),
+            NodeKind::Fresnel => Ok(ShaderNode::Fresnel(number("ior", 1.5)?)),
+            NodeKind::Mix => Ok(ShaderNode::Mix(input("a")?, input("b")?, input("t")?)),
+            NodeKind::Multiply => Ok(ShaderNode::Multiply(input("a")?, input("b")?)),
+            NodeKind::Remap => Ok(ShaderNode::Remap {
+                input: input("input")?,
+                from_min: number("from_min", 0.0)?,
+                from_max: number("from_max", 1.0)?,
+                to_min: number("to_min", 0.0)?,
+                to_max: number("to_max", 1.0)?,
+            }),
+            NodeKind::Clamp => Ok(ShaderNode::Clamp {
+                input: input("input")?,
+                min: number("min", 0.0)?,
+                max: number("max", 1.0)?,
+            }),
+        }
+    }
+}
+
+/// A palette of named material presets, which scenes can build materials from by name, optionally
+/// overriding some of the parameters. Presets can bind their parameters to graphs of named shader
+/// nodes, which vary the parameters over the surface. Libraries are saved as text files like this:
+///
+/// ```text
+/// # Comments start with a hash.
+/// [frosted_glass]
+/// type = disney
+/// roughness = 0.3
+/// specular_trans = 1
+///
+/// [lacquered_glass]
+/// type = layered
+/// base = frosted_glass
+/// tint = 0.9 0.95 1
+///
+/// # Plastic that's redder where it faces the viewer.
+/// [facing]
+/// type = facing_ratio
+///
+/// [rim_color]
+/// type = mix
+/// a = 0.2 0.05 0.05
+/// b = 0.9 0.1 0.1
+/// t = facing
+///
+/// [rim_plastic]
+/// type = disney
+/// base_color = rim_color
+/// ```
+///
+/// Each section is a preset or a node, whose name must be a single word. The `type` of a preset
+/// is one of the kinds in `PresetKind`, and the other keys are the parameters of its builder. The
+/// `type` of a node is one of the kinds in `NodeKind`, written in snake case, and the other keys
+/// are its parameters.
+#[derive(Clone, Default)]
+pub struct MaterialLibrary {
+    presets: std::vec::Vec<(String, MaterialPreset)>,
+    nodes: std::vec::Vec<(String, NodePreset)>,
+}
+
+/// A section of a library file, before it's known whether it's a preset or a node.
+struct Section {
+    line: usize,
+    name: String,
+    kind: Option<String>,
+    params: std::vec::Vec<(String, PresetValue)>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> MaterialLibrary {
+        MaterialLibrary {presets: vec![], nodes: vec![]}
+    }
+
+    /// A library of common materials: glass, brushed_aluminum, metallic_paint, car_paint (a
+    /// clear coat over metallic_paint), plastic, rubber, and skin. The skin's scattering distances
+    /// assume that scene units are centimeters.
+    pub fn studio() -> MaterialLibrary {
+        use self::PresetValue::*;
+        let mut library = MaterialLibrary::new();
+        library.insert("glass", MaterialPreset::new(PresetKind::Disney)
+                .set("roughness", Number(0.0))
+                .set("specular_trans", Number(1.0))
+                .set("ior", Number(1.5))
+                .clone());
+        library.insert("brushed_aluminum", MaterialPreset::new(PresetKind::Conductor)
+                .set("preset", Name(String::from("aluminum")))
+                .set("roughness", Number(0.35))
+                .set("anisotropic", Number(0.8))
+                .clone());
+        library.insert("metallic_paint", MaterialPreset::new(PresetKind::Disney)
+                .set("base_color", Color(core::Vec::new(0.5, 0.03, 0.03)))
+                .set("metallic", Number(0.6))
+                .set("roughness", Number(0.4))
+                .clone());
+        library.insert("car_paint", MaterialPreset::new(PresetKind::Layered)
+                .set("base", Name(String::from("metallic_paint")))
+                .set("roughness", Number(0.02))
+                .set("ior", Number(1.5))
+                .clone());
+        library.insert("plastic", MaterialPreset::new(PresetKind::Disney)
+                .set("base_color", Color(core::Vec::new(0.8, 0.1, 0.1)))
+                .set("roughness", Number(0.3))
+                .set("ior", Number(1.46))
+                .clone());
+        library.insert("rubber", MaterialPreset::new(PresetKind::Disney)
+                .set("base_color", Color(core::Vec::new(0.03, 0.03, 0.03)))
+                .set("roughness", Number(0.85))
+                .set("ior", Number(1.52))
+                .set("sheen", Number(0.3))
+                .clone());
+        library.insert("skin", MaterialPreset::new(PresetKind::Disney)
+                .set("base_color", Color(core::Vec::new(0.85, 0.6, 0.5)))
+                .set("roughness", Number(0.45))
+                .set("ior", Number(1.4))
+                .set("subsurface", Number(1.0))
+                .set("subsurface_radius", Color(core::Vec::new(0.37, 0.14, 0.07)))
+                .set("sheen", Number(0.2))
+                .clone());
+        library
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MaterialLibrary, String> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(reason) => {
+                return Err(format!("Couldn't open material library: {}", reason));
+            }
+        };
+
+        let mut contents = String::new();
+        if let Err(reason) = file.read_to_string(&mut contents) {
+            return Err(format!("Couldn't read material library: {}", reason));
+        }
+
+        MaterialLibrary::parse(&contents)
+    }
+
+    /// Parses a library in the text format. The presets and nodes are checked for unknown
+    /// parameters and invalid values, but the presets and nodes that they refer to may be in a
+    /// library that's merged in later, and texture files aren't loaded until they're built.
+    pub fn parse(contents: &str) -> Result<MaterialLibrary, String> {
+        let error = |line: usize, message: String| {
+            format!("Material library parse error: line {}: {}", line, message)
+        };
+
+        let mut sections: std::vec::Vec<Section> = vec![];
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return Err(error(line_number, String::from("missing ']'")));
+                }
+                let name = line[1..(line.len() - 1)].trim();
+                if name.is_empty() || name.contains(char::is_whitespace) {
+                    return Err(error(line_number,
+                            format!("name '{}' should be a single word", name)));
+                }
+                if sections.iter().any(|section| section.name == name) {
+                    return Err(error(line_number, format!("{} is defined twice", name)));
+                }
+                sections.push(Section {
+                    line: line_number, name: String::from(name), kind: None, params: vec![]
+                });
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(equals) => (line[..equals].trim(), line[(equals + 1)..].trim()),
+                None => return Err(error(line_number, String::from("expected key = value"))),
+            };
+            let section = match sections.last_mut() {
+                Some(section) => section,
+                None => {
+                    return Err(error(line_number, String::from("expected a [name] first")));
+                }
+            };
+            if key == "type" {
+                if section.kind.is_some() {
+                    return Err(error(line_number, String::from("type is set twice")));
+                }
+                section.kind = Some(String::from(value));
+            }
+            else {
+                if section.params.iter().any(|param| param.0 == key) {
+                    return Err(error(line_number, format!("{} is set twice", key)));
+                }
+                let value = PresetValue::parse(value)
+                        .map_err(|e| error(line_number, format!("{}: {}", key, e)))?;
+                section.params.push((String::from(key), value));
+            }
+        }
+
+        // Check each section with placeholders for the presets, nodes, and textures it refers to.
+        let base = |_: &str| Ok(Arc::new(Material::diffuse(core::Vec::one())));
+        let node = |_: &str| Ok(Arc::new(ShaderNode::Constant(core::Vec::zero())));
+        let texture = |_: &str, _: textures::WrapMode, _: bool| {
+            let texture: Arc<dyn textures::Texture> =
+                    Arc::new(textures::ConstantTexture::new(core::Vec::zero()));
+            Ok(texture)
+        };
+        let mut library = MaterialLibrary::new();
+        for section in sections {
+            let line = section.line;
+            let kind = match section.kind {
+                Some(kind) => kind,
+                None => return Err(error(line, format!("{} has no type", section.name))),
+            };
+            let result = if let Some(node_kind) = NodeKind::parse(&kind) {
+                let preset = NodePreset {kind: node_kind, params: section.params};
+                let result = preset.build(&node, &texture).map(|_| ());
+                library.insert_node(&section.name, preset);
+                result
+            }
+            else {
+                let preset_kind = PresetKind::parse(&kind)
+                        .map_err(|e| error(line, e))?;
+                let preset = MaterialPreset {kind: preset_kind, params: section.params};
+                let result = preset.build(&base, &node).map(|_| ());
+                library.insert(&section.name, preset);
+                result
+            };
+            if let Err(message) = result {
+                return Err(error(line, format!("{}: {}", section.name, message)));
+            }
+        }
+
+        Ok(library)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let mut file = match File::create(path) {
+            Ok(f) => f,
+            Err(reason) => {
+                return Err(format!("Couldn't create material library: {}", reason));
+            }
+        };
+        if let Err(reason) = file.write_all(self.to_string().as_bytes()) {
+            return Err(format!("Couldn't write material library: {}", reason));
+        }
+        Ok(())
+    }
+
+    /// Adds a preset, replacing any preset with the same name.
+    pub fn insert(&mut self, name: &str, preset: MaterialPreset) -> &mut Self {
+        match self.presets.iter_mut().find(|entry| entry.0 == name) {
+            Some(entry) => entry.1 = preset,
+            None => self.presets.push((String::from(name), preset)),
+        }
+        self
+    }
+
+    /// Adds a shader node, replacing any node with the same name.
+    pub fn insert_node(&mut self, name: &str, node: NodePreset) -> &mut Self {
+        match self.nodes.iter_mut().find(|entry| entry.0 == name) {
+            Some(entry) => entry.1 = node,
+            None => self.nodes.push((String::from(name), node)),
+        }
+        self
+    }
+
+    /// Adds all of the presets and nodes in another library, replacing the ones with the same
+    /// names.
+    pub fn merge(&mut self, other: MaterialLibrary) -> &mut Self {
+        for (name, preset) in other.presets {
+            self.insert(&name, preset);
+        }
+        for (name, node) in other.nodes {
+            self.insert_node(&name, node);
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MaterialPreset> {
+        self.presets.iter().find(|entry| entry.0 == name).map(|entry| &entry.1)
+    }
+
+    pub fn get_node(&self, name: &str) -> Option<&NodePreset> {
+        self.nodes.iter().find(|entry| entry.0 == name).map(|entry| &entry.1)
+    }
+
+    /// The names of the presets, in the order that they were added.
+    pub fn names(&self) -> std::vec::Vec<&str> {
+        self.presets.iter().map(|entry| entry.0.as_str()).collect()
+    }
+
+    pub fn build(&self, name: &str) -> Result<Material, String> {
+        self.build_with(name, &[])
+    }
+
+    /// Builds the named preset with some of its parameters replaced, e.g.
+    /// `library.build_with("plastic", &[("roughness", PresetValue::Number(0.1))])`.
+    pub fn build_with(&self, name: &str, overrides: &[(&str, PresetValue)])
+        -> Result<Material, String>
+    {
+        self.build_preset(name, overrides, 0)
+    }
+
+    /// Builds the graph of shader nodes that ends at the named node, e.g. to bind it to a
+    /// parameter of a material that's built in code.
+    pub fn build_node(&self, name: &str) -> Result<Arc<ShaderNode>, String> {
+        self.build_node_at(name, 0)
+    }
+
+    fn build_preset(&self, name: &str, overrides: &[(&str, PresetValue)], depth: usize)
+        -> Result<Material, String>
+    {
+        if depth > MAX_REFERENCE_DEPTH {
+            return Err(format!("{} is layered too deeply; is it its own base?", name));
+        }
+        let mut preset = match self.get(name) {
+            Some(preset) => preset.clone(),
+            None => return Err(format!("There's no material preset named {}", name)),
+        };
+        for &(key, ref value) in overrides {
+            preset.set(key, value.clone());
+        }
+        preset.build(
+                &|base| self.build_preset(base, &[], depth + 1).map(Arc::new),
+                &|node| self.build_node_at(node, depth + 1))
+                .map_err(|message| {
+                    if depth == 0 { format!("{}: {}", name, message) } else { message }
+                })
+    }
+
+    fn build_node_at(&self, name: &str, depth: usize) -> Result<Arc<ShaderNode>, String> {
+        if depth > MAX_REFERENCE_DEPTH {
+            return Err(format!("{} is nested too deeply; does it depend on itself?", name));
+        }
+        let node = match self.get_node(name) {
+            Some(node) => node,
+            None => return Err(format!("There's no shader node named {}", name)),
+        };
+        let texture = |file: &str, wrap: textures::WrapMode, srgb: bool| {
+            textures::ImageTexture::from_file(file, wrap, srgb)
+                    .map(|texture| Arc::new(texture) as Arc<dyn textures::Texture>)
+        };
+        node.build(&|input| self.build_node_at(input, depth + 1), &texture).map(Arc::new)
+    }
+}
+
+impl fmt::Display for MaterialLibrary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let nodes = self.nodes.iter()
+                .map(|(name, node)| (name, node.kind.name(), &node.params));
+        let presets = self.presets.iter()
+                .map(|(name, preset)| (name, preset.kind.name(), &preset.params));
+        for (index, (name, kind, params)) in nodes.chain(presets).enumerate() {
+            if index != 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", name)?;
+            writeln!(f, "type = {}", kind)?;
+            for (key, value) in params {
+                writeln!(f, "{} = {}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
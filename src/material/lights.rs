@@ -1,22 +1,297 @@
+use material::ies;
+use material::textures;
+
 use core;
 use geom;
 
+use std;
+use std::sync::Arc;
+use rand;
+use rand::Rng;
+use rand::distributions::IndependentSample;
+
 pub trait Light : Sync + Send {
     fn l_world(&self, i: &core::Vec, surface_props: &geom::SurfaceProperties) -> core::Vec;
+
+    /// Samples a direction in which light is emitted from a point on the surface.
+    /// Returns the world-space direction and its pdf with respect to solid angle.
+    /// The default implementation samples a cosine-weighted hemisphere around the geometric
+    /// normal.
+    fn sample_dir_world(&self, surface_props: &geom::SurfaceProperties,
+        rng: &mut rand::XorShiftRng) -> (core::Vec, f32)
+    {
+        let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: false};
+        let dir = cosine_sample_hemis.ind_sample(rng);
+        let dir_pdf = core::CosineSampleHemisphere::pdf(&dir);
+
+        let (tangent, binormal) = surface_props.geom_normal.coord_system();
+        let dir_world = dir.local_to_world(&tangent, &binormal, &surface_props.geom_normal);
+        (dir_world, dir_pdf)
+    }
+
+    /// Notifies the light of the total surface area of the prim that it's attached to, so that
+    /// lights specified in physical units can normalize their emission. The default does nothing.
+    fn set_surface_area(&mut self, _area: f32) {}
+
+    /// The name of the light group that this light contributes to, if any. Renderers can output
+    /// a separate image for each group so that lights can be rebalanced in compositing.
+    fn group(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Luminous efficacy used to convert between photometric and radiometric units, in lm/W.
+/// A radiance with luminance 1 corresponds to 683 nits.
+pub const LUMINOUS_EFFICACY: f32 = 683.0;
+
+/// Units in which the intensity of an area light is specified.
+/// When using physical units, the scene is assumed to be in meters, and the light's color only
+/// determines the chromaticity; it is normalized to unit luminance. An emission texture scales
+/// the emission after it's normalized, so a texture that averages to half over the surface halves
+/// the emitted power.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightUnits {
+    /// The intensity is a bare multiplier on the color, which is emitted as radiance.
+    Radiance,
+    /// The intensity is the total luminous flux emitted by the surface divided by
+    /// LUMINOUS_EFFICACY, i.e. lumens / 683. This is power in the renderer's units, which only
+    /// matches the radiant power in watts for light at the peak of the eye's sensitivity.
+    LuminousWatts,
+    /// The intensity is the total luminous flux emitted by the surface, in lumens.
+    Lumens,
+    /// The intensity is the peak luminance of the surface, in nits (cd/m^2).
+    Nits,
 }
 
+/// Resolution of the tabulated distribution used to importance-sample IES profiles, in
+/// polar angle (over the hemisphere) and azimuth.
+const PROFILE_THETA_RES: usize = 64;
+const PROFILE_PHI_RES: usize = 128;
+/// Number of subdivisions of each cell of the profile distribution, per axis, at which the
+/// profile is evaluated.
+const PROFILE_CELL_SUBDIVISIONS: usize = 4;
+/// Fraction of the average of the profile distribution that's added to every cell, so that
+/// directions between the evaluated points can still be sampled.
+const PROFILE_DIST_FLOOR: f32 = 0.01;
+
 pub struct DiffuseAreaLight {
-    pub color: core::Vec
+    pub color: core::Vec,
+    pub group: Option<String>,
+    intensity: f32,
+    units: LightUnits,
+    /// Multiplier that converts the color into radiance; depends on the units and surface area.
+    scale: f32,
+    two_sided: bool,
+    texture: Option<Arc<dyn textures::Texture>>,
+    profile: Option<Arc<ies::IesProfile>>,
+    /// Distribution over (phi, theta) in the hemisphere around the normal, roughly proportional
+    /// to the emitted power in each direction, and nonzero everywhere. Only used if there's a
+    /// profile.
+    profile_dist: Option<core::PiecewiseConstant2D>,
+    /// The integral of the profile times the cosine to the normal over the hemisphere.
+    profile_projected_solid_angle: f32,
+}
+
+impl DiffuseAreaLight {
+    pub fn new(color: core::Vec) -> DiffuseAreaLight {
+        DiffuseAreaLight::new_with_options(color, 1.0, LightUnits::Radiance, false, None, None)
+    }
+
+    /// Creates a light that can optionally emit on both sides of the surface, have its color
+    /// modulated by a texture, and have its emission shaped by a goniometric profile, whose
+    /// vertical axis is aligned with the surface normal.
+    /// Lights in units of power emit nothing until set_surface_area is called.
+    pub fn new_with_options(
+        color: core::Vec, intensity: f32, units: LightUnits, two_sided: bool,
+        texture: Option<Arc<dyn textures::Texture>>, profile: Option<Arc<ies::IesProfile>>)
+        -> DiffuseAreaLight
+    {
+        let (profile_dist, profile_projected_solid_angle) = match profile {
+            Some(ref p) => {
+                let (dist, projected_solid_angle) = DiffuseAreaLight::compute_profile_dist(p);
+                (Some(dist), projected_solid_angle)
+            },
+            None => (None, 0.0)
+        };
+        let mut light = DiffuseAreaLight {
+            color, group: None, intensity, units, scale: 0.0,
+            two_sided, texture, profile, profile_dist, profile_projected_solid_angle
+        };
+        light.scale = light.compute_scale(None);
+        light
+    }
+
+    /// Computes the multiplier that converts the color into radiance. Units of power need the
+    /// surface area; without it, the light emits nothing.
+    fn compute_scale(&self, area: Option<f32>) -> f32 {
+        // Physical units only control the brightness, so normalize the color to unit luminance.
+        let luminance = self.color.luminance();
+        let sides = if self.two_sided { 2.0 } else { 1.0 };
+        let radiance = match self.units {
+            LightUnits::Radiance => self.intensity * luminance,
+            LightUnits::Nits => self.intensity / LUMINOUS_EFFICACY,
+            LightUnits::LuminousWatts | LightUnits::Lumens => {
+                let luminous_watts = if self.units == LightUnits::Lumens {
+                    self.intensity / LUMINOUS_EFFICACY
+                }
+                else {
+                    self.intensity
+                };
+
+                // Power = radiance * area * projected solid angle, for each emitting side.
+                let denom = area.unwrap_or(0.0) * sides * self.projected_solid_angle();
+                if denom > 0.0 { luminous_watts / denom } else { 0.0 }
+            }
+        };
+        if luminance > 0.0 { radiance / luminance } else { 0.0 }
+    }
+
+    /// The projected solid angle of the emission on one side of the surface, i.e. the integral of
+    /// the profile times the cosine to the normal over the hemisphere. This is pi for a
+    /// Lambertian emitter.
+    fn projected_solid_angle(&self) -> f32 {
+        match self.profile {
+            Some(_) => self.profile_projected_solid_angle,
+            None => std::f32::consts::PI
+        }
+    }
+
+    /// Tabulates the distribution used to sample directions from the profile, and integrates the
+    /// profile's projected solid angle. The distribution is over [0, 1)^2, which maps to theta in
+    /// [0, pi/2) and phi in [0, 2pi).
+    fn compute_profile_dist(profile: &ies::IesProfile) -> (core::PiecewiseConstant2D, f32) {
+        let d_theta = std::f32::consts::FRAC_PI_2 / PROFILE_THETA_RES as f32;
+        let d_phi = core::TWO_PI / PROFILE_PHI_RES as f32;
+        let eval = |theta: f32, phi: f32| {
+            profile.eval(&core::Vec::from_spherical(f32::cos(theta), phi))
+        };
+
+        // The emitted power in a direction is proportional to the radiance times the cosine to
+        // the normal, and the sin(theta) factor accounts for the change of variables from
+        // solid angle to (phi, theta). Each cell takes the largest value of the profile at a grid
+        // of points covering the cell, including its edges, and the largest value of the cosine
+        // and sine within the cell, so that narrow beams that miss the center of a cell are
+        // still sampled. The projected solid angle is integrated at the midpoints of the grid.
+        let n = PROFILE_CELL_SUBDIVISIONS;
+        let mut func = std::vec::Vec::<f32>::with_capacity(PROFILE_THETA_RES * PROFILE_PHI_RES);
+        let mut projected_solid_angle = 0.0;
+        for row in 0..PROFILE_THETA_RES {
+            let theta_min = row as f32 * d_theta;
+            let cos_sin_max = f32::cos(theta_min) * f32::sin(theta_min + d_theta);
+            for col in 0..PROFILE_PHI_RES {
+                let phi_min = col as f32 * d_phi;
+                let mut profile_max: f32 = 0.0;
+                for j in 0..(n + 1) {
+                    for k in 0..(n + 1) {
+                        let theta = theta_min + d_theta * j as f32 / n as f32;
+                        let phi = phi_min + d_phi * k as f32 / n as f32;
+                        profile_max = f32::max(profile_max, eval(theta, phi));
+                    }
+                }
+                for j in 0..n {
+                    for k in 0..n {
+                        let theta = theta_min + d_theta * (j as f32 + 0.5) / n as f32;
+                        let phi = phi_min + d_phi * (k as f32 + 0.5) / n as f32;
+                        projected_solid_angle += eval(theta, phi) * f32::cos(theta) *
+                                f32::sin(theta) * d_theta * d_phi / (n * n) as f32;
+                    }
+                }
+                func.push(profile_max * cos_sin_max);
+            }
+        }
+
+        // Beams can still fall between the grid points, so give every direction some chance.
+        let average = func.iter().sum::<f32>() / func.len() as f32;
+        let floor = if average > 0.0 { PROFILE_DIST_FLOOR * average } else { 1.0 };
+        for x in &mut func {
+            *x += floor;
+        }
+        (core::PiecewiseConstant2D::new(&func, PROFILE_PHI_RES, PROFILE_THETA_RES),
+                projected_solid_angle)
+    }
+
+    /// Frame around the geometric normal in which the profile is evaluated. The tangent is used
+    /// so that the profile's horizontal angles are stable across the surface.
+    fn emission_frame(surface_props: &geom::SurfaceProperties) -> (core::Vec, core::Vec) {
+        let n = &surface_props.geom_normal;
+        let t = &surface_props.tangent - &(surface_props.tangent.dot(n) * n);
+        if t.is_nearly_zero() {
+            n.coord_system()
+        }
+        else {
+            let t = t.normalized();
+            let b = n.cross(&t);
+            (t, b)
+        }
+    }
 }
 
 impl Light for DiffuseAreaLight {
     fn l_world(&self, i: &core::Vec, surface_props: &geom::SurfaceProperties) -> core::Vec {
-        // Only emit light if the vector is on the same side as the normal.
-        if i.dot(&surface_props.geom_normal) > 0.0 {
-            self.color
+        // Only emit light if the vector is on the same side as the normal, unless two-sided.
+        let cos_theta = i.dot(&surface_props.geom_normal);
+        if cos_theta == 0.0 || (cos_theta < 0.0 && !self.two_sided) {
+            return core::Vec::zero();
         }
-        else {
-            core::Vec::zero()
+
+        let mut color = &self.color * self.scale;
+        if let Some(ref texture) = self.texture {
+            color = color.comp_mult(&texture.eval(surface_props));
+        }
+        if let Some(ref profile) = self.profile {
+            // The back side of a two-sided light mirrors the front side.
+            let (tangent, binormal) = DiffuseAreaLight::emission_frame(surface_props);
+            let local = i.world_to_local(&tangent, &binormal, &surface_props.geom_normal);
+            let local = core::Vec::new(local.x, local.y, f32::abs(local.z));
+            color = &color * profile.eval(&local);
+        }
+        color
+    }
+
+    fn sample_dir_world(&self, surface_props: &geom::SurfaceProperties,
+        rng: &mut rand::XorShiftRng) -> (core::Vec, f32)
+    {
+        let (tangent, binormal) = DiffuseAreaLight::emission_frame(surface_props);
+        let (mut dir, mut pdf) = match self.profile_dist {
+            Some(ref dist) => {
+                let ((u, v), uv_pdf) = dist.sample_continuous(rng.next_f32(), rng.next_f32());
+                let theta = v * std::f32::consts::FRAC_PI_2;
+                let phi = u * core::TWO_PI;
+                let sin_theta = f32::sin(theta);
+                let dir = core::Vec::from_spherical(f32::cos(theta), phi);
+                let pdf = if sin_theta == 0.0 {
+                    0.0
+                }
+                else {
+                    uv_pdf / (std::f32::consts::FRAC_PI_2 * core::TWO_PI * sin_theta)
+                };
+                (dir, pdf)
+            },
+            _ => {
+                let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: false};
+                let dir = cosine_sample_hemis.ind_sample(rng);
+                let pdf = core::CosineSampleHemisphere::pdf(&dir);
+                (dir, pdf)
+            }
+        };
+
+        // Two-sided lights emit the same distribution on each side.
+        if self.two_sided {
+            if rng.next_f32() < 0.5 {
+                dir = core::Vec::new(dir.x, dir.y, -dir.z);
+            }
+            pdf *= 0.5;
         }
+
+        let dir_world = dir.local_to_world(&tangent, &binormal, &surface_props.geom_normal);
+        (dir_world, pdf)
+    }
+
+    fn set_surface_area(&mut self, area: f32) {
+        self.scale = self.compute_scale(Some(area));
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.group.as_deref()
     }
 }
@@ -1,3 +1,4 @@
+use material::energy;
 use material::util;
 use material::util::Fresnel;
 use material::util::MicrofacetDistribution;
@@ -7,7 +8,9 @@ use core;
 use std;
 use std::fmt;
 use std::fmt::Display;
+use std::sync::OnceLock;
 use rand;
+use rand::Rng;
 use rand::distributions::IndependentSample;
 
 pub struct LobeSample {
@@ -36,6 +39,10 @@ bitflags! {
         const LOBE_REFLECTION   = 0b00001000;
         /// Out and in direction are different hemispheres.
         const LOBE_TRANSMISSION = 0b00010000;
+        /// Out direction enters the surface, where light continues as a random walk through the
+        /// interior medium returned by subsurface(). Like specular lobes, lobes with this flag must
+        /// have their f() and pdf() return zero.
+        const LOBE_SUBSURFACE   = 0b00100000;
     }
 }
 
@@ -70,13 +77,28 @@ pub trait Lobe : Display + Sync + Send {
     fn kind(&self) -> LobeKind {
         LobeKind::LOBE_DIFFUSE | LobeKind::LOBE_REFLECTION
     }
+
+    /// Approximate fraction of the light from the incoming direction that the lobe scatters.
+    /// Materials choose which lobe to sample in proportion to it, so it only needs to be cheap and
+    /// roughly right, but it should be positive wherever the lobe scatters light.
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        1.0
+    }
+
+    /// The interior medium for lobes with the LOBE_SUBSURFACE flag.
+    fn subsurface(&self) -> Option<util::SubsurfaceMedium> {
+        None
+    }
 }
 
 /// Implements diffuse, retro-reflection, and sheen for the Disney BRDF.
+/// On thin surfaces, the flatness blends the diffuse response toward an approximation of
+/// subsurface scattering (Hanrahan-Krueger), as in the 2015 Disney notes.
 pub struct DisneyDiffuseRefl {
     color: core::Vec,
     sheen_color: core::Vec,
     roughness: f32,
+    flatness: f32,
 }
 
 impl DisneyDiffuseRefl {
@@ -86,7 +108,17 @@ impl DisneyDiffuseRefl {
         let diffuse_color = &color * diffuse_weight;
         let sheen_color = (sheen * diffuse_weight) *
                 &core::Vec::one().lerp(&color.tint(), sheen_tint);
-        DisneyDiffuseRefl {color: diffuse_color, sheen_color: sheen_color, roughness: roughness}
+        DisneyDiffuseRefl {color: diffuse_color, sheen_color, roughness, flatness: 0.0}
+    }
+
+    pub fn new_thin(
+            color: core::Vec, roughness: f32, sheen: f32, sheen_tint: f32, diffuse_weight: f32,
+            flatness: f32)
+            -> DisneyDiffuseRefl
+    {
+        let mut lobe = DisneyDiffuseRefl::new(color, roughness, sheen, sheen_tint, diffuse_weight);
+        lobe.flatness = flatness;
+        lobe
     }
 }
 
@@ -111,8 +143,23 @@ impl Lobe for DisneyDiffuseRefl {
                 * (f_out + f_in + f_out * f_in * (r_r - 1.0)));
         let sheen = &self.sheen_color * util::fresnel_schlick_weight(cos_theta_d);
 
+        let diffuse = if self.flatness > 0.0 {
+            let f_ss90 = self.roughness * cos_theta_d * cos_theta_d;
+            let f_ss = core::lerp(1.0, f_ss90, f_in) * core::lerp(1.0, f_ss90, f_out);
+            let ss = 1.25 * (f_ss * (1.0 / (i.abs_cos_theta() + o.abs_cos_theta()) - 0.5) + 0.5);
+            let fake_subsurface = &self.color * (std::f32::consts::FRAC_1_PI * ss);
+            diffuse.lerp(&fake_subsurface, self.flatness)
+        }
+        else {
+            diffuse
+        };
+
         return &diffuse + &(&retro + &sheen);
     }
+
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        (&self.color + &self.sheen_color).luminance()
+    }
 }
 
 impl Display for DisneyDiffuseRefl {
@@ -127,12 +174,138 @@ pub struct StandardMicrofacetRefl<Dist: util::MicrofacetDistribution, Fr: util::
     microfacet: Dist,
     fresnel: Fr,
     color: core::Vec,
+    multiple_scattering: Option<MultipleScatteringRefl>,
 }
 
-impl<Dist, Fr> Lobe for StandardMicrofacetRefl<Dist, Fr>
+/// The energy that a single-scattering GGX reflection lobe loses to light bouncing between
+/// microfacets, added back as a separate, roughly diffuse term. This follows Kulla and Conty,
+/// "Revisiting Physically Based Shading at Imageworks" (SIGGRAPH 2017 course), where the term is
+///     f_ms(i, o) = F_ms (1 - E(i)) (1 - E(o)) / (pi (1 - E_avg))
+/// and E is the directional albedo of the lobe without Fresnel.
+struct MultipleScatteringRefl {
+    alpha: f32,
+    /// The average Fresnel reflectance after multiple bounces, times the lobe color.
+    color: core::Vec,
+    /// One over pi (1 - E_avg), which makes the term integrate to color (1 - E(i)).
+    norm: f32,
+}
+
+impl MultipleScatteringRefl {
+    fn new(alpha: f32, color: &core::Vec, fresnel_avg: &core::Vec)
+        -> Option<MultipleScatteringRefl>
+    {
+        let e_avg = ggx_albedo_table().average(alpha, 1.0);
+        if e_avg >= MAX_COMPENSATED_ALBEDO {
+            return None;
+        }
+
+        let mut f_ms = core::Vec::zero();
+        for c in 0..3 {
+            let f_avg = fresnel_avg[c];
+            f_ms[c] = f_avg * f_avg * e_avg / (1.0 - f_avg * (1.0 - e_avg));
+        }
+        Some(MultipleScatteringRefl {
+            alpha,
+            color: color.comp_mult(&f_ms),
+            norm: 1.0 / (std::f32::consts::PI * (1.0 - e_avg)),
+        })
+    }
+
+    fn f(&self, i: &core::Vec, o: &core::Vec) -> core::Vec {
+        let table = ggx_albedo_table();
+        let e_i = table.albedo(i.cos_theta(), self.alpha, 1.0);
+        let e_o = table.albedo(o.cos_theta(), self.alpha, 1.0);
+        &self.color * (self.norm * (1.0 - e_i) * (1.0 - e_o))
+    }
+
+    /// The fraction of the light from the incoming direction that the term scatters.
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        self.color.luminance() * (1.0 - ggx_albedo_table().albedo(i.cos_theta(), self.alpha, 1.0))
+    }
+}
+
+/// Lobes whose average albedo is at least this high don't need energy compensation.
+const MAX_COMPENSATED_ALBEDO: f32 = 0.999;
+
+/// The number of samples used to estimate each entry of an albedo table.
+const ALBEDO_TABLE_SAMPLES: usize = 512;
+
+/// Estimates the directional albedo of a lobe from the incoming direction with the given cosine.
+/// Light is traced from the light toward the camera, since transmission only conserves energy in
+/// that direction; toward the camera, radiance is scaled by the square of the relative eta.
+fn estimate_albedo(lobe: &dyn Lobe, cos_theta: f32, rng: &mut rand::XorShiftRng) -> f32 {
+    let i = core::Vec::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+    let mut sum = 0.0;
+    for _ in 0..ALBEDO_TABLE_SAMPLES {
+        let sample = lobe.sample_f(&i, false, rng);
+        if sample.pdf > 0.0 {
+            sum += sample.result.x * sample.outgoing.abs_cos_theta() / sample.pdf;
+        }
+    }
+    sum / ALBEDO_TABLE_SAMPLES as f32
+}
+
+/// Builds the albedo tables used for energy compensation, if they haven't been built yet. Stages
+/// call this when they're created, so that render threads don't stall on the tables mid-render.
+pub fn build_albedo_tables() {
+    ggx_albedo_table();
+    ggx_dielectric_albedo_table();
+}
+
+/// The directional albedo of single-scattering GGX reflection without Fresnel, by roughness.
+fn ggx_albedo_table() -> &'static energy::AlbedoTable {
+    static TABLE: OnceLock<energy::AlbedoTable> = OnceLock::new();
+    TABLE.get_or_init(|| energy::AlbedoTable::build(32, 32, 1, 1.0, |cos_theta, alpha, _, rng| {
+        let lobe = StandardMicrofacetRefl {
+            microfacet: util::GgxDistribution::new(alpha.sqrt(), 0.0),
+            fresnel: util::SchlickFresnel::new(core::Vec::one()),
+            color: core::Vec::one(),
+            multiple_scattering: None,
+        };
+        estimate_albedo(&lobe, cos_theta, rng)
+    }))
+}
+
+/// The directional albedo of a single-scattering rough dielectric interface (GGX reflection and
+/// transmission together), by roughness and by the relative index of refraction of the far side.
+fn ggx_dielectric_albedo_table() -> &'static energy::AlbedoTable {
+    static TABLE: OnceLock<energy::AlbedoTable> = OnceLock::new();
+    TABLE.get_or_init(|| energy::AlbedoTable::build(16, 16, 17, 3.0, |cos_theta, alpha, eta, rng| {
+        // Relative indices of refraction below one are seen from inside the surface.
+        let (ior, cos_theta) = if eta >= 1.0 { (eta, cos_theta) } else { (1.0 / eta, -cos_theta) };
+        let ior = f32::max(ior, 1.01);
+        let refl = StandardMicrofacetRefl {
+            microfacet: util::GgxDistribution::new(alpha.sqrt(), 0.0),
+            fresnel: util::DielectricFresnel::new(ior),
+            color: core::Vec::one(),
+            multiple_scattering: None,
+        };
+        let trans = DisneySpecularTrans {
+            microfacet: util::GgxDistribution::new(alpha.sqrt(), 0.0),
+            fresnel: util::DielectricFresnel::new(ior),
+            ior,
+            color: core::Vec::one(),
+            compensate: false,
+        };
+        estimate_albedo(&refl, cos_theta, rng) + estimate_albedo(&trans, cos_theta, rng)
+    }))
+}
+
+impl<Fr> StandardMicrofacetRefl<util::GgxDistribution, Fr> where Fr: util::Fresnel {
+    /// Creates a GGX reflection lobe that compensates for multiple scattering.
+    fn new_ggx(microfacet: util::GgxDistribution, fresnel: Fr, color: core::Vec)
+        -> StandardMicrofacetRefl<util::GgxDistribution, Fr>
+    {
+        let multiple_scattering = MultipleScatteringRefl::new(
+                microfacet.alpha(), &color, &fresnel.average());
+        StandardMicrofacetRefl {microfacet, fresnel, color, multiple_scattering}
+    }
+}
+
+impl<Dist, Fr> StandardMicrofacetRefl<Dist, Fr>
     where Dist: util::MicrofacetDistribution, Fr: util::Fresnel
 {
-    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+    fn f_single(&self, i: &core::Vec, o: &core::Vec) -> core::Vec {
         let cos_theta_in = i.abs_cos_theta();
         let cos_theta_out = o.abs_cos_theta();
         let half_unnorm = i + o;
@@ -140,44 +313,80 @@ impl<Dist, Fr> Lobe for StandardMicrofacetRefl<Dist, Fr>
             return core::Vec::zero();
         }
 
+        // Orient the half vector outward, so that the Fresnel term knows which side we're on.
         let half = half_unnorm.normalized();
-        let fresnel = self.fresnel.fresnel(o.dot(&half));
+        let cos_theta_half = if half.z < 0.0 { -o.dot(&half) } else { o.dot(&half) };
+        let fresnel = self.fresnel.fresnel(cos_theta_half);
         let d = self.microfacet.d(&half);
         let g = self.microfacet.g(i, o);
         &self.color.comp_mult(&fresnel) * (d * g / (4.0 * cos_theta_out * cos_theta_in))
     }
 
+    /// The probability of sampling the multiple-scattering term instead of the microfacets.
+    fn multiple_scattering_probability(&self, i: &core::Vec) -> f32 {
+        match self.multiple_scattering {
+            Some(ref multiple_scattering) => {
+                let multiple = multiple_scattering.albedo(i);
+                let single = self.color.comp_mult(
+                        &self.fresnel.fresnel(i.abs_cos_theta())).luminance();
+                if multiple + single > 0.0 { multiple / (multiple + single) } else { 0.0 }
+            },
+            None => 0.0
+        }
+    }
+}
+
+impl<Dist, Fr> Lobe for StandardMicrofacetRefl<Dist, Fr>
+    where Dist: util::MicrofacetDistribution, Fr: util::Fresnel
+{
+    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+        let single = self.f_single(i, o);
+        match self.multiple_scattering {
+            Some(ref multiple_scattering) if i.is_local_same_hemisphere(o) =>
+                    &single + &multiple_scattering.f(i, o),
+            _ => single
+        }
+    }
+
     fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
         if !i.is_local_same_hemisphere(o) {
             0.0
         }
         else {
             let half = (i + o).normalized();
-            self.microfacet.pdf(i, &half) / (4.0 * i.dot(&half))
+            let single_pdf = self.microfacet.pdf(i, &half) / (4.0 * i.dot(&half));
+            let p = self.multiple_scattering_probability(i);
+            core::lerp(single_pdf, core::CosineSampleHemisphere::pdf(o), p)
         }
     }
 
     fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
         -> LobeSample
     {
-        // Sample microfacet orientation (half) and reflected direction (o).
+        // Sample microfacet orientation (half) and reflected direction (o), or sample the
+        // multiple-scattering term like a diffuse lobe.
         if i.z == 0.0 {
             LobeSample::zero()
         }
         else {
-            let half = self.microfacet.sample_half(i, rng);
-            let o = i.reflect(&half);
+            let p = self.multiple_scattering_probability(i);
+            let o = if p > 0.0 && rng.next_f32() < p {
+                let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: i.z < 0.0};
+                cosine_sample_hemis.ind_sample(rng)
+            }
+            else {
+                let half = self.microfacet.sample_half(i, rng);
+                i.reflect(&half)
+            };
+
             if !i.is_local_same_hemisphere(&o) {
                 LobeSample::zero()
             }
             else {
-                // Compute PDF of outoing vector for microfacet reflection.
-                let result = self.f(i, &o, camera_to_light);
-                let pdf = self.microfacet.pdf(i, &half) / (4.0 * i.dot(&half));
                 LobeSample {
-                    result: result,
+                    result: self.f(i, &o, camera_to_light),
                     outgoing: o,
-                    pdf: pdf
+                    pdf: self.pdf(i, &o)
                 }
             }
         }
@@ -186,6 +395,14 @@ impl<Dist, Fr> Lobe for StandardMicrofacetRefl<Dist, Fr>
     fn kind(&self) -> LobeKind {
         LobeKind::LOBE_GLOSSY | LobeKind::LOBE_REFLECTION
     }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let single = self.color.comp_mult(&self.fresnel.fresnel(i.abs_cos_theta())).luminance();
+        match self.multiple_scattering {
+            Some(ref multiple_scattering) => single + multiple_scattering.albedo(i),
+            None => single
+        }
+    }
 }
 
 impl<Dist, Fr> Display for StandardMicrofacetRefl<Dist, Fr>
@@ -214,14 +431,67 @@ impl DisneySpecularRefl {
             specular_tint: f32, metallic: f32)
             -> StandardMicrofacetRefl<util::GgxDistribution, util::DisneyFresnel>
     {
-        // Note: The color will be computed by the DisneyFresnel, so we just set it to white on the
-        // lobe itself.
+        DisneySpecularRefl::new_weighted(
+                color, roughness, anisotropic, ior, specular_tint, metallic, 1.0)
+    }
+
+    /// Creates the lobe scaled by the given weight, for when another lobe takes over part of the
+    /// specular reflection.
+    pub fn new_weighted(
+            color: core::Vec, roughness: f32, anisotropic: f32, ior: f32,
+            specular_tint: f32, metallic: f32, weight: f32)
+            -> StandardMicrofacetRefl<util::GgxDistribution, util::DisneyFresnel>
+    {
+        // Note: The color will be computed by the DisneyFresnel, so we just set it to the weight
+        // on the lobe itself.
         let ior_adjusted = f32::max(ior, 1.01);
-        StandardMicrofacetRefl {
-            microfacet: util::GgxDistribution::new(roughness, anisotropic),
-            fresnel: util::DisneyFresnel::new(ior_adjusted, color, specular_tint, metallic),
-            color: core::Vec::one(),
-        }
+        StandardMicrofacetRefl::new_ggx(
+                util::GgxDistribution::new(roughness, anisotropic),
+                util::DisneyFresnel::new(ior_adjusted, color, specular_tint, metallic),
+                weight * &core::Vec::one())
+    }
+}
+
+/// Rough reflection from a conductor, using the GGX microfacet distribution and the conductor
+/// Fresnel equations.
+pub type ConductorRefl = StandardMicrofacetRefl<util::GgxDistribution, util::ConductorFresnel>;
+
+impl ConductorRefl {
+    pub fn new(fresnel: util::ConductorFresnel, roughness: f32) -> ConductorRefl {
+        ConductorRefl::new_aniso(fresnel, roughness, 0.0)
+    }
+
+    pub fn new_aniso(fresnel: util::ConductorFresnel, roughness: f32, anisotropic: f32)
+        -> ConductorRefl
+    {
+        StandardMicrofacetRefl::new_ggx(
+                util::GgxDistribution::new(roughness, anisotropic), fresnel, core::Vec::one())
+    }
+}
+
+/// Rough reflection from a dielectric, using the GGX microfacet distribution and the dielectric
+/// Fresnel equations.
+pub type DielectricRefl = StandardMicrofacetRefl<util::GgxDistribution, util::DielectricFresnel>;
+
+impl DielectricRefl {
+    pub fn new(roughness: f32, ior: f32) -> DielectricRefl {
+        StandardMicrofacetRefl::new_ggx(
+                util::GgxDistribution::new(roughness, 0.0),
+                util::DielectricFresnel::new(ior),
+                core::Vec::one())
+    }
+}
+
+/// A GGX reflection lobe whose Fresnel term includes interference in a thin film, e.g. for
+/// soap bubbles, oil slicks, and anodized metals.
+pub type ThinFilmRefl = StandardMicrofacetRefl<util::GgxDistribution, util::ThinFilmFresnel>;
+
+impl ThinFilmRefl {
+    pub fn new_aniso(fresnel: util::ThinFilmFresnel, roughness: f32, anisotropic: f32)
+        -> ThinFilmRefl
+    {
+        StandardMicrofacetRefl::new_ggx(
+                util::GgxDistribution::new(roughness, anisotropic), fresnel, core::Vec::one())
     }
 }
 
@@ -238,16 +508,21 @@ impl DisneyClearcoatRefl {
             microfacet: util::Gtr1Distribution::new(clearcoat_gloss),
             fresnel: util::SchlickFresnel {r0: 0.04 * &core::Vec::one()},
             color: (0.25 * clearcoat) * &core::Vec::one(),
+            multiple_scattering: None,
         }
     }
 }
 
 /// This implementation is derived from the MicrofacetTransmission in PBRT 3e.
+/// Light lost to multiple scattering between the microfacets is added back as a roughly diffuse
+/// transmission term, so that the lobe and a matching GGX reflection lobe together have an albedo
+/// of about one, in the style of Kulla and Conty (see MultipleScatteringRefl).
 pub struct DisneySpecularTrans {
     microfacet: util::GgxDistribution,
     fresnel: util::DielectricFresnel,
     ior: f32,
     color: core::Vec,
+    compensate: bool,
 }
 
 impl DisneySpecularTrans {
@@ -264,12 +539,13 @@ impl DisneySpecularTrans {
             fresnel: util::DielectricFresnel::new(ior_adjusted),
             ior: ior_adjusted,
             color: color,
+            compensate: true,
         }
     }
 }
 
-impl Lobe for DisneySpecularTrans {
-    fn f(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
+impl DisneySpecularTrans {
+    fn f_single(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
         // This is defined for transmission only.
         if i.is_local_same_hemisphere(&o) {
             return core::Vec::zero();
@@ -302,6 +578,12 @@ impl Lobe for DisneySpecularTrans {
         debug_assert!(o.is_finite());
         debug_assert!(half.is_finite(), "{} {} {}", half_unnorm, half, self.ior);
 
+        // Both directions must be on the same side of the microfacet as their own hemisphere;
+        // sampling only sees microfacets that face the incoming direction.
+        if i.dot(&half) * cos_theta_in <= 0.0 || o.dot(&half) * cos_theta_out <= 0.0 {
+            return core::Vec::zero();
+        }
+
         let fresnel = self.fresnel.fresnel(o.dot(&half));
         let d = self.microfacet.d(&half);
         let g = self.microfacet.g(i, o);
@@ -318,7 +600,7 @@ impl Lobe for DisneySpecularTrans {
         return res;
     }
 
-    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+    fn pdf_single(&self, i: &core::Vec, o: &core::Vec) -> f32 {
         if i.is_local_same_hemisphere(&o) {
             0.0
         }
@@ -335,11 +617,14 @@ impl Lobe for DisneySpecularTrans {
             // Compute half from i and o for microfacet transmission.
             let half_unnorm = i + &(o * eta);
             let half = if half_unnorm.z > 0.0 {
-                half_unnorm
+                half_unnorm.normalized()
             }
             else {
                 -&half_unnorm.normalized()
             };
+            if i.dot(&half) * i.cos_theta() <= 0.0 || o.dot(&half) * o.cos_theta() <= 0.0 {
+                return 0.0;
+            }
 
             // Compute change of variables for microfacet transmission.
             let sqrt_denom = i.dot(&half) + eta * o.dot(&half);
@@ -348,25 +633,92 @@ impl Lobe for DisneySpecularTrans {
         }
     }
 
+    /// Returns the relative index of refraction across the surface from the given direction.
+    fn eta(&self, w: &core::Vec) -> f32 {
+        if w.cos_theta() > 0.0 { self.ior } else { 1.0 / self.ior }
+    }
+
+    /// The multiple-scattering term, from the camera direction to the light direction. It isn't
+    /// symmetric, so the two directions can't be swapped. It's normalized over the camera side,
+    /// so that light arriving from the light direction is scattered with an albedo of 1 - E.
+    fn f_multiple(&self, camera_dir: &core::Vec, light_dir: &core::Vec) -> core::Vec {
+        let table = ggx_dielectric_albedo_table();
+        let alpha = self.microfacet.alpha();
+        let eta_camera = self.eta(camera_dir);
+        let e_avg = table.average(alpha, eta_camera);
+        if e_avg >= MAX_COMPENSATED_ALBEDO {
+            return core::Vec::zero();
+        }
+
+        let e_camera = table.albedo(camera_dir.cos_theta(), alpha, eta_camera);
+        let e_light = table.albedo(light_dir.cos_theta(), alpha, self.eta(light_dir));
+        &self.color * ((1.0 - e_camera) * (1.0 - e_light) / (std::f32::consts::PI * (1.0 - e_avg)))
+    }
+
+    /// The fraction of the light from the incoming direction that the multiple-scattering term
+    /// scatters, relative to the whole lobe.
+    fn multiple_scattering_probability(&self, i: &core::Vec) -> f32 {
+        if !self.compensate {
+            return 0.0;
+        }
+
+        let multiple = 1.0 - ggx_dielectric_albedo_table().albedo(
+                i.cos_theta(), self.microfacet.alpha(), self.eta(i));
+        let single = 1.0 - self.fresnel.fresnel(i.cos_theta()).x;
+        if multiple + single > 0.0 { multiple / (multiple + single) } else { 0.0 }
+    }
+}
+
+impl Lobe for DisneySpecularTrans {
+    fn f(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
+        let single = self.f_single(i, o, camera_to_light);
+        if !self.compensate || i.is_local_same_hemisphere(o) {
+            single
+        }
+        else if camera_to_light {
+            &single + &self.f_multiple(i, o)
+        }
+        else {
+            &single + &self.f_multiple(o, i)
+        }
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        if i.is_local_same_hemisphere(o) {
+            0.0
+        }
+        else {
+            let p = self.multiple_scattering_probability(i);
+            core::lerp(self.pdf_single(i, o), core::CosineSampleHemisphere::pdf(o), p)
+        }
+    }
+
     fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
         -> LobeSample
     {
-        // Sample microfacet orientation (half) and reflected direction (o).
+        // Sample microfacet orientation (half) and refracted direction (o), or sample the
+        // multiple-scattering term like a diffuse transmission lobe.
         if i.z == 0.0 {
             LobeSample::zero()
         }
         else {
-            let half = self.microfacet.sample_half(i, rng);
-            let eta = if i.cos_theta() > 0.0 {
-                // Entering.
-                1.0 / self.ior
+            let p = self.multiple_scattering_probability(i);
+            let o = if p > 0.0 && rng.next_f32() < p {
+                let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: i.z > 0.0};
+                cosine_sample_hemis.ind_sample(rng)
             }
             else {
-                // Exiting.
-                self.ior
+                let half = self.microfacet.sample_half(i, rng);
+                let eta = if i.cos_theta() > 0.0 {
+                    // Entering.
+                    1.0 / self.ior
+                }
+                else {
+                    // Exiting.
+                    self.ior
+                };
+                i.refract(&half, eta)
             };
-
-            let o = i.refract(&half, eta);
             debug_assert!(o.is_finite());
 
             if o.is_exactly_zero() {
@@ -390,6 +742,19 @@ impl Lobe for DisneySpecularTrans {
     fn kind(&self) -> LobeKind {
        LobeKind:: LOBE_GLOSSY | LobeKind::LOBE_TRANSMISSION
     }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let fresnel = self.fresnel.fresnel(i.cos_theta());
+        let single = self.color.comp_mult(&(&core::Vec::one() - &fresnel)).luminance();
+        if self.compensate {
+            let multiple = 1.0 - ggx_dielectric_albedo_table().albedo(
+                    i.cos_theta(), self.microfacet.alpha(), self.eta(i));
+            single + self.color.luminance() * multiple
+        }
+        else {
+            single
+        }
+    }
 }
 
 impl Display for DisneySpecularTrans {
@@ -398,24 +763,415 @@ impl Display for DisneySpecularTrans {
     }
 }
 
+/// A perfectly smooth dielectric interface that either reflects or refracts, chosen
+/// stochastically by the Fresnel term. The reflected and transmitted light can be tinted
+/// separately; a black reflection color makes the lobe transmission-only, e.g. when another lobe
+/// already accounts for specular reflection.
+pub struct SmoothDielectric {
+    reflect_color: core::Vec,
+    trans_color: core::Vec,
+    ior: f32,
+}
+
+impl SmoothDielectric {
+    pub fn new(reflect_color: core::Vec, trans_color: core::Vec, ior: f32) -> SmoothDielectric {
+        SmoothDielectric {reflect_color, trans_color, ior}
+    }
+}
+
+impl Lobe for SmoothDielectric {
+    fn f(&self, _: &core::Vec, _: &core::Vec, _: bool) -> core::Vec {
+        core::Vec::zero()
+    }
+
+    fn pdf(&self, _: &core::Vec, _: &core::Vec) -> f32 {
+        0.0
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> LobeSample
+    {
+        if i.z == 0.0 {
+            return LobeSample::zero();
+        }
+
+        let fresnel = util::fresnel_dielectric(i.cos_theta(), self.ior);
+        let reflect = &self.reflect_color * fresnel;
+        let trans = &self.trans_color * (1.0 - fresnel);
+        let reflect_weight = reflect.luminance();
+        let trans_weight = trans.luminance();
+        if reflect_weight + trans_weight <= 0.0 {
+            return LobeSample::zero();
+        }
+
+        // Choose between reflection and refraction in proportion to their contributions. The
+        // choice probability is divided out of the result, so that the pdf is one per the
+        // LOBE_SPECULAR contract.
+        let reflect_prob = reflect_weight / (reflect_weight + trans_weight);
+        if rng.next_f32() < reflect_prob {
+            let o = core::Vec::new(-i.x, -i.y, i.z);
+            LobeSample {
+                result: &reflect / (reflect_prob * o.abs_cos_theta()),
+                outgoing: o,
+                pdf: 1.0
+            }
+        }
+        else {
+            let (eta_i, eta_t, normal) = if i.cos_theta() > 0.0 {
+                // Entering.
+                (1.0, self.ior, core::Vec::z_axis())
+            }
+            else {
+                // Exiting.
+                (self.ior, 1.0, -&core::Vec::z_axis())
+            };
+
+            let o = i.refract(&normal, eta_i / eta_t);
+            if o.is_exactly_zero() {
+                return LobeSample::zero();
+            }
+
+            // Radiance is compressed into a smaller solid angle when entering a denser medium, so
+            // it scales by (eta_i / eta_t)^2 when tracing from the camera. Importance is not
+            // scaled, so the adjoint BSDF is used when tracing from lights.
+            let factor = if camera_to_light { (eta_i * eta_i) / (eta_t * eta_t) } else { 1.0 };
+            LobeSample {
+                result: &trans * (factor / ((1.0 - reflect_prob) * o.abs_cos_theta())),
+                outgoing: o,
+                pdf: 1.0
+            }
+        }
+    }
+
+    fn kind(&self) -> LobeKind {
+        LobeKind::LOBE_SPECULAR | LobeKind::LOBE_REFLECTION | LobeKind::LOBE_TRANSMISSION
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let fresnel = util::fresnel_dielectric(i.cos_theta(), self.ior);
+        self.reflect_color.luminance() * fresnel + self.trans_color.luminance() * (1.0 - fresnel)
+    }
+}
+
+impl Display for SmoothDielectric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SmoothDielectric(reflect_color={}, trans_color={}, ior={})",
+                self.reflect_color, self.trans_color, self.ior)
+    }
+}
+
+/// Specular transmission through a thin-walled dielectric, like a sheet of glass or a soap film.
+/// Light exits the far side of the wall in the same medium, so it isn't bent. The transmitted
+/// direction mirrors the microfacet reflection direction through the surface.
+pub struct DisneyThinTrans {
+    microfacet: util::GgxDistribution,
+    ior: f32,
+    color: core::Vec,
+    smooth: bool,
+}
+
+impl DisneyThinTrans {
+    pub fn new(color: core::Vec, roughness: f32, ior: f32) -> DisneyThinTrans {
+        DisneyThinTrans::new_aniso(color, roughness, 0.0, ior)
+    }
+
+    pub fn new_aniso(color: core::Vec, roughness: f32, anisotropic: f32, ior: f32)
+        -> DisneyThinTrans
+    {
+        // The roughness is scaled to account for the wall's two interfaces, per the 2015 notes.
+        let scaled_roughness = core::clamp_unit((0.65 * ior - 0.35) * roughness);
+        DisneyThinTrans {
+            microfacet: util::GgxDistribution::new(scaled_roughness, anisotropic),
+            ior,
+            color,
+            smooth: scaled_roughness == 0.0,
+        }
+    }
+}
+
+impl Lobe for DisneyThinTrans {
+    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+        if self.smooth || i.is_local_same_hemisphere(o) {
+            return core::Vec::zero();
+        }
+
+        let cos_theta_in = i.abs_cos_theta();
+        let cos_theta_out = o.abs_cos_theta();
+        let mirrored = core::Vec::new(o.x, o.y, -o.z);
+        let half_unnorm = i + &mirrored;
+        if half_unnorm.is_exactly_zero() || cos_theta_in == 0.0 || cos_theta_out == 0.0 {
+            return core::Vec::zero();
+        }
+
+        let half = half_unnorm.normalized();
+        let fresnel = util::fresnel_dielectric(f32::abs(i.dot(&half)), self.ior);
+        let d = self.microfacet.d(&half);
+        let g = self.microfacet.g(i, &mirrored);
+        &self.color * ((1.0 - fresnel) * d * g / (4.0 * cos_theta_out * cos_theta_in))
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        if self.smooth || i.is_local_same_hemisphere(o) {
+            0.0
+        }
+        else {
+            let half = (i + &core::Vec::new(o.x, o.y, -o.z)).normalized();
+            self.microfacet.pdf(i, &half) / (4.0 * f32::abs(i.dot(&half)))
+        }
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> LobeSample
+    {
+        if i.z == 0.0 {
+            return LobeSample::zero();
+        }
+
+        if self.smooth {
+            // Pass straight through the wall.
+            let o = -i;
+            let fresnel = util::fresnel_dielectric(i.abs_cos_theta(), self.ior);
+            return LobeSample {
+                result: &self.color * ((1.0 - fresnel) / o.abs_cos_theta()),
+                outgoing: o,
+                pdf: 1.0
+            };
+        }
+
+        let half = self.microfacet.sample_half(i, rng);
+        let mirrored = i.reflect(&half);
+        if !i.is_local_same_hemisphere(&mirrored) {
+            return LobeSample::zero();
+        }
+
+        let o = core::Vec::new(mirrored.x, mirrored.y, -mirrored.z);
+        LobeSample {
+            result: self.f(i, &o, camera_to_light),
+            outgoing: o,
+            pdf: self.pdf(i, &o)
+        }
+    }
+
+    fn kind(&self) -> LobeKind {
+        if self.smooth {
+            LobeKind::LOBE_SPECULAR | LobeKind::LOBE_TRANSMISSION
+        }
+        else {
+            LobeKind::LOBE_GLOSSY | LobeKind::LOBE_TRANSMISSION
+        }
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let fresnel = util::fresnel_dielectric(i.abs_cos_theta(), self.ior);
+        self.color.luminance() * (1.0 - fresnel)
+    }
+}
+
+impl Display for DisneyThinTrans {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DisneyThinTrans(color={}, ior={})", self.color, self.ior)
+    }
+}
+
+/// Enters the surface of a subsurface-scattering material. The direction into the surface is
+/// cosine-distributed; the integrator then walks through the interior medium until the path leaves
+/// the surface again.
+pub struct DisneySubsurface {
+    weight: core::Vec,
+    medium: util::SubsurfaceMedium,
+}
+
+impl DisneySubsurface {
+    pub fn new(weight: core::Vec, medium: util::SubsurfaceMedium) -> DisneySubsurface {
+        DisneySubsurface {weight, medium}
+    }
+}
+
+impl Lobe for DisneySubsurface {
+    fn f(&self, _: &core::Vec, _: &core::Vec, _: bool) -> core::Vec {
+        core::Vec::zero()
+    }
+
+    fn pdf(&self, _: &core::Vec, _: &core::Vec) -> f32 {
+        0.0
+    }
+
+    fn sample_f(&self, i: &core::Vec, _: bool, rng: &mut rand::XorShiftRng) -> LobeSample {
+        if i.z == 0.0 {
+            return LobeSample::zero();
+        }
+
+        // The cosine and pdf cancel out, so the pdf is one as for specular lobes.
+        let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: i.z > 0.0};
+        let o = cosine_sample_hemis.ind_sample(rng);
+        LobeSample {
+            result: &self.weight / o.abs_cos_theta(),
+            outgoing: o,
+            pdf: 1.0
+        }
+    }
+
+    fn kind(&self) -> LobeKind {
+        LobeKind::LOBE_SUBSURFACE | LobeKind::LOBE_TRANSMISSION
+    }
+
+    fn subsurface(&self) -> Option<util::SubsurfaceMedium> {
+        Some(self.medium)
+    }
+
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        self.weight.luminance()
+    }
+}
+
+impl Display for DisneySubsurface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DisneySubsurface(weight={}, sigma_t={}, albedo={})",
+                self.weight, self.medium.sigma_t, self.medium.albedo)
+    }
+}
+
+/// Lambertian transmission; scatters light into the hemisphere opposite the incoming direction.
+pub struct DiffuseTrans {
+    color: core::Vec,
+}
+
+impl DiffuseTrans {
+    pub fn new(color: core::Vec) -> DiffuseTrans {
+        DiffuseTrans {color}
+    }
+}
+
+impl Lobe for DiffuseTrans {
+    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+        if i.is_local_same_hemisphere(o) {
+            core::Vec::zero()
+        }
+        else {
+            &self.color * std::f32::consts::FRAC_1_PI
+        }
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        if i.is_local_same_hemisphere(o) {
+            0.0
+        }
+        else {
+            core::CosineSampleHemisphere::pdf(o)
+        }
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> LobeSample
+    {
+        let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: i.z > 0.0};
+        let o = cosine_sample_hemis.ind_sample(rng);
+        LobeSample {
+            result: self.f(i, &o, camera_to_light),
+            outgoing: o,
+            pdf: self.pdf(i, &o)
+        }
+    }
+
+    fn kind(&self) -> LobeKind {
+        LobeKind::LOBE_DIFFUSE | LobeKind::LOBE_TRANSMISSION
+    }
+
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        self.color.luminance()
+    }
+}
+
+impl Display for DiffuseTrans {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DiffuseTrans(color={})", self.color)
+    }
+}
+
+/// Lambertian reflection.
 pub struct PerfectDiffuse {
+    color: core::Vec,
 }
 
 impl PerfectDiffuse {
-    pub fn new() -> PerfectDiffuse {
-        PerfectDiffuse {}
+    pub fn new(color: core::Vec) -> PerfectDiffuse {
+        PerfectDiffuse {color}
     }
 }
 
 impl Lobe for PerfectDiffuse {
     fn f(&self, _: &core::Vec, _: &core::Vec, _: bool) -> core::Vec {
-        &core::Vec::new(0.5, 0.5, 0.5) * std::f32::consts::FRAC_1_PI
+        &self.color * std::f32::consts::FRAC_1_PI
+    }
+
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        self.color.luminance()
     }
 }
 
 impl Display for PerfectDiffuse {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "PerfectDiffuse")
+        write!(f, "PerfectDiffuse(color={})", self.color)
+    }
+}
+
+/// Diffuse reflection from a rough surface made of Lambertian microfacets, which reflects more
+/// light back toward the incoming direction than a Lambertian surface does.
+/// This is the qualitative model from Oren and Nayar's 1994 SIGGRAPH paper, as formulated in
+/// PBRT, 3rd edition, section 8.4.2 (around page 534).
+pub struct OrenNayar {
+    color: core::Vec,
+    sigma: f32,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    /// The sigma is the standard deviation of the microfacet orientation angle, in degrees.
+    /// With a sigma of zero, the lobe is Lambertian.
+    pub fn new(color: core::Vec, sigma: f32) -> OrenNayar {
+        let sigma_rad = sigma.to_radians();
+        let sigma2 = sigma_rad * sigma_rad;
+        let a = 1.0 - (sigma2 / (2.0 * (sigma2 + 0.33)));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        OrenNayar {color, sigma, a, b}
+    }
+}
+
+impl Lobe for OrenNayar {
+    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+        let sin_theta_i = i.sin_theta();
+        let sin_theta_o = o.sin_theta();
+
+        // Compute the cosine term of the Oren-Nayar model.
+        let max_cos = if sin_theta_i > 1.0e-4 && sin_theta_o > 1.0e-4 {
+            let d_cos = i.cos_phi() * o.cos_phi() + i.sin_phi() * o.sin_phi();
+            f32::max(0.0, d_cos)
+        }
+        else {
+            0.0
+        };
+
+        // Compute the sine and tangent terms of the Oren-Nayar model.
+        let (sin_alpha, tan_beta) = if i.abs_cos_theta() > o.abs_cos_theta() {
+            (sin_theta_o, sin_theta_i / i.abs_cos_theta())
+        }
+        else {
+            (sin_theta_i, sin_theta_o / o.abs_cos_theta())
+        };
+
+        &self.color * (std::f32::consts::FRAC_1_PI *
+                (self.a + self.b * max_cos * sin_alpha * tan_beta))
+    }
+
+    fn albedo(&self, _: &core::Vec) -> f32 {
+        self.color.luminance()
+    }
+}
+
+impl Display for OrenNayar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OrenNayar(color={}, sigma={})", self.color, self.sigma)
     }
 }
 
@@ -429,25 +1185,20 @@ impl PerfectMirror {
 }
 
 impl Lobe for PerfectMirror {
-    fn f(&self, _: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
-        &core::Vec::one() / o.abs_cos_theta()
+    fn f(&self, _: &core::Vec, _: &core::Vec, _: bool) -> core::Vec {
+        core::Vec::zero()
     }
 
     fn pdf(&self, _: &core::Vec, _: &core::Vec) -> f32 {
-        1.0
+        0.0
     }
 
-    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, _: &mut rand::XorShiftRng)
-        -> LobeSample
-    {
+    fn sample_f(&self, i: &core::Vec, _: bool, _: &mut rand::XorShiftRng) -> LobeSample {
         let o = core::Vec::new(-i.x, -i.y, i.z);
-        let result = self.f(i, &o, camera_to_light);
-        let pdf = self.pdf(i, &o);
-
         LobeSample {
-            result: result,
+            result: &core::Vec::one() / o.abs_cos_theta(),
             outgoing: o,
-            pdf: pdf
+            pdf: 1.0
         }
     }
 
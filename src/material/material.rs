@@ -1,44 +1,110 @@
+use material::hair;
+use material::ies;
 use material::lights;
 use material::lobes;
+use material::merl;
+use material::shaders;
+use material::textures;
+use material::util;
 
 use core;
 use geom;
 
 use std;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
 use rand;
+use rand::Rng;
 use rand::distributions::IndependentSample;
 use rand::distributions::range::Range;
 
+/// The default index of refraction of thin-film coatings, that of a soap film.
+const DEFAULT_THIN_FILM_IOR: f32 = 1.33;
+
 pub struct MaterialSample {
     pub emission: core::Vec,
     pub radiance: core::Vec,
     pub outgoing: core::Vec,
     pub pdf: f32,
     pub kind: lobes::LobeKind,
+    /// The interior medium to walk through, if the sampled lobe entered the surface.
+    pub subsurface: Option<util::SubsurfaceMedium>,
+}
+
+/// A lobe of another material. The lobes of constant materials are shared with the material
+/// rather than rebuilt.
+pub enum SharedLobe {
+    Shared(Arc<Material>, usize),
+    Owned(Box<dyn lobes::Lobe>),
+}
+
+impl Deref for SharedLobe {
+    type Target = dyn lobes::Lobe;
+
+    fn deref(&self) -> &Self::Target {
+        match *self {
+            SharedLobe::Shared(ref material, index) => &*material.lobes[index],
+            SharedLobe::Owned(ref lobe) => &**lobe
+        }
+    }
 }
 
 pub struct Material {
     display: core::Vec,
     light: Option<Box<lights::Light>>,
-    lobes: std::vec::Vec<Box<lobes::Lobe>>
+    /// Lobes shared by all shading points; only used if there's no shader.
+    lobes: std::vec::Vec<Box<dyn lobes::Lobe>>,
+    /// Builds the lobes at each shading point, if they vary over the surface.
+    shader: Option<Box<dyn shaders::Shader>>,
+    normal_map: Option<textures::NormalMap>,
+    /// Opacity mask; surfaces without one are fully opaque.
+    opacity: Option<textures::MaterialParam<f32>>,
 }
 
 impl Material {
     pub fn diffuse_light(incandescence: core::Vec) -> Material {
         Material {
             display: incandescence,
-            light: Some(Box::new(lights::DiffuseAreaLight {color: incandescence})),
-            lobes: vec![]
+            light: Some(Box::new(lights::DiffuseAreaLight::new(incandescence))),
+            lobes: vec![],
+            shader: None,
+            normal_map: None,
+            opacity: None,
         }
     }
 
-    pub fn diffuse() -> Material {
-        Material {
+    /// The material at the point where a subsurface random walk leaves the surface. Light from
+    /// the interior is transmitted diffusely out of the surface.
+    pub fn subsurface_exit() -> &'static Material {
+        static SUBSURFACE_EXIT: OnceLock<Material> = OnceLock::new();
+        SUBSURFACE_EXIT.get_or_init(|| Material {
             display: core::Vec::one(),
             light: None,
+            lobes: vec![Box::new(lobes::DiffuseTrans::new(core::Vec::one()))],
+            shader: None,
+            normal_map: None,
+            opacity: None,
+        })
+    }
+
+    /// Generates a builder to construct an area light with optional two-sided emission,
+    /// texture, and IES profile.
+    /// You'll need to call build() on the builder to finish building.
+    pub fn area_light() -> AreaLightBuilder {
+        AreaLightBuilder::new()
+    }
+
+    /// Creates a Lambertian material with the given reflectance.
+    pub fn diffuse(color: core::Vec) -> Material {
+        Material {
+            display: color,
+            light: None,
             lobes: vec![
-                Box::new(lobes::PerfectDiffuse::new())
-            ]
+                Box::new(lobes::PerfectDiffuse::new(color))
+            ],
+            shader: None,
+            normal_map: None,
+            opacity: None,
         }
     }
 
@@ -48,27 +114,317 @@ impl Material {
             light: None,
             lobes: vec![
                 Box::new(lobes::PerfectMirror::new())
-            ]
+            ],
+            shader: None,
+            normal_map: None,
+            opacity: None,
         }
     }
 
+    /// Creates a material that blends between the lobes of two materials by a mask, which is zero
+    /// for material a and one for material b. The lights and normal maps of the blended materials
+    /// are ignored.
+    pub fn mix(a: Arc<Material>, b: Arc<Material>, mask: textures::MaterialParam<f32>)
+        -> Material
+    {
+        let display = a.display.lerp(
+                &b.display, core::clamp_unit(mask.eval(&geom::SurfaceProperties::zero())));
+        Material::from_shader(display, Box::new(shaders::MixShader::new(a, b, mask)))
+    }
+
+    /// Generates a builder to construct a material with a dielectric coating over the lobes of a
+    /// base material.
+    /// You'll need to call build() on the builder to finish building.
+    pub fn layered(base: Arc<Material>) -> LayeredMaterialBuilder {
+        LayeredMaterialBuilder::new(base)
+    }
+
+    /// Generates a builder to construct a textured Lambertian or Oren-Nayar diffuse material.
+    /// You'll need to call build() on the builder to finish building.
+    pub fn matte() -> MatteMaterialBuilder {
+        MatteMaterialBuilder::new()
+    }
+
+    /// Creates a material that reflects light according to a measured BRDF.
+    pub fn measured(brdf: Arc<merl::MerlBrdf>) -> Material {
+        Material {
+            display: *brdf.normal_albedo(),
+            light: None,
+            lobes: vec![
+                Box::new(merl::MeasuredRefl::new(brdf))
+            ],
+            shader: None,
+            normal_map: None,
+            opacity: None,
+        }
+    }
+
+    /// Generates a builder to construct a hair or fur material.
+    /// You'll need to call build() on the builder to finish building.
+    pub fn hair() -> HairMaterialBuilder {
+        HairMaterialBuilder::new()
+    }
+
+    /// Generates a builder to construct a rough conductor (metal) material.
+    /// You'll need to call build() on the builder to finish building.
+    pub fn conductor() -> ConductorMaterialBuilder {
+        ConductorMaterialBuilder::new()
+    }
+
     /// Generates a builder to construct a Disney principled material.
     /// You'll need to call build() on the builder to finish building.
     pub fn disney() -> DisneyMaterialBuilder {
         DisneyMaterialBuilder::new()
     }
 
+    /// Creates a material whose lobes are built by the given shader. If the shader is constant,
+    /// the lobes are built once up front.
+    pub fn from_shader(display: core::Vec, shader: Box<dyn shaders::Shader>) -> Material {
+        if shader.is_constant() {
+            Material {
+                display,
+                light: None,
+                lobes: shader.lobes(&geom::SurfaceProperties::zero()),
+                shader: None,
+                normal_map: None,
+                opacity: None,
+            }
+        }
+        else {
+            Material {
+                display,
+                light: None,
+                lobes: vec![],
+                shader: Some(shader),
+                normal_map: None,
+                opacity: None,
+            }
+        }
+    }
+
     pub fn display_color(&self) -> &core::Vec {
         &self.display
     }
 
+    /// Sets a normal or bump map that perturbs the shading frame.
+    pub fn set_normal_map(&mut self, normal_map: textures::NormalMap) {
+        self.normal_map = Some(normal_map);
+    }
+
+    /// Sets an opacity mask, where zero is fully transparent and one is fully opaque. Rays pass
+    /// through the masked-out parts of the surface as if it weren't there, so the mask doesn't
+    /// need to be accounted for in the lobes.
+    pub fn set_opacity(&mut self, opacity: textures::MaterialParam<f32>) {
+        self.opacity = Some(opacity);
+    }
+
+    /// Returns the opacity at a hit point, in [0, 1].
+    pub fn opacity(&self, surface_props: &geom::SurfaceProperties) -> f32 {
+        match self.opacity {
+            Some(ref opacity) => core::clamp_unit(opacity.eval(surface_props)),
+            None => 1.0
+        }
+    }
+
+    /// Returns the surface properties at a hit point with the shading frame perturbed by the
+    /// material's normal or bump map, if any. Integrators should call this on each hit before
+    /// evaluating or sampling the material, so that the shading normal used for lobes and for
+    /// cosine and shading normal correction factors is consistent.
+    pub fn shading_props(&self, surface_props: geom::SurfaceProperties)
+        -> geom::SurfaceProperties
+    {
+        match self.normal_map {
+            Some(ref normal_map) => normal_map.apply(&surface_props),
+            None => surface_props
+        }
+    }
+
+    /// Whether the material's lobes are the same at every shading point.
+    pub fn is_constant(&self) -> bool {
+        self.shader.is_none()
+    }
+
+    /// Returns the lobes of the material at the given shading point, for use by other materials.
+    pub fn shared_lobes(material: &Arc<Material>, surface_props: &geom::SurfaceProperties)
+        -> std::vec::Vec<SharedLobe>
+    {
+        match material.shader {
+            Some(ref shader) => shader.lobes(surface_props).into_iter()
+                    .map(SharedLobe::Owned)
+                    .collect(),
+            None => (0..material.lobes.len())
+                    .map(|index| SharedLobe::Shared(material.clone(), index))
+                    .collect()
+        }
+    }
+
+    /// Returns the lobes at the given shading point.
+    pub fn lobes_at<'a>(&'a self, surface_props: &geom::SurfaceProperties)
+        -> shaders::LobeList<'a>
+    {
+        match self.shader {
+            Some(ref shader) => shaders::LobeList::Owned(shader.lobes(surface_props)),
+            None => shaders::LobeList::Borrowed(&self.lobes)
+        }
+    }
+
+    /// Builds the lobes of the material at a hit point, so that the material can be evaluated and
+    /// sampled there without rebuilding them. The surface properties are perturbed by
+    /// shading_props(), and view-dependent shaders (e.g. shader graphs with facing-ratio nodes)
+    /// see the surface from the given direction, which should face away from the hit point toward
+    /// the camera end of the path.
+    pub fn bsdf<'a>(&'a self, surface_props: geom::SurfaceProperties, viewer_world: &core::Vec)
+        -> Bsdf<'a>
+    {
+        let mut surface_props = self.shading_props(surface_props);
+        surface_props.incoming = *viewer_world;
+        Bsdf::new(self, surface_props)
+    }
+
+    fn bsdf_from<'a>(&'a self, incoming_world: &core::Vec,
+        surface_props: &geom::SurfaceProperties) -> Bsdf<'a>
+    {
+        let mut surface_props = surface_props.clone();
+        if surface_props.incoming.is_exactly_zero() {
+            surface_props.incoming = *incoming_world;
+        }
+        Bsdf::new(self, surface_props)
+    }
+
     /// Evaluates all the lobes at the given world-space incoming and outgoing vectors.
+    /// This and the other evaluation methods below build the lobes for a single use; integrators
+    /// should build them once per hit with bsdf() instead. View-dependent shaders see the surface
+    /// from surface_props.incoming, or from the incoming direction if that isn't set.
     pub fn f_world(&self,
         incoming_world: &core::Vec,
         outgoing_world: &core::Vec,
         surface_props: &geom::SurfaceProperties,
         camera_to_light: bool) -> core::Vec
     {
+        self.bsdf_from(incoming_world, surface_props)
+                .f_world(incoming_world, outgoing_world, camera_to_light)
+    }
+
+    pub fn pdf_world(&self,
+        incoming_world: &core::Vec,
+        outgoing_world: &core::Vec,
+        surface_props: &geom::SurfaceProperties) -> f32
+    {
+        self.bsdf_from(incoming_world, surface_props).pdf_world(incoming_world, outgoing_world)
+    }
+
+    /// Evaluates the attached light, if any, and returns the emission for the given incoming
+    /// direction.
+    pub fn light_world(&self, incoming_world: &core::Vec, surface_props: &geom::SurfaceProperties)
+        -> core::Vec
+    {
+        debug_assert!(core::is_close(surface_props.normal.magnitude(), 1.0, 1e-3));
+        debug_assert!(core::is_close(surface_props.tangent.magnitude(), 1.0, 1e-3));
+        debug_assert!(core::is_close(surface_props.binormal.magnitude(), 1.0, 1e-3));
+        
+        match self.light {
+            Some(ref light) => light.l_world(incoming_world, surface_props),
+            None => core::Vec::zero()
+        }
+    }
+
+    /// Samples a world-space direction in which the attached light emits from the given surface
+    /// point, returning the direction and its pdf with respect to solid angle.
+    /// Materials without a light sample a cosine-weighted hemisphere around the geometric normal.
+    pub fn sample_light_dir_world(&self, surface_props: &geom::SurfaceProperties,
+        rng: &mut rand::XorShiftRng) -> (core::Vec, f32)
+    {
+        match self.light {
+            Some(ref light) => light.sample_dir_world(surface_props, rng),
+            None => {
+                let cosine_sample_hemis = core::CosineSampleHemisphere {flipped: false};
+                let dir = cosine_sample_hemis.ind_sample(rng);
+                let dir_pdf = core::CosineSampleHemisphere::pdf(&dir);
+                let (tangent, binormal) = surface_props.geom_normal.coord_system();
+                let dir_world = dir.local_to_world(&tangent, &binormal, &surface_props.geom_normal);
+                (dir_world, dir_pdf)
+            }
+        }
+    }
+
+    /// See Bsdf::sample_world.
+    pub fn sample_world(&self,
+        incoming_world: &core::Vec,
+        surface_props: &geom::SurfaceProperties,
+        camera_to_light: bool,
+        rng: &mut rand::XorShiftRng) -> MaterialSample
+    {
+        self.bsdf_from(incoming_world, surface_props)
+                .sample_world(incoming_world, camera_to_light, rng)
+    }
+
+    /// Informs the attached light, if any, of the surface area of the prim using this material.
+    /// Prims call this when they're constructed.
+    pub fn set_surface_area(&mut self, area: f32) {
+        if let Some(ref mut light) = self.light {
+            light.set_surface_area(area);
+        }
+    }
+
+    /// Returns the name of the light group of the attached light, if any.
+    pub fn light_group(&self) -> Option<&str> {
+        match self.light {
+            Some(ref light) => light.group(),
+            None => None
+        }
+    }
+
+    pub fn has_light(&self) -> bool {
+        match self.light {
+            Some(_) => true,
+            None => false
+        }
+    }
+
+    /// Returns the number of lobes in this material at the given shading point whose kind
+    /// intersects the given kind mask.
+    pub fn count_lobes(&self, mask: lobes::LobeKind, surface_props: &geom::SurfaceProperties)
+        -> usize
+    {
+        Bsdf::new(self, surface_props.clone()).count_lobes(mask)
+    }
+}
+
+/// The lobes of a material at a shading point, built once so that the material can be evaluated
+/// and sampled there repeatedly. Materials with shaders build their lobes for each shading point,
+/// which is too costly to repeat for every evaluation.
+pub struct Bsdf<'a> {
+    material: &'a Material,
+    surface_props: geom::SurfaceProperties,
+    lobes: shaders::LobeList<'a>,
+}
+
+impl<'a> Bsdf<'a> {
+    fn new(material: &'a Material, surface_props: geom::SurfaceProperties) -> Bsdf<'a> {
+        let lobes = material.lobes_at(&surface_props);
+        Bsdf {material, surface_props, lobes}
+    }
+
+    /// The shading point, with the shading frame perturbed by the material's normal map.
+    pub fn surface_props(&self) -> &geom::SurfaceProperties {
+        &self.surface_props
+    }
+
+    /// Returns the lobes at the same shading point as seen from another direction, for
+    /// view-dependent shaders. The lobes of materials without shaders are shared, not rebuilt.
+    pub fn facing(&self, viewer_world: &core::Vec) -> Bsdf<'a> {
+        let mut surface_props = self.surface_props.clone();
+        surface_props.incoming = *viewer_world;
+        Bsdf::new(self.material, surface_props)
+    }
+
+    /// Evaluates all the lobes at the given world-space incoming and outgoing vectors.
+    pub fn f_world(&self,
+        incoming_world: &core::Vec,
+        outgoing_world: &core::Vec,
+        camera_to_light: bool) -> core::Vec
+    {
+        let surface_props = &self.surface_props;
         debug_assert!(core::is_close(surface_props.normal.magnitude(), 1.0, 1e-3));
         debug_assert!(core::is_close(surface_props.tangent.magnitude(), 1.0, 1e-3));
         debug_assert!(core::is_close(surface_props.binormal.magnitude(), 1.0, 1e-3));
@@ -82,7 +438,7 @@ impl Material {
         let reflect = (incoming_world.dot(&surface_props.geom_normal) *
                     outgoing_world.dot(&surface_props.geom_normal)) > 0.0;
         let mut radiance = core::Vec::zero();
-        for lobe in &self.lobes {
+        for lobe in self.lobes.iter() {
             if (reflect && lobe.kind().contains(lobes::LobeKind::LOBE_REFLECTION)) ||
                     (!reflect && lobe.kind().contains(lobes::LobeKind::LOBE_TRANSMISSION)) {
                 radiance = &radiance + &lobe.f(&incoming_local, &outgoing_local, camera_to_light);
@@ -94,10 +450,11 @@ impl Material {
 
     pub fn pdf_world(&self,
         incoming_world: &core::Vec,
-        outgoing_world: &core::Vec,
-        surface_props: &geom::SurfaceProperties) -> f32
+        outgoing_world: &core::Vec) -> f32
     {
-        if self.lobes.len() == 0 {
+        let surface_props = &self.surface_props;
+        let lobes = &self.lobes;
+        if lobes.is_empty() {
             return 0.0;
         }
 
@@ -110,39 +467,25 @@ impl Material {
             return 0.0;
         }
 
+        let selection = LobeSelection::new(lobes, &incoming_local);
         let mut pdf = 0.0;
-        for lobe in &self.lobes {
-            pdf += lobe.pdf(&incoming_local, &outgoing_local);
+        for lobe in lobes.iter() {
+            pdf += selection.probability(&**lobe, &incoming_local) *
+                    lobe.pdf(&incoming_local, &outgoing_local);
         }
 
-        return pdf / self.lobes.len() as f32;
-    }
-
-    /// Evaluates the attached light, if any, and returns the emission for the given incoming
-    /// direction.
-    pub fn light_world(&self, incoming_world: &core::Vec, surface_props: &geom::SurfaceProperties)
-        -> core::Vec
-    {
-        debug_assert!(core::is_close(surface_props.normal.magnitude(), 1.0, 1e-3));
-        debug_assert!(core::is_close(surface_props.tangent.magnitude(), 1.0, 1e-3));
-        debug_assert!(core::is_close(surface_props.binormal.magnitude(), 1.0, 1e-3));
-        
-        match self.light {
-            Some(ref light) => light.l_world(incoming_world, surface_props),
-            None => core::Vec::zero()
-        }
+        pdf
     }
 
     /// See PBRT 3e, page 832.
     /// Args:
     ///   incoming_world should face away from the intersection point.
-    ///   surface_props should be in world-space.
     pub fn sample_world(&self,
         incoming_world: &core::Vec,
-        surface_props: &geom::SurfaceProperties,
         camera_to_light: bool,
         rng: &mut rand::XorShiftRng) -> MaterialSample
     {
+        let surface_props = &self.surface_props;
         debug_assert!(core::is_close(surface_props.normal.magnitude(), 1.0, 1e-3));
         debug_assert!(core::is_close(surface_props.tangent.magnitude(), 1.0, 1e-3));
         debug_assert!(core::is_close(surface_props.binormal.magnitude(), 1.0, 1e-3));
@@ -158,25 +501,27 @@ impl Material {
         // Calculate emission. This doesn't depend on reflecting an outgoing ray.
         // Note that lighting isn't computed using the shading space (since it doesn't depend on
         // shading normals/tangents/binormals).
-        let emission = match self.light {
+        let emission = match self.material.light {
             Some(ref light) => light.l_world(incoming_world, surface_props),
             None => core::Vec::zero()
         };
 
-        if self.lobes.len() == 0 {
+        let lobes = &self.lobes;
+        if lobes.is_empty() {
             return MaterialSample {
                 emission: emission,
                 radiance: core::Vec::zero(),
                 outgoing: core::Vec::zero(),
                 pdf: 1.0,
                 kind: lobes::LobeKind::LOBE_NONE,
+                subsurface: None,
             };
         }
 
         // Choose a lobe and sample it.
-        let range = Range::new(0, self.lobes.len());
-        let r = range.ind_sample(rng);
-        let lobe = &self.lobes[r];
+        let selection = LobeSelection::new(lobes, &incoming_local);
+        let r = selection.choose(lobes, &incoming_local, rng);
+        let lobe = &lobes[r];
         let sample = lobe.sample_f(&incoming_local, camera_to_light, rng);
 
         let outgoing_world = sample.outgoing.local_to_world(
@@ -185,27 +530,31 @@ impl Material {
         let mut pdf = sample.pdf;
 
         // Compute overall PDF over all lobes (if the chosen lobe wasn't specular).
-        if !lobe.kind().contains(lobes::LobeKind::LOBE_SPECULAR) {
-            for idx in 0..self.lobes.len() {
+        let delta = lobe.kind().intersects(
+                lobes::LobeKind::LOBE_SPECULAR | lobes::LobeKind::LOBE_SUBSURFACE);
+        pdf *= selection.probability(&**lobe, &incoming_local);
+        if !delta {
+            for idx in 0..lobes.len() {
                 if idx != r {
-                    pdf += self.lobes[idx].pdf(&incoming_local, &sample.outgoing);
+                    pdf += selection.probability(&*lobes[idx], &incoming_local) *
+                            lobes[idx].pdf(&incoming_local, &sample.outgoing);
                 }
             }
         }
-        pdf /= self.lobes.len() as f32;
 
         // Compute overall BSDF over all lobes (if the chosen lobe wasn't specular).
-        if !lobe.kind().contains(lobes::LobeKind::LOBE_SPECULAR) {
+        if !delta {
             // Whether we're evalauting BTDFs or BRDFs should actually be based on geom normal,
             // not shading normal.
             let reflect = (incoming_world.dot(&surface_props.geom_normal) *
                     outgoing_world.dot(&surface_props.geom_normal)) > 0.0;
-            for idx in 0..self.lobes.len() {
+            for idx in 0..lobes.len() {
+                let kind = lobes[idx].kind();
                 if idx != r &&
-                        ((reflect && lobe.kind().contains(lobes::LobeKind::LOBE_REFLECTION)) ||
-                        (!reflect && lobe.kind().contains(lobes::LobeKind::LOBE_TRANSMISSION))) {
+                        ((reflect && kind.contains(lobes::LobeKind::LOBE_REFLECTION)) ||
+                        (!reflect && kind.contains(lobes::LobeKind::LOBE_TRANSMISSION))) {
                     radiance = &radiance +
-                            &self.lobes[idx].f(&incoming_local, &sample.outgoing, camera_to_light);
+                            &lobes[idx].f(&incoming_local, &sample.outgoing, camera_to_light);
                 }
             }
         }
@@ -218,6 +567,7 @@ impl Material {
                 outgoing: outgoing_world,
                 pdf: 1.0,
                 kind: lobes::LobeKind::LOBE_NONE,
+                subsurface: None,
             };
         }
 
@@ -231,40 +581,538 @@ impl Material {
             outgoing: outgoing_world,
             pdf: pdf,
             kind: lobe.kind(),
+            subsurface: lobe.subsurface(),
         };
     }
 
-    pub fn has_light(&self) -> bool {
-        match self.light {
-            Some(_) => true,
-            None => false
-        }
-    }
-
-    /// Returns the number of lobes in this material whose kind intersects the given kind mask.
+    /// Returns the number of lobes whose kind intersects the given kind mask.
     pub fn count_lobes(&self, mask: lobes::LobeKind) -> usize {
         let mut count = 0usize;
-        for lobe in &self.lobes {
+        for lobe in self.lobes.iter() {
             if lobe.kind().intersects(mask) {
                 count += 1;
             }
         }
         return count;
     }
+
+    /// Releases the borrow of the material, keeping the lobes that were built for this shading
+    /// point, so that they can be stored in buffers that outlive the scene.
+    pub fn detach(self) -> DetachedBsdf {
+        let lobes = match self.lobes {
+            shaders::LobeList::Owned(lobes) => Some(lobes),
+            shaders::LobeList::Borrowed(_) => None
+        };
+        DetachedBsdf {surface_props: self.surface_props, lobes}
+    }
 }
 
-pub struct DisneyMaterialBuilder {
-    _base_color: core::Vec,
+/// A Bsdf without its material. The lobes of materials without shaders aren't kept, since they
+/// belong to the material.
+pub struct DetachedBsdf {
+    surface_props: geom::SurfaceProperties,
+    lobes: Option<std::vec::Vec<Box<dyn lobes::Lobe>>>,
+}
+
+impl DetachedBsdf {
+    /// Returns the Bsdf again. The material must be the one that the Bsdf was detached from.
+    pub fn attach<'a>(&'a self, material: &'a Material) -> Bsdf<'a> {
+        let lobes = match self.lobes {
+            Some(ref lobes) => shaders::LobeList::Borrowed(lobes),
+            None => shaders::LobeList::Borrowed(&material.lobes)
+        };
+        Bsdf {material, surface_props: self.surface_props.clone(), lobes}
+    }
+}
+
+/// Chooses lobes for sampling in proportion to their approximate albedo for the incoming
+/// direction, so that faint lobes get few samples. The combined pdf over all lobes is the
+/// probability-weighted sum of the lobes' pdfs (one-sample MIS with the balance heuristic).
+struct LobeSelection {
+    total_weight: f32,
+    count: usize,
+}
+
+impl LobeSelection {
+    fn new(lobes: &[Box<dyn lobes::Lobe>], incoming_local: &core::Vec) -> LobeSelection {
+        let total_weight = lobes.iter().map(|lobe| LobeSelection::weight(&**lobe, incoming_local))
+                .sum();
+        LobeSelection {total_weight, count: lobes.len()}
+    }
+
+    fn weight(lobe: &dyn lobes::Lobe, incoming_local: &core::Vec) -> f32 {
+        let albedo = lobe.albedo(incoming_local);
+        if albedo > 0.0 && albedo.is_finite() { albedo } else { 0.0 }
+    }
+
+    /// The probability of choosing the lobe. If no lobe has a positive albedo, lobes are chosen
+    /// uniformly.
+    fn probability(&self, lobe: &dyn lobes::Lobe, incoming_local: &core::Vec) -> f32 {
+        if self.total_weight > 0.0 {
+            LobeSelection::weight(lobe, incoming_local) / self.total_weight
+        }
+        else {
+            1.0 / self.count as f32
+        }
+    }
+
+    fn choose(&self, lobes: &[Box<dyn lobes::Lobe>], incoming_local: &core::Vec,
+        rng: &mut rand::XorShiftRng) -> usize
+    {
+        if self.total_weight <= 0.0 {
+            let range = Range::new(0, lobes.len());
+            return range.ind_sample(rng);
+        }
+
+        let target = rng.next_f32() * self.total_weight;
+        let mut sum = 0.0;
+        for (idx, lobe) in lobes.iter().enumerate() {
+            let weight = LobeSelection::weight(&**lobe, incoming_local);
+            sum += weight;
+            if weight > 0.0 && target < sum {
+                return idx;
+            }
+        }
+
+        // Guard against rounding error by picking the last lobe that can be chosen.
+        lobes.iter().rposition(|lobe| LobeSelection::weight(&**lobe, incoming_local) > 0.0)
+                .unwrap_or(0)
+    }
+}
+
+/// Creates a material with a diffuse area light and no lobes.
+/// The emitted radiance is the color, multiplied by the texture (evaluated at the surface UVs)
+/// and by the IES profile (evaluated relative to the surface normal), if present.
+/// The intensity can be given in physical units, in which case it's normalized by the area of
+/// the prim that the material is attached to.
+pub struct AreaLightBuilder {
+    _color: core::Vec,
+    _intensity: f32,
+    _units: lights::LightUnits,
+    _two_sided: bool,
+    _texture: Option<Arc<dyn textures::Texture>>,
+    _ies_profile: Option<Arc<ies::IesProfile>>,
+    _group: Option<String>,
+}
+
+impl AreaLightBuilder {
+    pub fn new() -> AreaLightBuilder {
+        AreaLightBuilder {
+            _color: core::Vec::one(),
+            _intensity: 1.0,
+            _units: lights::LightUnits::Radiance,
+            _two_sided: false,
+            _texture: None,
+            _ies_profile: None,
+            _group: None,
+        }
+    }
+
+    pub fn build(&self) -> Material {
+        let mut light = lights::DiffuseAreaLight::new_with_options(
+                self._color, self._intensity, self._units, self._two_sided,
+                self._texture.clone(), self._ies_profile.clone());
+        light.group = self._group.clone();
+        Material {
+            display: self._color,
+            light: Some(Box::new(light)),
+            lobes: vec![],
+            shader: None,
+            normal_map: None,
+            opacity: None,
+        }
+    }
+
+    pub fn color(&mut self, val: core::Vec) -> &mut Self {
+        self._color = val;
+        self
+    }
+
+    /// Sets the color to that of a blackbody radiator at the given temperature in Kelvin.
+    pub fn temperature(&mut self, kelvin: f32) -> &mut Self {
+        self._color = util::blackbody(kelvin);
+        self
+    }
+
+    pub fn intensity(&mut self, val: f32) -> &mut Self {
+        self._intensity = val;
+        self
+    }
+
+    pub fn units(&mut self, val: lights::LightUnits) -> &mut Self {
+        self._units = val;
+        self
+    }
+
+    pub fn two_sided(&mut self, val: bool) -> &mut Self {
+        self._two_sided = val;
+        self
+    }
+
+    pub fn texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._texture = Some(val);
+        self
+    }
+
+    pub fn ies_profile(&mut self, val: Arc<ies::IesProfile>) -> &mut Self {
+        self._ies_profile = Some(val);
+        self
+    }
+
+    /// Sets the name of the light group that the light contributes to. Lights aren't in any
+    /// group by default.
+    pub fn group(&mut self, val: &str) -> &mut Self {
+        self._group = Some(String::from(val));
+        self
+    }
+}
+
+/// Creates a material with a clear or tinted dielectric coating over a base material, like varnish
+/// over wood or lacquer over metal. Light reflected by the coating doesn't reach the base, and
+/// light reflected by the base is attenuated by the coating's Fresnel transmission on the way in
+/// and out, as well as by the tint.
+pub struct LayeredMaterialBuilder {
+    _base: Arc<Material>,
     _roughness: f32,
-    _anisotropic: f32,
     _ior: f32,
-    _metallic: f32,
-    _specular_trans: f32,
-    _specular_tint: f32,
-    _sheen: f32,
-    _sheen_tint: f32,
-    _clearcoat: f32,
-    _clearcoat_gloss: f32,
+    _tint: core::Vec,
+}
+
+impl LayeredMaterialBuilder {
+    pub fn new(base: Arc<Material>) -> LayeredMaterialBuilder {
+        LayeredMaterialBuilder {
+            _base: base,
+            _roughness: 0.0,
+            _ior: 1.5,
+            _tint: core::Vec::one(),
+        }
+    }
+
+    pub fn build(&self) -> Material {
+        let display = self._base.display.comp_mult(&self._tint);
+        Material::from_shader(display, Box::new(shaders::LayeredShader::new(
+                self._base.clone(), core::clamp_unit(self._roughness), self._ior, self._tint)))
+    }
+
+    pub fn roughness(&mut self, val: f32) -> &mut Self {
+        self._roughness = val;
+        self
+    }
+
+    pub fn ior(&mut self, val: f32) -> &mut Self {
+        self._ior = val;
+        self
+    }
+
+    /// The color of light that passes through the coating to the base and back at normal
+    /// incidence. Light at glancing angles travels farther through the coating and is tinted more.
+    pub fn tint(&mut self, val: core::Vec) -> &mut Self {
+        self._tint = val;
+        self
+    }
+}
+
+/// The normal or bump map and opacity mask of a material being built, which all the surface
+/// material builders share through SurfaceBuilder.
+#[derive(Clone, Default)]
+pub struct SurfaceOptions {
+    normal_map: Option<textures::NormalMap>,
+    opacity: Option<textures::MaterialParam<f32>>,
+}
+
+impl SurfaceOptions {
+    /// Sets the options on a material that was just built.
+    fn apply(&self, material: &mut Material) {
+        material.normal_map = self.normal_map.clone();
+        material.opacity = self.opacity.clone();
+    }
+}
+
+/// Setters for the options that every surface material builder supports; see SurfaceOptions.
+pub trait SurfaceBuilder {
+    fn surface_options(&mut self) -> &mut SurfaceOptions;
+
+    /// Sets a tangent-space normal map. Replaces any bump map.
+    fn normal_map(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self.surface_options().normal_map = Some(textures::NormalMap::TangentSpace(val));
+        self
+    }
+
+    /// Sets a bump map with the given scale. Replaces any normal map.
+    fn bump_map(&mut self, val: Arc<dyn textures::Texture>, scale: f32) -> &mut Self {
+        self.surface_options().normal_map = Some(textures::NormalMap::Bump(val, scale));
+        self
+    }
+
+    /// Sets a constant opacity for the whole surface. See `Material::set_opacity`.
+    fn opacity(&mut self, val: f32) -> &mut Self {
+        self.surface_options().opacity = Some(textures::MaterialParam::Constant(val));
+        self
+    }
+
+    /// Sets an opacity mask, e.g. for foliage cards or chain-link fences. The mask uses the
+    /// texture's luminance.
+    fn opacity_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self.surface_options().opacity = Some(textures::MaterialParam::Texture(val));
+        self
+    }
+}
+
+/// Creates a diffuse material. With a sigma of zero, the material is Lambertian; otherwise it uses
+/// the Oren-Nayar model, where sigma is the standard deviation in degrees of the angle of the
+/// surface's microfacets. Rough surfaces like clay, plaster, and the moon look flatter than
+/// Lambertian surfaces because they scatter more light back toward the light source.
+pub struct MatteMaterialBuilder {
+    _shader: shaders::MatteShader,
+    _surface: SurfaceOptions,
+}
+
+impl MatteMaterialBuilder {
+    pub fn new() -> MatteMaterialBuilder {
+        MatteMaterialBuilder {
+            _shader: shaders::MatteShader {
+                color: textures::MaterialParam::Constant(core::Vec::new(0.5, 0.5, 0.5)),
+                sigma: textures::MaterialParam::Constant(0.0),
+            },
+            _surface: SurfaceOptions::default(),
+        }
+    }
+
+    pub fn build(&self) -> Material {
+        // The display color can't vary, so use the texture's color at the origin.
+        let display = self._shader.color.eval(&geom::SurfaceProperties::zero());
+        let mut material = Material::from_shader(display, Box::new(self._shader.clone()));
+        self._surface.apply(&mut material);
+        material
+    }
+
+    pub fn color(&mut self, val: core::Vec) -> &mut Self {
+        self._shader.color = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn color_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.color = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// Sets the roughness in degrees, from 0 to 90. Values around 20 are typical.
+    pub fn sigma(&mut self, val: f32) -> &mut Self {
+        self._shader.sigma = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    /// Sets the roughness from a texture, whose luminance is scaled from [0, 1] to [0, 90] degrees.
+    pub fn sigma_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.sigma = textures::MaterialParam::Texture(val);
+        self
+    }
+}
+
+impl SurfaceBuilder for MatteMaterialBuilder {
+    fn surface_options(&mut self) -> &mut SurfaceOptions {
+        &mut self._surface
+    }
+}
+
+/// Creates a material for hair and fur fibers, using the model of Chiang et al. (2016), for use
+/// on Curve prims. Other prims with this material need the same parameterization: a shading
+/// frame whose tangent runs along the fiber and whose normal faces the viewer, and a v texture
+/// coordinate that runs from 0 to 1 across the fiber's width in the direction of the binormal.
+/// The fiber's color comes from its absorption coefficient, which can be given directly, derived
+/// from melanin concentrations (the default, medium brown), or fit to a desired color.
+pub struct HairMaterialBuilder {
+    _eumelanin: f32,
+    _pheomelanin: f32,
+    _color: Option<core::Vec>,
+    _sigma_a: Option<core::Vec>,
+    _beta_m: f32,
+    _beta_n: f32,
+    _alpha: f32,
+    _ior: f32,
+}
+
+impl HairMaterialBuilder {
+    pub fn new() -> HairMaterialBuilder {
+        HairMaterialBuilder {
+            _eumelanin: 1.3,
+            _pheomelanin: 0.0,
+            _color: None,
+            _sigma_a: None,
+            _beta_m: 0.3,
+            _beta_n: 0.3,
+            _alpha: 2.0,
+            _ior: 1.55,
+        }
+    }
+
+    pub fn build(&self) -> Material {
+        let sigma_a = match (self._sigma_a, self._color) {
+            (Some(sigma_a), _) => sigma_a,
+            (None, Some(color)) => hair::HairLobe::sigma_a_from_color(&color, self._beta_n),
+            (None, None) => hair::HairLobe::sigma_a_from_melanin(
+                    self._eumelanin, self._pheomelanin)
+        };
+        // Approximate the display color by the transmittance across the fiber.
+        let display = match self._color {
+            Some(color) => color,
+            None => (&sigma_a * -1.0).exp()
+        };
+        Material::from_shader(display, Box::new(shaders::HairShader {
+            sigma_a,
+            beta_m: self._beta_m,
+            beta_n: self._beta_n,
+            alpha: self._alpha,
+            ior: self._ior,
+        }))
+    }
+
+    /// Sets the eumelanin concentration, which makes hair brown or black. Blond hair is around
+    /// 0.3, brown around 1.3, and black around 8. Replaces any color or absorption coefficient.
+    pub fn eumelanin(&mut self, val: f32) -> &mut Self {
+        self._eumelanin = val;
+        self._color = None;
+        self._sigma_a = None;
+        self
+    }
+
+    /// Sets the pheomelanin concentration, which makes hair red or yellow. Replaces any color or
+    /// absorption coefficient.
+    pub fn pheomelanin(&mut self, val: f32) -> &mut Self {
+        self._pheomelanin = val;
+        self._color = None;
+        self._sigma_a = None;
+        self
+    }
+
+    /// Sets the approximate color of a mass of fibers. Replaces any absorption coefficient.
+    pub fn color(&mut self, val: core::Vec) -> &mut Self {
+        self._color = Some(val);
+        self._sigma_a = None;
+        self
+    }
+
+    /// Sets the absorption coefficient inside the fiber, per unit of fiber diameter.
+    pub fn sigma_a(&mut self, val: core::Vec) -> &mut Self {
+        self._sigma_a = Some(val);
+        self
+    }
+
+    /// Sets the longitudinal roughness, from 0 to 1, which spreads highlights along the fiber.
+    pub fn beta_m(&mut self, val: f32) -> &mut Self {
+        self._beta_m = val;
+        self
+    }
+
+    /// Sets the azimuthal roughness, from 0 to 1, which spreads light around the fiber.
+    pub fn beta_n(&mut self, val: f32) -> &mut Self {
+        self._beta_n = val;
+        self
+    }
+
+    /// Sets the tilt of the cuticle scales in degrees, which shifts the primary and secondary
+    /// highlights apart.
+    pub fn alpha(&mut self, val: f32) -> &mut Self {
+        self._alpha = val;
+        self
+    }
+
+    pub fn ior(&mut self, val: f32) -> &mut Self {
+        self._ior = val;
+        self
+    }
+}
+
+/// Creates a material that reflects like a metal with the given complex index of refraction
+/// (eta + ik), specified per color channel. Presets are available for common metals.
+/// The metal can be coated with a thin film, e.g. for anodized or heat-tinted metals.
+pub struct ConductorMaterialBuilder {
+    _shader: shaders::ConductorShader,
+    _surface: SurfaceOptions,
+}
+
+impl ConductorMaterialBuilder {
+    pub fn new() -> ConductorMaterialBuilder {
+        ConductorMaterialBuilder {
+            _shader: shaders::ConductorShader {
+                fresnel: util::ConductorFresnel::aluminum(),
+                roughness: 0.2,
+                anisotropic: 0.0,
+                thin_film_thickness: textures::MaterialParam::Constant(0.0),
+                thin_film_ior: DEFAULT_THIN_FILM_IOR,
+            },
+            _surface: SurfaceOptions::default(),
+        }
+    }
+
+    pub fn build(&self) -> Material {
+        let fresnel = &self._shader.fresnel;
+        let display = util::fresnel_conductor(1.0, &fresnel.eta, &fresnel.k);
+        let mut material = Material::from_shader(display, Box::new(self._shader.clone()));
+        self._surface.apply(&mut material);
+        material
+    }
+
+    /// Sets the eta and k of a preset, e.g. `ConductorFresnel::gold()`.
+    pub fn preset(&mut self, val: util::ConductorFresnel) -> &mut Self {
+        self._shader.fresnel = val;
+        self
+    }
+
+    pub fn eta(&mut self, val: core::Vec) -> &mut Self {
+        self._shader.fresnel.eta = val;
+        self
+    }
+
+    pub fn k(&mut self, val: core::Vec) -> &mut Self {
+        self._shader.fresnel.k = val;
+        self
+    }
+
+    pub fn roughness(&mut self, val: f32) -> &mut Self {
+        self._shader.roughness = val;
+        self
+    }
+
+    pub fn anisotropic(&mut self, val: f32) -> &mut Self {
+        self._shader.anisotropic = val;
+        self
+    }
+
+    /// Coats the metal with a thin film of the given thickness in nanometers, which tints its
+    /// reflection with iridescent colors. Zero (the default) means no film.
+    pub fn thin_film_thickness(&mut self, val: f32) -> &mut Self {
+        self._shader.thin_film_thickness = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    /// Sets the film thickness from a texture, whose luminance is scaled from [0, 1] to
+    /// [0, 1000] nanometers.
+    pub fn thin_film_thickness_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.thin_film_thickness = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// The index of refraction of the thin film, e.g. about 2.4 for the titanium dioxide on
+    /// anodized titanium.
+    pub fn thin_film_ior(&mut self, val: f32) -> &mut Self {
+        self._shader.thin_film_ior = val;
+        self
+    }
+}
+
+impl SurfaceBuilder for ConductorMaterialBuilder {
+    fn surface_options(&mut self) -> &mut SurfaceOptions {
+        &mut self._surface
+    }
+}
+
+pub struct DisneyMaterialBuilder {
+    _shader: shaders::DisneyShader,
+    _surface: SurfaceOptions,
+    _emission: core::Vec,
+    _emission_intensity: f32,
+    _emission_texture: Option<Arc<dyn textures::Texture>>,
 }
 
 /// Creates a material with lobes that form the Disney principled BSSRDF shader.
@@ -272,118 +1120,253 @@ pub struct DisneyMaterialBuilder {
 /// http://blog.selfshadow.com/publications/s2012-shading-course/burley/s2012_pbs_disney_brdf_notes_v3.pdf
 /// Burley's 2015 SIGGRAPH course notes extends it to transmissive effects:
 /// http://blog.selfshadow.com/publications/s2015-shading-course/burley/s2015_pbs_disney_bsdf_notes.pdf
+/// Any parameter other than the indices of refraction can be bound to a texture instead of a
+/// constant; scalar parameters use the texture's luminance. Textured materials build their lobes
+/// at each shading point. Transmissive materials with zero roughness reflect and refract through a
+/// smooth (delta) dielectric lobe instead of microfacet lobes.
 impl DisneyMaterialBuilder {
     pub fn new() -> DisneyMaterialBuilder {
         DisneyMaterialBuilder {
-            _base_color: core::Vec::one(),
-            _roughness: 0.5,
-            _anisotropic: 0.0,
-            _ior: 1.5,
-            _metallic: 0.0,
-            _specular_trans: 0.0,
-            _specular_tint: 0.0,
-            _sheen: 0.0,
-            _sheen_tint: 0.5,
-            _clearcoat: 0.0,
-            _clearcoat_gloss: 0.1,
+            _shader: shaders::DisneyShader {
+                base_color: textures::MaterialParam::Constant(core::Vec::one()),
+                roughness: textures::MaterialParam::Constant(0.5),
+                anisotropic: textures::MaterialParam::Constant(0.0),
+                ior: 1.5,
+                metallic: textures::MaterialParam::Constant(0.0),
+                specular_trans: textures::MaterialParam::Constant(0.0),
+                specular_tint: textures::MaterialParam::Constant(0.0),
+                sheen: textures::MaterialParam::Constant(0.0),
+                sheen_tint: textures::MaterialParam::Constant(0.5),
+                clearcoat: textures::MaterialParam::Constant(0.0),
+                clearcoat_gloss: textures::MaterialParam::Constant(0.1),
+                subsurface: textures::MaterialParam::Constant(0.0),
+                subsurface_radius: core::Vec::one(),
+                thin: false,
+                diff_trans: textures::MaterialParam::Constant(0.0),
+                flatness: textures::MaterialParam::Constant(0.0),
+                thin_film_thickness: textures::MaterialParam::Constant(0.0),
+                thin_film_ior: DEFAULT_THIN_FILM_IOR,
+            },
+            _surface: SurfaceOptions::default(),
+            _emission: core::Vec::zero(),
+            _emission_intensity: 1.0,
+            _emission_texture: None,
         }
     }
 
     pub fn build(&self) -> Material {
-        // Combo of three models: diffuse_weight + trans_weight + metallic = 1.0
-        let diffuse_weight = (1.0 - self._metallic) * (1.0 - self._specular_trans);
-        let trans_weight = (1.0 - self._metallic) * self._specular_trans;
-        let mut lobes_list = std::vec::Vec::<Box<lobes::Lobe>>::new();
-        
-        // Diffuse, retro-reflection, and sheen
-        if diffuse_weight > 0.0 {
-            lobes_list.push(Box::new(lobes::DisneyDiffuseRefl::new(
-                    self._base_color, self._roughness, self._sheen, self._sheen_tint,
-                    diffuse_weight)));
-        }
-
-        // Specular reflection
-        if self._ior > 1.0 {
-            lobes_list.push(Box::new(lobes::DisneySpecularRefl::new_aniso(
-                    self._base_color, self._roughness, self._anisotropic, self._ior,
-                    self._specular_tint, self._metallic)))
+        // The display color can't vary, so use the texture's color at the origin.
+        let display = self._shader.base_color.eval(&geom::SurfaceProperties::zero());
+        let mut material = Material::from_shader(display, Box::new(self._shader.clone()));
+        self._surface.apply(&mut material);
+        if !self._emission.is_exactly_zero() && self._emission_intensity != 0.0 {
+            material.light = Some(Box::new(lights::DiffuseAreaLight::new_with_options(
+                    self._emission, self._emission_intensity, lights::LightUnits::Radiance, false,
+                    self._emission_texture.clone(), None)));
         }
+        material
+    }
 
-        // Clearcoat (second specular lobe)
-        if self._clearcoat > 0.0 {
-            lobes_list.push(Box::new(lobes::DisneyClearcoatRefl::new(
-                    self._clearcoat, self._clearcoat_gloss)));
-        }
+    /// Makes the surface glow with the given radiance on the side that its normal faces, in
+    /// addition to scattering light with its lobes, e.g. for a screen under a glossy coating.
+    /// The surface becomes a light that the integrators sample directly. Black (the default)
+    /// means no emission.
+    pub fn emission(&mut self, val: core::Vec) -> &mut Self {
+        self._emission = val;
+        self
+    }
 
-        // Specular transmission
-        if trans_weight > 0.0 {
-            // PBRT suggests that we take scale up the base color to its sqrt
-            // for art-direction purposes; it makes it so that light that enters and exits
-            // will have the base color instead of being darker.
-            let specular_trans_color = trans_weight * &self._base_color.sqrt();
-            lobes_list.push(Box::new(lobes::DisneySpecularTrans::new_aniso(
-                    specular_trans_color, self._roughness, self._anisotropic, self._ior)));
-        }
+    /// Scales the emission color.
+    pub fn emission_intensity(&mut self, val: f32) -> &mut Self {
+        self._emission_intensity = val;
+        self
+    }
 
-        Material {
-            display: self._base_color,
-            light: None,
-            lobes: lobes_list
-        }
+    /// Multiplies the emission color by a texture, evaluated at the surface UVs.
+    pub fn emission_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._emission_texture = Some(val);
+        self
     }
 
     pub fn base_color(&mut self, val: core::Vec) -> &mut Self {
-        self._base_color = val;
+        self._shader.base_color = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn base_color_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.base_color = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn roughness(&mut self, val: f32) -> &mut Self {
-        self._roughness = val;
+        self._shader.roughness = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn roughness_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.roughness = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn anisotropic(&mut self, val: f32) -> &mut Self {
-        self._anisotropic = val;
+        self._shader.anisotropic = textures::MaterialParam::Constant(val);
         self
     }
 
+    pub fn anisotropic_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.anisotropic = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// The index of refraction can't be textured, since it describes the medium inside the
+    /// surface.
     pub fn ior(&mut self, val: f32) -> &mut Self {
-        self._ior = val;
+        self._shader.ior = val;
         self
     }
 
     pub fn metallic(&mut self, val: f32) -> &mut Self {
-        self._metallic = val;
+        self._shader.metallic = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn metallic_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.metallic = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn specular_trans(&mut self, val: f32) -> &mut Self {
-        self._specular_trans = val;
+        self._shader.specular_trans = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn specular_trans_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.specular_trans = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn specular_tint(&mut self, val: f32) -> &mut Self {
-        self._specular_tint = val;
+        self._shader.specular_tint = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn specular_tint_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.specular_tint = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn sheen(&mut self, val: f32) -> &mut Self {
-        self._sheen = val;
+        self._shader.sheen = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn sheen_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.sheen = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn sheen_tint(&mut self, val: f32) -> &mut Self {
-        self._sheen_tint = val;
+        self._shader.sheen_tint = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn sheen_tint_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.sheen_tint = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn clearcoat(&mut self, val: f32) -> &mut Self {
-        self._clearcoat = val;
+        self._shader.clearcoat = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn clearcoat_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.clearcoat = textures::MaterialParam::Texture(val);
         self
     }
 
     pub fn clearcoat_gloss(&mut self, val: f32) -> &mut Self {
-        self._clearcoat_gloss = val;
+        self._shader.clearcoat_gloss = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn clearcoat_gloss_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.clearcoat_gloss = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// Blends the diffuse lobe into volumetric subsurface scattering inside the surface, which
+    /// should be a closed mesh or sphere. The base color sets the albedo of the scattering medium.
+    pub fn subsurface(&mut self, val: f32) -> &mut Self {
+        self._shader.subsurface = textures::MaterialParam::Constant(val);
         self
     }
+
+    pub fn subsurface_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.subsurface = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// The scatter distance per color channel, in scene units. Like the index of refraction, this
+    /// describes the medium inside the surface and can't be textured.
+    pub fn subsurface_radius(&mut self, val: core::Vec) -> &mut Self {
+        self._shader.subsurface_radius = val;
+        self
+    }
+
+    /// Treats the surface as a thin sheet with no interior, e.g. for leaves, paper, and cloth
+    /// modeled as single-sided planes. Specular transmission passes through the sheet without
+    /// bending, and subsurface scattering is replaced by diff_trans and flatness.
+    pub fn thin(&mut self, val: bool) -> &mut Self {
+        self._shader.thin = val;
+        self
+    }
+
+    /// The fraction of diffuse light transmitted through a thin surface.
+    pub fn diff_trans(&mut self, val: f32) -> &mut Self {
+        self._shader.diff_trans = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn diff_trans_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.diff_trans = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// Blends the diffuse reflection of a thin surface toward a flatter, subsurface-like look.
+    pub fn flatness(&mut self, val: f32) -> &mut Self {
+        self._shader.flatness = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    pub fn flatness_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.flatness = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// Coats the specular reflection with a thin film of the given thickness in nanometers,
+    /// which tints it with iridescent colors. Zero (the default) means no film.
+    pub fn thin_film_thickness(&mut self, val: f32) -> &mut Self {
+        self._shader.thin_film_thickness = textures::MaterialParam::Constant(val);
+        self
+    }
+
+    /// Sets the film thickness from a texture, whose luminance is scaled from [0, 1] to
+    /// [0, 1000] nanometers.
+    pub fn thin_film_thickness_texture(&mut self, val: Arc<dyn textures::Texture>) -> &mut Self {
+        self._shader.thin_film_thickness = textures::MaterialParam::Texture(val);
+        self
+    }
+
+    /// The index of refraction of the thin film, e.g. 1.33 for soapy water.
+    pub fn thin_film_ior(&mut self, val: f32) -> &mut Self {
+        self._shader.thin_film_ior = val;
+        self
+    }
+}
+
+impl SurfaceBuilder for DisneyMaterialBuilder {
+    fn surface_options(&mut self) -> &mut SurfaceOptions {
+        &mut self._surface
+    }
 }
@@ -0,0 +1,314 @@
+use material::lobes;
+
+use core;
+
+use std;
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use rand;
+use rand::Rng;
+
+// Note: the file format and the half/difference angle parameterization are those of the MERL
+// BRDF database; see Matusik et al., "A Data-Driven Reflectance Model" (SIGGRAPH 2003), and the
+// reference code distributed with the database.
+
+const THETA_HALF_RES: usize = 90;
+const THETA_DIFF_RES: usize = 90;
+/// Only half of the phi_diff range is stored, because the BRDF is reciprocal.
+const PHI_DIFF_RES: usize = 180;
+const NUM_ENTRIES: usize = THETA_HALF_RES * THETA_DIFF_RES * PHI_DIFF_RES;
+
+/// The scale factors that convert the stored values of each channel into reflectance.
+const RED_SCALE: f32 = 1.0 / 1500.0;
+const GREEN_SCALE: f32 = 1.15 / 1500.0;
+const BLUE_SCALE: f32 = 1.66 / 1500.0;
+
+/// The resolution of the tabulated sampling distributions. There's one distribution over the
+/// outgoing (phi, theta) for each incoming theta.
+const SAMPLING_THETA_IN_RES: usize = 32;
+const SAMPLING_THETA_OUT_RES: usize = 32;
+const SAMPLING_PHI_OUT_RES: usize = 64;
+
+/// An isotropic BRDF measured on a regular grid of half and difference angles, loaded from a
+/// binary file in the format of the MERL BRDF database.
+///
+/// The data only covers reflection from the front of the surface, and is evaluated in a local
+/// frame where the normal is +z. Directions below the surface are mirrored to the front.
+pub struct MerlBrdf {
+    /// Reflectance values, stored as values[channel * NUM_ENTRIES + index].
+    values: std::vec::Vec<f32>,
+    /// Distributions over the outgoing (phi, theta), proportional to the luminance of the BRDF
+    /// times the cosine to the normal, for each incoming theta. Phi is measured from the
+    /// azimuth of the incoming direction.
+    sampling: std::vec::Vec<core::PiecewiseConstant2D>,
+    /// The directional albedo of each color channel at normal incidence.
+    normal_albedo: core::Vec,
+}
+
+impl MerlBrdf {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<MerlBrdf, String> {
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(reason) => {
+                return Err(format!("Couldn't open MERL BRDF file: {}", reason));
+            }
+        };
+
+        let mut bytes = std::vec::Vec::<u8>::new();
+        if let Err(reason) = file.read_to_end(&mut bytes) {
+            return Err(format!("Couldn't read MERL BRDF file: {}", reason));
+        }
+
+        MerlBrdf::parse(&bytes)
+    }
+
+    /// Parses the contents of a MERL BRDF file: three little-endian 32-bit integers giving the
+    /// dimensions of the table, followed by the red, green, and blue tables as little-endian
+    /// doubles.
+    pub fn parse(bytes: &[u8]) -> Result<MerlBrdf, String> {
+        if bytes.len() < 12 {
+            return Err("MERL BRDF parse error: missing header".to_string());
+        }
+        let mut dims = [0i32; 3];
+        for (d, chunk) in dims.iter_mut().zip(bytes[0..12].chunks(4)) {
+            *d = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        if dims != [THETA_HALF_RES as i32, THETA_DIFF_RES as i32, PHI_DIFF_RES as i32] {
+            return Err(format!("MERL BRDF parse error: unexpected dimensions {}x{}x{}",
+                    dims[0], dims[1], dims[2]));
+        }
+
+        let data = &bytes[12..];
+        if data.len() != 3 * NUM_ENTRIES * 8 {
+            return Err(format!("MERL BRDF parse error: expected {} bytes of data, found {}",
+                    3 * NUM_ENTRIES * 8, data.len()));
+        }
+
+        // Unmeasured entries are stored as negative numbers; treat them as black.
+        let scales = [RED_SCALE, GREEN_SCALE, BLUE_SCALE];
+        let mut values = std::vec::Vec::<f32>::with_capacity(3 * NUM_ENTRIES);
+        for (index, chunk) in data.chunks(8).enumerate() {
+            let mut raw = [0u8; 8];
+            raw.copy_from_slice(chunk);
+            let value = f64::from_le_bytes(raw) as f32 * scales[index / NUM_ENTRIES];
+            values.push(f32::max(value, 0.0));
+        }
+
+        let mut brdf = MerlBrdf {values, sampling: vec![], normal_albedo: core::Vec::zero()};
+        brdf.compute_sampling();
+        Ok(brdf)
+    }
+
+    fn compute_sampling(&mut self) {
+        // The luminance of the reflected light in each direction is proportional to the BRDF
+        // times the cosine to the normal, and the sin(theta) factor accounts for the change of
+        // variables from solid angle to (phi, theta).
+        let mut sampling = std::vec::Vec::with_capacity(SAMPLING_THETA_IN_RES);
+        for row_in in 0..SAMPLING_THETA_IN_RES {
+            let theta_in = (row_in as f32 + 0.5) / SAMPLING_THETA_IN_RES as f32
+                    * std::f32::consts::FRAC_PI_2;
+            let i = core::Vec::from_spherical(f32::cos(theta_in), 0.0);
+            let mut func = std::vec::Vec::<f32>::with_capacity(
+                    SAMPLING_THETA_OUT_RES * SAMPLING_PHI_OUT_RES);
+            let mut albedo = core::Vec::zero();
+            for row in 0..SAMPLING_THETA_OUT_RES {
+                let theta = (row as f32 + 0.5) / SAMPLING_THETA_OUT_RES as f32
+                        * std::f32::consts::FRAC_PI_2;
+                for col in 0..SAMPLING_PHI_OUT_RES {
+                    let phi = (col as f32 + 0.5) / SAMPLING_PHI_OUT_RES as f32 * core::TWO_PI;
+                    let o = core::Vec::from_spherical(f32::cos(theta), phi);
+                    let reflected = &self.eval(&i, &o) * (f32::cos(theta) * f32::sin(theta));
+                    func.push(reflected.luminance());
+                    albedo = &albedo + &reflected;
+                }
+            }
+            if row_in == 0 {
+                let cell = std::f32::consts::FRAC_PI_2 * core::TWO_PI
+                        / (SAMPLING_THETA_OUT_RES * SAMPLING_PHI_OUT_RES) as f32;
+                self.normal_albedo = &albedo * cell;
+            }
+            sampling.push(core::PiecewiseConstant2D::new(
+                    &func, SAMPLING_PHI_OUT_RES, SAMPLING_THETA_OUT_RES));
+        }
+        self.sampling = sampling;
+    }
+
+    /// Evaluates the BRDF for the given pair of directions in the local frame.
+    pub fn eval(&self, i: &core::Vec, o: &core::Vec) -> core::Vec {
+        if !i.is_local_same_hemisphere(o) {
+            return core::Vec::zero();
+        }
+        let (i, o) = if i.z < 0.0 { (mirror(i), mirror(o)) } else { (*i, *o) };
+
+        let half_unnorm = &i + &o;
+        if half_unnorm.is_exactly_zero() {
+            return core::Vec::zero();
+        }
+        let half = half_unnorm.normalized();
+        let theta_half = f32::acos(core::clamp(half.z, -1.0, 1.0));
+        let phi_half = f32::atan2(half.y, half.x);
+
+        // The difference vector is the incoming direction in a frame where the half vector is
+        // the normal.
+        let diff = rotate_y(&rotate_z(&i, -phi_half), -theta_half);
+        let theta_diff = f32::acos(core::clamp(diff.z, -1.0, 1.0));
+        let phi_diff = f32::atan2(diff.y, diff.x);
+
+        let index = phi_diff_index(phi_diff)
+                + PHI_DIFF_RES * (theta_diff_index(theta_diff)
+                + THETA_DIFF_RES * theta_half_index(theta_half));
+        core::Vec::new(
+                self.values[index],
+                self.values[index + NUM_ENTRIES],
+                self.values[index + 2 * NUM_ENTRIES])
+    }
+
+    /// The directional albedo of each color channel at normal incidence.
+    pub fn normal_albedo(&self) -> &core::Vec {
+        &self.normal_albedo
+    }
+
+    fn sampling_for(&self, i: &core::Vec) -> &core::PiecewiseConstant2D {
+        let theta_in = f32::acos(core::clamp(f32::abs(i.z), 0.0, 1.0));
+        let row = (theta_in / std::f32::consts::FRAC_PI_2 * SAMPLING_THETA_IN_RES as f32) as usize;
+        &self.sampling[usize::min(row, SAMPLING_THETA_IN_RES - 1)]
+    }
+
+    /// Samples an outgoing direction in proportion to the tabulated luminance of the reflected
+    /// light. Returns the direction and its pdf with respect to solid angle.
+    pub fn sample(&self, i: &core::Vec, rng: &mut rand::XorShiftRng) -> (core::Vec, f32) {
+        let dist = self.sampling_for(i);
+        let ((u, v), uv_pdf) = dist.sample_continuous(rng.next_f32(), rng.next_f32());
+        let theta = v * std::f32::consts::FRAC_PI_2;
+        let phi = u * core::TWO_PI + f32::atan2(i.y, i.x);
+        let (sin_theta, cos_theta) = f32::sin_cos(theta);
+        let cos_theta = if i.z < 0.0 { -cos_theta } else { cos_theta };
+        let o = core::Vec::new(sin_theta * f32::cos(phi), sin_theta * f32::sin(phi), cos_theta);
+
+        let pdf = if sin_theta == 0.0 {
+            0.0
+        }
+        else {
+            uv_pdf / (std::f32::consts::FRAC_PI_2 * core::TWO_PI * sin_theta)
+        };
+        (o, pdf)
+    }
+
+    /// Returns the pdf with respect to solid angle that sample chooses the outgoing direction.
+    pub fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        if !i.is_local_same_hemisphere(o) {
+            return 0.0;
+        }
+
+        // Find theta with atan2 rather than acos, which is imprecise near the normal.
+        let sin_theta = f32::sqrt(o.x * o.x + o.y * o.y);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        let theta = f32::atan2(sin_theta, f32::abs(o.z));
+
+        let mut phi = f32::atan2(o.y, o.x) - f32::atan2(i.y, i.x);
+        if phi < 0.0 {
+            phi += core::TWO_PI;
+        }
+        let u = phi / core::TWO_PI;
+        let v = theta / std::f32::consts::FRAC_PI_2;
+        self.sampling_for(i).pdf(u, v)
+                / (std::f32::consts::FRAC_PI_2 * core::TWO_PI * f32::sin(theta))
+    }
+
+    /// The approximate directional albedo, in terms of luminance, from the incoming direction.
+    pub fn albedo(&self, i: &core::Vec) -> f32 {
+        self.sampling_for(i).integral() * std::f32::consts::FRAC_PI_2 * core::TWO_PI
+    }
+}
+
+fn mirror(v: &core::Vec) -> core::Vec {
+    core::Vec::new(v.x, v.y, -v.z)
+}
+
+fn rotate_z(v: &core::Vec, angle: f32) -> core::Vec {
+    let (sin, cos) = f32::sin_cos(angle);
+    core::Vec::new(cos * v.x - sin * v.y, sin * v.x + cos * v.y, v.z)
+}
+
+fn rotate_y(v: &core::Vec, angle: f32) -> core::Vec {
+    let (sin, cos) = f32::sin_cos(angle);
+    core::Vec::new(cos * v.x + sin * v.z, v.y, cos * v.z - sin * v.x)
+}
+
+/// The theta_half axis is sampled more densely near the normal, where specular peaks are.
+fn theta_half_index(theta_half: f32) -> usize {
+    if theta_half <= 0.0 {
+        return 0;
+    }
+    let x = f32::sqrt(theta_half / std::f32::consts::FRAC_PI_2) * THETA_HALF_RES as f32;
+    usize::min(x as usize, THETA_HALF_RES - 1)
+}
+
+fn theta_diff_index(theta_diff: f32) -> usize {
+    let x = theta_diff / std::f32::consts::FRAC_PI_2 * THETA_DIFF_RES as f32;
+    core::clamp(x as usize, 0, THETA_DIFF_RES - 1)
+}
+
+fn phi_diff_index(phi_diff: f32) -> usize {
+    // Reciprocity: phi_diff and phi_diff + pi are the same.
+    let phi_diff = if phi_diff < 0.0 { phi_diff + std::f32::consts::PI } else { phi_diff };
+    let x = phi_diff / std::f32::consts::PI * PHI_DIFF_RES as f32;
+    core::clamp(x as usize, 0, PHI_DIFF_RES - 1)
+}
+
+/// A reflection lobe that looks up a measured BRDF, and samples it using the BRDF's tabulated
+/// importance distribution instead of cosine sampling.
+pub struct MeasuredRefl {
+    brdf: Arc<MerlBrdf>,
+}
+
+impl MeasuredRefl {
+    pub fn new(brdf: Arc<MerlBrdf>) -> MeasuredRefl {
+        MeasuredRefl {brdf}
+    }
+}
+
+impl lobes::Lobe for MeasuredRefl {
+    fn f(&self, i: &core::Vec, o: &core::Vec, _: bool) -> core::Vec {
+        self.brdf.eval(i, o)
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        self.brdf.pdf(i, o)
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> lobes::LobeSample
+    {
+        if i.z == 0.0 {
+            return lobes::LobeSample::zero();
+        }
+
+        let (o, pdf) = self.brdf.sample(i, rng);
+        lobes::LobeSample {
+            result: self.f(i, &o, camera_to_light),
+            outgoing: o,
+            pdf
+        }
+    }
+
+    fn kind(&self) -> lobes::LobeKind {
+        lobes::LobeKind::LOBE_GLOSSY | lobes::LobeKind::LOBE_REFLECTION
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        self.brdf.albedo(i)
+    }
+}
+
+impl Display for MeasuredRefl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MeasuredRefl(normal_albedo={})", self.brdf.normal_albedo)
+    }
+}
@@ -1,10 +1,40 @@
+mod energy;
+
+mod hair;
+pub use material::hair::HairLobe;
+
+mod ies;
+pub use material::ies::IesProfile;
+
+mod library;
+pub use material::library::{MaterialLibrary, MaterialPreset, PresetKind, PresetValue, NodePreset,
+        NodeKind};
+
 mod lights;
+pub use material::lights::{Light, DiffuseAreaLight, LightUnits, LUMINOUS_EFFICACY};
 
 mod lobes;
 pub use material::lobes::*;
 
 mod material;
-pub use material::material::{Material, MaterialSample};
+pub use material::material::{Material, MaterialSample, SharedLobe, Bsdf, DetachedBsdf,
+        SurfaceOptions, SurfaceBuilder};
+
+mod merl;
+pub use material::merl::{MerlBrdf, MeasuredRefl};
+
+mod nodes;
+pub use material::nodes::ShaderNode;
+
+mod procedural;
+pub use material::procedural::*;
+
+mod shaders;
+pub use material::shaders::{Shader, LobeList, DisneyShader, ConductorShader, MatteShader,
+        HairShader, MixShader, LayeredShader};
+
+mod textures;
+pub use material::textures::*;
 
 mod util;
 pub use material::util::*;
@@ -0,0 +1,104 @@
+use material::textures;
+use material::util;
+
+use core;
+use geom;
+
+use std::sync::Arc;
+
+/// A node in a shader graph, which computes a color at each shading point from the colors of its
+/// input nodes. Scalars are represented as gray colors, and scalar material parameters use the
+/// luminance of the result, like they do for textures. Nodes are textures themselves, so a graph
+/// can be bound to any textured material parameter, e.g. with
+/// `DisneyMaterialBuilder::roughness_texture`. Inputs are shared, so a node can feed several
+/// others.
+pub enum ShaderNode {
+    Constant(core::Vec),
+    /// Looks up a texture, at the texture coordinates computed by the given node if there is one,
+    /// or else at the surface's texture coordinates.
+    Texture(Arc<dyn textures::Texture>, Option<Arc<ShaderNode>>),
+    /// The world-space shading normal, with components in [-1, 1].
+    Normal,
+    /// The texture coordinates, in the x and y components.
+    Uv,
+    /// The world-space position.
+    Position,
+    /// The cosine of the angle between the shading normal and the incoming direction: one when the
+    /// surface faces the viewer and zero at grazing angles. Surfaces shaded without an incoming
+    /// direction are treated as facing the viewer.
+    FacingRatio,
+    /// The Fresnel reflectance of a dielectric with the given index of refraction, as seen from
+    /// the incoming direction.
+    Fresnel(f32),
+    /// Interpolates between the first and second inputs by the third, per color channel.
+    Mix(Arc<ShaderNode>, Arc<ShaderNode>, Arc<ShaderNode>),
+    /// Multiplies the inputs per color channel.
+    Multiply(Arc<ShaderNode>, Arc<ShaderNode>),
+    /// Linearly maps the input from the range [from_min, from_max] to [to_min, to_max], per color
+    /// channel. Values outside of the range are extrapolated.
+    Remap {input: Arc<ShaderNode>, from_min: f32, from_max: f32, to_min: f32, to_max: f32},
+    /// Clamps the input to [min, max], per color channel.
+    Clamp {input: Arc<ShaderNode>, min: f32, max: f32},
+}
+
+impl ShaderNode {
+    fn facing_ratio(surface_props: &geom::SurfaceProperties) -> f32 {
+        if surface_props.incoming.is_exactly_zero() {
+            1.0
+        }
+        else {
+            core::clamp_unit(f32::abs(surface_props.incoming.dot(&surface_props.normal)))
+        }
+    }
+
+    fn map(input: &core::Vec, f: &dyn Fn(f32) -> f32) -> core::Vec {
+        core::Vec::new(f(input.x), f(input.y), f(input.z))
+    }
+}
+
+impl textures::Texture for ShaderNode {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        match *self {
+            ShaderNode::Constant(color) => color,
+            ShaderNode::Texture(ref texture, ref uv) => match *uv {
+                Some(ref uv) => {
+                    let mut lookup_props = surface_props.clone();
+                    lookup_props.uv = uv.eval(surface_props);
+                    texture.eval(&lookup_props)
+                },
+                None => texture.eval(surface_props)
+            },
+            ShaderNode::Normal => surface_props.normal,
+            ShaderNode::Uv => core::Vec::new(surface_props.uv.x, surface_props.uv.y, 0.0),
+            ShaderNode::Position => surface_props.position,
+            ShaderNode::FacingRatio => {
+                ShaderNode::facing_ratio(surface_props) * &core::Vec::one()
+            },
+            ShaderNode::Fresnel(ior) => {
+                let fresnel = util::fresnel_dielectric(
+                        ShaderNode::facing_ratio(surface_props), ior);
+                fresnel * &core::Vec::one()
+            },
+            ShaderNode::Mix(ref a, ref b, ref t) => {
+                let (a, b, t) = (a.eval(surface_props), b.eval(surface_props),
+                        t.eval(surface_props));
+                core::Vec::new(
+                        core::lerp(a.x, b.x, t.x), core::lerp(a.y, b.y, t.y),
+                        core::lerp(a.z, b.z, t.z))
+            },
+            ShaderNode::Multiply(ref a, ref b) => {
+                a.eval(surface_props).comp_mult(&b.eval(surface_props))
+            },
+            ShaderNode::Remap {ref input, from_min, from_max, to_min, to_max} => {
+                let from_range = from_max - from_min;
+                ShaderNode::map(&input.eval(surface_props), &|x| {
+                    let t = if from_range == 0.0 { 0.0 } else { (x - from_min) / from_range };
+                    core::lerp(to_min, to_max, t)
+                })
+            },
+            ShaderNode::Clamp {ref input, min, max} => {
+                ShaderNode::map(&input.eval(surface_props), &|x| core::clamp(x, min, max))
+            },
+        }
+    }
+}
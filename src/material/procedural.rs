@@ -0,0 +1,354 @@
+use material::textures;
+
+use core;
+use geom;
+
+use std;
+use rand;
+use rand::{Rng, SeedableRng};
+
+/// The space in which a procedural texture is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSpace {
+    /// The texture coordinates of the surface. Textures evaluated in this space are planar.
+    Uv,
+    /// The position of the shading point before the prim's transform was applied.
+    Object,
+    /// The position of the shading point in world space.
+    World,
+}
+
+/// Maps a shading point into the coordinate system of a procedural texture. The point is scaled
+/// and then offset, so a scale of 4 makes a pattern repeat four times as often.
+#[derive(Clone, Copy)]
+pub struct TextureMapping {
+    pub space: TextureSpace,
+    pub scale: core::Vec,
+    pub offset: core::Vec,
+}
+
+impl TextureMapping {
+    pub fn new(space: TextureSpace, scale: core::Vec, offset: core::Vec) -> TextureMapping {
+        TextureMapping {space, scale, offset}
+    }
+
+    /// Maps the shading point without any scale or offset.
+    pub fn identity(space: TextureSpace) -> TextureMapping {
+        TextureMapping::new(space, core::Vec::one(), core::Vec::zero())
+    }
+
+    pub fn map(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = match self.space {
+            TextureSpace::Uv => core::Vec::new(surface_props.uv.x, surface_props.uv.y, 0.0),
+            TextureSpace::Object => surface_props.object_position,
+            TextureSpace::World => surface_props.position,
+        };
+        &p.comp_mult(&self.scale) + &self.offset
+    }
+
+    /// Whether patterns should ignore the z-coordinate, i.e. for texture coordinates.
+    pub fn is_planar(&self) -> bool {
+        self.space == TextureSpace::Uv
+    }
+}
+
+/// Alternates between two colors in a grid of unit squares (or cubes, in 3D spaces).
+pub struct CheckerTexture {
+    pub mapping: TextureMapping,
+    pub color_a: textures::MaterialParam<core::Vec>,
+    pub color_b: textures::MaterialParam<core::Vec>,
+}
+
+impl CheckerTexture {
+    pub fn new(mapping: TextureMapping, color_a: textures::MaterialParam<core::Vec>,
+        color_b: textures::MaterialParam<core::Vec>) -> CheckerTexture
+    {
+        CheckerTexture {mapping, color_a, color_b}
+    }
+}
+
+impl textures::Texture for CheckerTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = self.mapping.map(surface_props);
+        let mut sum = f32::floor(p.x) as i64 + f32::floor(p.y) as i64;
+        if !self.mapping.is_planar() {
+            sum += f32::floor(p.z) as i64;
+        }
+
+        if sum.rem_euclid(2) == 0 {
+            self.color_a.eval(surface_props)
+        }
+        else {
+            self.color_b.eval(surface_props)
+        }
+    }
+}
+
+/// Draws lines of the given width along the integer coordinates over a background color.
+pub struct GridTexture {
+    pub mapping: TextureMapping,
+    pub line_width: f32,
+    pub line_color: textures::MaterialParam<core::Vec>,
+    pub background_color: textures::MaterialParam<core::Vec>,
+}
+
+impl GridTexture {
+    pub fn new(mapping: TextureMapping, line_width: f32,
+        line_color: textures::MaterialParam<core::Vec>,
+        background_color: textures::MaterialParam<core::Vec>) -> GridTexture
+    {
+        GridTexture {mapping, line_width, line_color, background_color}
+    }
+}
+
+impl textures::Texture for GridTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = self.mapping.map(surface_props);
+        let dims = if self.mapping.is_planar() { 2 } else { 3 };
+        let half_width = 0.5 * self.line_width;
+        let on_line = (0..dims).any(|i| f32::abs(p[i] - f32::round(p[i])) < half_width);
+
+        if on_line {
+            self.line_color.eval(surface_props)
+        }
+        else {
+            self.background_color.eval(surface_props)
+        }
+    }
+}
+
+/// The shape of a gradient.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GradientKind {
+    /// Ramps along the x-axis from 0 to 1.
+    Linear,
+    /// Ramps with the distance from the origin from 0 to 1.
+    Radial,
+}
+
+/// Blends between two colors according to the position along a ramp; the ramp is clamped
+/// outside of [0, 1].
+pub struct GradientTexture {
+    pub mapping: TextureMapping,
+    pub kind: GradientKind,
+    pub color_a: textures::MaterialParam<core::Vec>,
+    pub color_b: textures::MaterialParam<core::Vec>,
+}
+
+impl GradientTexture {
+    pub fn new(mapping: TextureMapping, kind: GradientKind,
+        color_a: textures::MaterialParam<core::Vec>,
+        color_b: textures::MaterialParam<core::Vec>) -> GradientTexture
+    {
+        GradientTexture {mapping, kind, color_a, color_b}
+    }
+}
+
+impl textures::Texture for GradientTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = self.mapping.map(surface_props);
+        let t = match self.kind {
+            GradientKind::Linear => p.x,
+            GradientKind::Radial => {
+                if self.mapping.is_planar() {
+                    f32::sqrt(p.x * p.x + p.y * p.y)
+                }
+                else {
+                    p.magnitude()
+                }
+            }
+        };
+        self.color_a.eval(surface_props).lerp(
+                &self.color_b.eval(surface_props), core::clamp_unit(t))
+    }
+}
+
+/// Ken Perlin's improved gradient noise; see "Improving Noise" (SIGGRAPH 2002).
+struct PerlinNoise {
+    /// Random permutation of 0..256, repeated twice to avoid wrapping indices.
+    perm: std::vec::Vec<usize>,
+}
+
+impl PerlinNoise {
+    fn new(seed: u32) -> PerlinNoise {
+        let mut rng = rand::XorShiftRng::from_seed([seed, 0x9e3779b9, 0x7f4a7c15, 0x2545f491]);
+        let mut perm: std::vec::Vec<usize> = (0..256).collect();
+        rng.shuffle(&mut perm);
+        let repeated = perm.clone();
+        perm.extend(repeated);
+        PerlinNoise {perm}
+    }
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+        let h = hash & 15;
+        let u = if h < 8 { x } else { y };
+        let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    }
+
+    /// Returns noise in roughly [-1, 1] that's zero at integer coordinates.
+    fn noise(&self, p: &core::Vec) -> f32 {
+        let (fx, fy, fz) = (f32::floor(p.x), f32::floor(p.y), f32::floor(p.z));
+        let xi = (fx as i64 & 255) as usize;
+        let yi = (fy as i64 & 255) as usize;
+        let zi = (fz as i64 & 255) as usize;
+        let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+        let (u, v, w) = (PerlinNoise::fade(x), PerlinNoise::fade(y), PerlinNoise::fade(z));
+
+        let perm = &self.perm;
+        let a = perm[xi] + yi;
+        let aa = perm[a] + zi;
+        let ab = perm[a + 1] + zi;
+        let b = perm[xi + 1] + yi;
+        let ba = perm[b] + zi;
+        let bb = perm[b + 1] + zi;
+
+        let grad = PerlinNoise::grad;
+        core::lerp(
+            core::lerp(
+                core::lerp(grad(perm[aa], x, y, z), grad(perm[ba], x - 1.0, y, z), u),
+                core::lerp(grad(perm[ab], x, y - 1.0, z), grad(perm[bb], x - 1.0, y - 1.0, z), u),
+                v),
+            core::lerp(
+                core::lerp(grad(perm[aa + 1], x, y, z - 1.0),
+                        grad(perm[ba + 1], x - 1.0, y, z - 1.0), u),
+                core::lerp(grad(perm[ab + 1], x, y - 1.0, z - 1.0),
+                        grad(perm[bb + 1], x - 1.0, y - 1.0, z - 1.0), u),
+                v),
+            w)
+    }
+}
+
+/// Fractal Brownian motion built from octaves of Perlin noise. The noise value is remapped to
+/// [0, 1] and used to blend between two colors.
+pub struct NoiseTexture {
+    pub mapping: TextureMapping,
+    pub octaves: usize,
+    /// Frequency multiplier between successive octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between successive octaves.
+    pub gain: f32,
+    pub color_a: textures::MaterialParam<core::Vec>,
+    pub color_b: textures::MaterialParam<core::Vec>,
+    noise: PerlinNoise,
+}
+
+impl NoiseTexture {
+    pub fn new(mapping: TextureMapping, octaves: usize, seed: u32,
+        color_a: textures::MaterialParam<core::Vec>,
+        color_b: textures::MaterialParam<core::Vec>) -> NoiseTexture
+    {
+        NoiseTexture {
+            mapping,
+            octaves,
+            lacunarity: 2.0,
+            gain: 0.5,
+            color_a,
+            color_b,
+            noise: PerlinNoise::new(seed),
+        }
+    }
+
+    /// Returns the fBm value in roughly [-1, 1].
+    pub fn fbm(&self, p: &core::Vec) -> f32 {
+        let mut sum = 0.0;
+        let mut total_amplitude = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        for _ in 0..self.octaves {
+            sum += amplitude * self.noise.noise(&(p * frequency));
+            total_amplitude += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+
+        if total_amplitude > 0.0 { sum / total_amplitude } else { 0.0 }
+    }
+}
+
+impl textures::Texture for NoiseTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = self.mapping.map(surface_props);
+        let t = core::clamp_unit(0.5 + 0.5 * self.fbm(&p));
+        self.color_a.eval(surface_props).lerp(&self.color_b.eval(surface_props), t)
+    }
+}
+
+/// Hashes integer cell coordinates into a pseudo-random 32-bit value.
+fn hash_cell(x: i64, y: i64, z: i64, seed: u32) -> u32 {
+    let mut h = seed.wrapping_mul(0x27d4eb2d) ^ (x as u32).wrapping_mul(0x8da6b343) ^
+            (y as u32).wrapping_mul(0xd8163841) ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^= h >> 16;
+    h
+}
+
+/// Converts the hash into a value in [0, 1) and advances it.
+fn next_hash_float(h: &mut u32) -> f32 {
+    *h = hash_cell(*h as i64, 0, 0, 0x68e31da4);
+    (*h >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Cellular noise after Worley, "A Cellular Texture Basis Function" (SIGGRAPH 1996). Each unit
+/// cell contains one randomly-placed feature point, and the distance to the nearest feature point
+/// is used to blend between two colors.
+pub struct WorleyTexture {
+    pub mapping: TextureMapping,
+    /// How far feature points may stray from the cell centers, in [0, 1].
+    pub jitter: f32,
+    pub seed: u32,
+    pub color_a: textures::MaterialParam<core::Vec>,
+    pub color_b: textures::MaterialParam<core::Vec>,
+}
+
+impl WorleyTexture {
+    pub fn new(mapping: TextureMapping, seed: u32, color_a: textures::MaterialParam<core::Vec>,
+        color_b: textures::MaterialParam<core::Vec>) -> WorleyTexture
+    {
+        WorleyTexture {mapping, jitter: 1.0, seed, color_a, color_b}
+    }
+
+    /// Returns the distance from the point to the nearest feature point.
+    pub fn nearest_distance(&self, p: &core::Vec) -> f32 {
+        let planar = self.mapping.is_planar();
+        let (cx, cy, cz) = (
+                f32::floor(p.x) as i64, f32::floor(p.y) as i64, f32::floor(p.z) as i64);
+        let z_range = if planar { 0..1 } else { -1..2 };
+
+        let mut nearest = f32::INFINITY;
+        for dz in z_range {
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let (x, y, z) = (cx + dx, cy + dy, cz + dz);
+                    let mut h = hash_cell(x, y, z, self.seed);
+                    let feature = core::Vec::new(
+                            x as f32 + 0.5 + self.jitter * (next_hash_float(&mut h) - 0.5),
+                            y as f32 + 0.5 + self.jitter * (next_hash_float(&mut h) - 0.5),
+                            if planar {
+                                p.z
+                            }
+                            else {
+                                z as f32 + 0.5 + self.jitter * (next_hash_float(&mut h) - 0.5)
+                            });
+                    nearest = f32::min(nearest, (&feature - p).magnitude());
+                }
+            }
+        }
+        nearest
+    }
+}
+
+impl textures::Texture for WorleyTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        let p = self.mapping.map(surface_props);
+        let t = core::clamp_unit(self.nearest_distance(&p));
+        self.color_a.eval(surface_props).lerp(&self.color_b.eval(surface_props), t)
+    }
+}
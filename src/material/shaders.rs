@@ -0,0 +1,494 @@
+use material::hair;
+use material::lobes;
+use material::material;
+use material::textures;
+use material::util;
+
+use core;
+use geom;
+
+use std;
+use std::fmt;
+use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::Arc;
+use rand;
+
+/// The lobes of a material at a shading point. Materials whose lobes don't vary over the surface
+/// lend out their lobes; otherwise the lobes are built for the shading point.
+pub enum LobeList<'a> {
+    Borrowed(&'a [Box<dyn lobes::Lobe>]),
+    Owned(std::vec::Vec<Box<dyn lobes::Lobe>>),
+}
+
+impl<'a> Deref for LobeList<'a> {
+    type Target = [Box<dyn lobes::Lobe>];
+
+    fn deref(&self) -> &[Box<dyn lobes::Lobe>] {
+        match *self {
+            LobeList::Borrowed(x) => x,
+            LobeList::Owned(ref x) => x
+        }
+    }
+}
+
+/// Builds the lobes of a material at a shading point, e.g. by evaluating textured parameters.
+pub trait Shader : Sync + Send {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>;
+
+    /// Whether the lobes are the same at every shading point, so they can be built once.
+    fn is_constant(&self) -> bool;
+}
+
+/// Builds lobes that form the Disney principled BSDF from possibly-textured parameters.
+/// See DisneyMaterialBuilder for a description of the parameters.
+#[derive(Clone)]
+pub struct DisneyShader {
+    pub base_color: textures::MaterialParam<core::Vec>,
+    pub roughness: textures::MaterialParam<f32>,
+    pub anisotropic: textures::MaterialParam<f32>,
+    pub ior: f32,
+    pub metallic: textures::MaterialParam<f32>,
+    pub specular_trans: textures::MaterialParam<f32>,
+    pub specular_tint: textures::MaterialParam<f32>,
+    pub sheen: textures::MaterialParam<f32>,
+    pub sheen_tint: textures::MaterialParam<f32>,
+    pub clearcoat: textures::MaterialParam<f32>,
+    pub clearcoat_gloss: textures::MaterialParam<f32>,
+    pub subsurface: textures::MaterialParam<f32>,
+    pub subsurface_radius: core::Vec,
+    pub thin: bool,
+    pub diff_trans: textures::MaterialParam<f32>,
+    pub flatness: textures::MaterialParam<f32>,
+    pub thin_film_thickness: textures::MaterialParam<f32>,
+    pub thin_film_ior: f32,
+}
+
+impl Shader for DisneyShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        let base_color = self.base_color.eval(surface_props);
+        let roughness = core::clamp_unit(self.roughness.eval(surface_props));
+        let anisotropic = core::clamp_unit(self.anisotropic.eval(surface_props));
+        let metallic = core::clamp_unit(self.metallic.eval(surface_props));
+        let specular_trans = core::clamp_unit(self.specular_trans.eval(surface_props));
+        let specular_tint = core::clamp_unit(self.specular_tint.eval(surface_props));
+        let sheen = self.sheen.eval(surface_props);
+        let sheen_tint = core::clamp_unit(self.sheen_tint.eval(surface_props));
+        let clearcoat = self.clearcoat.eval(surface_props);
+        let clearcoat_gloss = core::clamp_unit(self.clearcoat_gloss.eval(surface_props));
+        let subsurface = core::clamp_unit(self.subsurface.eval(surface_props));
+        let diff_trans = core::clamp_unit(self.diff_trans.eval(surface_props));
+        let flatness = core::clamp_unit(self.flatness.eval(surface_props));
+        let thin_film_thickness = eval_thin_film_thickness(
+                &self.thin_film_thickness, surface_props);
+
+        // Combo of three models: diffuse_weight + trans_weight + metallic = 1.0
+        let diffuse_weight = (1.0 - metallic) * (1.0 - specular_trans);
+        let trans_weight = (1.0 - metallic) * specular_trans;
+        let mut lobes_list = std::vec::Vec::<Box<dyn lobes::Lobe>>::new();
+
+        // Perfectly smooth glass reflects and refracts along single directions; the microfacet
+        // lobes become numerically unstable here. Its share of the specular reflection moves to
+        // the smooth dielectric lobe, which chooses between the two by the same Fresnel term.
+        // With a thin film, the reflection stays with the film's lobe for its interference.
+        let smooth_glass = !self.thin && roughness == 0.0 && trans_weight > 0.0;
+        let smooth_glass_reflects = smooth_glass && self.ior > 1.0 && thin_film_thickness <= 0.0;
+
+        // Diffuse, retro-reflection, and sheen
+        if self.thin {
+            // Thin surfaces have no interior, so they approximate subsurface scattering with
+            // flatness, and split the diffuse light between reflection and transmission.
+            let refl_weight = diffuse_weight * (1.0 - diff_trans);
+            if refl_weight > 0.0 {
+                lobes_list.push(Box::new(lobes::DisneyDiffuseRefl::new_thin(
+                        base_color, roughness, sheen, sheen_tint, refl_weight, flatness)));
+            }
+
+            let trans_weight = diffuse_weight * diff_trans;
+            if trans_weight > 0.0 {
+                lobes_list.push(Box::new(lobes::DiffuseTrans::new(&base_color * trans_weight)));
+            }
+        }
+        else if diffuse_weight * (1.0 - subsurface) > 0.0 {
+            lobes_list.push(Box::new(lobes::DisneyDiffuseRefl::new(
+                    base_color, roughness, sheen, sheen_tint, diffuse_weight * (1.0 - subsurface))));
+        }
+
+        // Subsurface scattering replaces part of the diffuse lobe; the base color is reached by
+        // multiple scattering in the medium.
+        let subsurface_weight = diffuse_weight * subsurface;
+        if !self.thin && subsurface_weight > 0.0 {
+            let medium = util::SubsurfaceMedium::from_color_and_distance(
+                    &base_color, &self.subsurface_radius);
+            lobes_list.push(Box::new(lobes::DisneySubsurface::new(
+                    &core::Vec::one() * subsurface_weight, medium)));
+        }
+
+        // Specular reflection, optionally through a thin film
+        if self.ior > 1.0 && thin_film_thickness > 0.0 {
+            let fresnel = util::ThinFilmFresnel::new(
+                    &util::DisneyFresnel::new(
+                            f32::max(self.ior, 1.01), base_color, specular_tint, metallic),
+                    thin_film_thickness, self.thin_film_ior);
+            lobes_list.push(Box::new(lobes::ThinFilmRefl::new_aniso(
+                    fresnel, roughness, anisotropic)))
+        }
+        else if self.ior > 1.0 {
+            let weight = if smooth_glass_reflects { 1.0 - trans_weight } else { 1.0 };
+            if weight > 0.0 {
+                lobes_list.push(Box::new(lobes::DisneySpecularRefl::new_weighted(
+                        base_color, roughness, anisotropic, self.ior, specular_tint, metallic,
+                        weight)))
+            }
+        }
+
+        // Clearcoat (second specular lobe)
+        if clearcoat > 0.0 {
+            lobes_list.push(Box::new(lobes::DisneyClearcoatRefl::new(
+                    clearcoat, clearcoat_gloss)));
+        }
+
+        // Specular transmission
+        if trans_weight > 0.0 {
+            // PBRT suggests that we take scale up the base color to its sqrt
+            // for art-direction purposes; it makes it so that light that enters and exits
+            // will have the base color instead of being darker.
+            let specular_trans_color = trans_weight * &base_color.sqrt();
+            if self.thin {
+                lobes_list.push(Box::new(lobes::DisneyThinTrans::new_aniso(
+                        specular_trans_color, roughness, anisotropic, self.ior)));
+            }
+            else if smooth_glass {
+                let reflect_color = if smooth_glass_reflects {
+                    trans_weight * &core::Vec::one().lerp(&base_color.tint(), specular_tint)
+                }
+                else {
+                    core::Vec::zero()
+                };
+                lobes_list.push(Box::new(lobes::SmoothDielectric::new(
+                        reflect_color, specular_trans_color, self.ior)));
+            }
+            else {
+                lobes_list.push(Box::new(lobes::DisneySpecularTrans::new_aniso(
+                        specular_trans_color, roughness, anisotropic, self.ior)));
+            }
+        }
+
+        lobes_list
+    }
+
+    fn is_constant(&self) -> bool {
+        self.base_color.is_constant() &&
+                self.roughness.is_constant() &&
+                self.anisotropic.is_constant() &&
+                self.metallic.is_constant() &&
+                self.specular_trans.is_constant() &&
+                self.specular_tint.is_constant() &&
+                self.sheen.is_constant() &&
+                self.sheen_tint.is_constant() &&
+                self.clearcoat.is_constant() &&
+                self.clearcoat_gloss.is_constant() &&
+                self.subsurface.is_constant() &&
+                self.diff_trans.is_constant() &&
+                self.flatness.is_constant() &&
+                self.thin_film_thickness.is_constant()
+    }
+}
+
+/// The largest thin-film thickness, in nanometers, that a texture can specify.
+const MAX_THIN_FILM_THICKNESS: f32 = 1000.0;
+
+fn eval_thin_film_thickness(
+    thickness: &textures::MaterialParam<f32>, surface_props: &geom::SurfaceProperties) -> f32
+{
+    // Textures can only hold values in [0, 1], so they're scaled to the range of thicknesses.
+    let thickness = match *thickness {
+        textures::MaterialParam::Constant(x) => x,
+        textures::MaterialParam::Texture(ref texture) =>
+                MAX_THIN_FILM_THICKNESS * texture.eval(surface_props).luminance()
+    };
+    f32::max(thickness, 0.0)
+}
+
+/// Builds a rough conductor lobe, optionally coated with a thin film of possibly-textured
+/// thickness. See ConductorMaterialBuilder for a description of the parameters.
+#[derive(Clone)]
+pub struct ConductorShader {
+    pub fresnel: util::ConductorFresnel,
+    pub roughness: f32,
+    pub anisotropic: f32,
+    pub thin_film_thickness: textures::MaterialParam<f32>,
+    pub thin_film_ior: f32,
+}
+
+impl Shader for ConductorShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        let roughness = core::clamp_unit(self.roughness);
+        let anisotropic = core::clamp_unit(self.anisotropic);
+        let thin_film_thickness = eval_thin_film_thickness(
+                &self.thin_film_thickness, surface_props);
+        if thin_film_thickness > 0.0 {
+            let fresnel = util::ThinFilmFresnel::new(
+                    &self.fresnel, thin_film_thickness, self.thin_film_ior);
+            vec![Box::new(lobes::ThinFilmRefl::new_aniso(fresnel, roughness, anisotropic))]
+        }
+        else {
+            vec![Box::new(lobes::ConductorRefl::new_aniso(
+                    self.fresnel.clone(), roughness, anisotropic))]
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.thin_film_thickness.is_constant()
+    }
+}
+
+/// The largest roughness angle of a matte material, in degrees.
+const MAX_SIGMA: f32 = 90.0;
+
+/// Builds a Lambertian or Oren-Nayar diffuse lobe from possibly-textured parameters.
+/// See MatteMaterialBuilder for a description of the parameters.
+#[derive(Clone)]
+pub struct MatteShader {
+    pub color: textures::MaterialParam<core::Vec>,
+    pub sigma: textures::MaterialParam<f32>,
+}
+
+impl Shader for MatteShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        let color = self.color.eval(surface_props);
+        // Textures can only hold values in [0, 1], so they're scaled to the range of angles.
+        let sigma = match self.sigma {
+            textures::MaterialParam::Constant(x) => x,
+            textures::MaterialParam::Texture(ref texture) =>
+                    MAX_SIGMA * texture.eval(surface_props).luminance()
+        };
+        let sigma = core::clamp(sigma, 0.0, MAX_SIGMA);
+        if sigma == 0.0 {
+            vec![Box::new(lobes::PerfectDiffuse::new(color))]
+        }
+        else {
+            vec![Box::new(lobes::OrenNayar::new(color, sigma))]
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.color.is_constant() && self.sigma.is_constant()
+    }
+}
+
+/// Builds a hair lobe for a fiber. The offset across the width of the fiber comes from the v
+/// texture coordinate, which must run from 0 to 1 across the fiber in the direction of the
+/// binormal, as it does on Curve prims.
+#[derive(Clone)]
+pub struct HairShader {
+    pub sigma_a: core::Vec,
+    pub beta_m: f32,
+    pub beta_n: f32,
+    pub alpha: f32,
+    pub ior: f32,
+}
+
+impl Shader for HairShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        debug_assert!(surface_props.uv.y >= 0.0 && surface_props.uv.y <= 1.0,
+                "hair needs a v coordinate across the fiber, got {}", surface_props.uv.y);
+        let h = -1.0 + 2.0 * surface_props.uv.y;
+        vec![Box::new(hair::HairLobe::new(
+                h, self.ior, self.sigma_a, self.beta_m, self.beta_n, self.alpha))]
+    }
+
+    fn is_constant(&self) -> bool {
+        false
+    }
+}
+
+/// Scales a lobe of another material.
+struct ScaledLobe {
+    lobe: material::SharedLobe,
+    scale: f32,
+}
+
+impl lobes::Lobe for ScaledLobe {
+    fn f(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
+        &self.lobe.f(i, o, camera_to_light) * self.scale
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        self.lobe.pdf(i, o)
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> lobes::LobeSample
+    {
+        let mut sample = self.lobe.sample_f(i, camera_to_light, rng);
+        sample.result = &sample.result * self.scale;
+        sample
+    }
+
+    fn kind(&self) -> lobes::LobeKind {
+        self.lobe.kind()
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        self.lobe.albedo(i) * self.scale
+    }
+
+    fn subsurface(&self) -> Option<util::SubsurfaceMedium> {
+        self.lobe.subsurface()
+    }
+}
+
+impl Display for ScaledLobe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ScaledLobe(lobe={}, scale={})", &*self.lobe, self.scale)
+    }
+}
+
+/// Blends the lobes of two materials by a mask, which is zero for material a and one for
+/// material b.
+pub struct MixShader {
+    a: Arc<material::Material>,
+    b: Arc<material::Material>,
+    mask: textures::MaterialParam<f32>,
+}
+
+impl MixShader {
+    pub fn new(
+        a: Arc<material::Material>, b: Arc<material::Material>,
+        mask: textures::MaterialParam<f32>) -> MixShader
+    {
+        MixShader {a, b, mask}
+    }
+}
+
+impl Shader for MixShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        let mask = core::clamp_unit(self.mask.eval(surface_props));
+        let mut lobes_list = std::vec::Vec::<Box<dyn lobes::Lobe>>::new();
+        for &(mat, scale) in &[(&self.a, 1.0 - mask), (&self.b, mask)] {
+            if scale > 0.0 {
+                for lobe in material::Material::shared_lobes(mat, surface_props) {
+                    lobes_list.push(Box::new(ScaledLobe {lobe, scale}));
+                }
+            }
+        }
+
+        lobes_list
+    }
+
+    fn is_constant(&self) -> bool {
+        self.mask.is_constant() && self.a.is_constant() && self.b.is_constant()
+    }
+}
+
+/// Attenuates a lobe of another material by a dielectric coating over it.
+struct CoatedLobe {
+    lobe: material::SharedLobe,
+    ior: f32,
+    tint: core::Vec,
+}
+
+impl CoatedLobe {
+    /// The fraction of light that enters the coating along the incoming direction, and leaves
+    /// along the outgoing direction, after being absorbed along the way.
+    fn attenuation(&self, i: &core::Vec, o: &core::Vec) -> core::Vec {
+        let cos_theta_in = i.abs_cos_theta();
+        let cos_theta_out = o.abs_cos_theta();
+        if cos_theta_in == 0.0 || cos_theta_out == 0.0 {
+            return core::Vec::zero();
+        }
+
+        let trans = (1.0 - util::fresnel_dielectric(cos_theta_in, self.ior)) *
+                (1.0 - util::fresnel_dielectric(cos_theta_out, self.ior));
+        let path_length = 0.5 / cos_theta_in + 0.5 / cos_theta_out;
+        &core::Vec::new(
+                f32::powf(self.tint.x, path_length),
+                f32::powf(self.tint.y, path_length),
+                f32::powf(self.tint.z, path_length)) * trans
+    }
+}
+
+impl lobes::Lobe for CoatedLobe {
+    fn f(&self, i: &core::Vec, o: &core::Vec, camera_to_light: bool) -> core::Vec {
+        self.lobe.f(i, o, camera_to_light).comp_mult(&self.attenuation(i, o))
+    }
+
+    fn pdf(&self, i: &core::Vec, o: &core::Vec) -> f32 {
+        self.lobe.pdf(i, o)
+    }
+
+    fn sample_f(&self, i: &core::Vec, camera_to_light: bool, rng: &mut rand::XorShiftRng)
+        -> lobes::LobeSample
+    {
+        let mut sample = self.lobe.sample_f(i, camera_to_light, rng);
+        sample.result = sample.result.comp_mult(&self.attenuation(i, &sample.outgoing));
+        sample
+    }
+
+    fn kind(&self) -> lobes::LobeKind {
+        self.lobe.kind()
+    }
+
+    fn albedo(&self, i: &core::Vec) -> f32 {
+        let trans = 1.0 - util::fresnel_dielectric(i.abs_cos_theta(), self.ior);
+        self.lobe.albedo(i) * trans * trans * self.tint.luminance()
+    }
+
+    fn subsurface(&self) -> Option<util::SubsurfaceMedium> {
+        self.lobe.subsurface()
+    }
+}
+
+impl Display for CoatedLobe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CoatedLobe(lobe={}, ior={}, tint={})", &*self.lobe, self.ior, self.tint)
+    }
+}
+
+/// Stacks a rough dielectric coating over the lobes of a base material. Energy reflected by the
+/// coating is removed from the base lobes via the Fresnel transmission into and out of the
+/// coating.
+pub struct LayeredShader {
+    base: Arc<material::Material>,
+    roughness: f32,
+    ior: f32,
+    tint: core::Vec,
+}
+
+impl LayeredShader {
+    pub fn new(base: Arc<material::Material>, roughness: f32, ior: f32, tint: core::Vec)
+        -> LayeredShader
+    {
+        LayeredShader {base, roughness, ior, tint}
+    }
+}
+
+impl Shader for LayeredShader {
+    fn lobes(&self, surface_props: &geom::SurfaceProperties)
+            -> std::vec::Vec<Box<dyn lobes::Lobe>>
+    {
+        let mut lobes_list = std::vec::Vec::<Box<dyn lobes::Lobe>>::new();
+        lobes_list.push(Box::new(lobes::DielectricRefl::new(self.roughness, self.ior)));
+        for lobe in material::Material::shared_lobes(&self.base, surface_props) {
+            lobes_list.push(Box::new(CoatedLobe {lobe, ior: self.ior, tint: self.tint}));
+        }
+
+        lobes_list
+    }
+
+    fn is_constant(&self) -> bool {
+        self.base.is_constant()
+    }
+}
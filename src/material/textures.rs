@@ -0,0 +1,268 @@
+use core;
+use geom;
+
+use std;
+use std::path::Path;
+use std::sync::Arc;
+use image;
+
+/// A color that varies over a surface. Textures are evaluated at a shading point, and are
+/// typically looked up by the texture coordinates in the surface properties.
+pub trait Texture : Sync + Send {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec;
+}
+
+/// A texture that has the same color everywhere.
+pub struct ConstantTexture {
+    pub color: core::Vec,
+}
+
+impl ConstantTexture {
+    pub fn new(color: core::Vec) -> ConstantTexture {
+        ConstantTexture {color}
+    }
+}
+
+impl Texture for ConstantTexture {
+    fn eval(&self, _: &geom::SurfaceProperties) -> core::Vec {
+        self.color
+    }
+}
+
+/// How texture coordinates outside of [0, 1) are mapped back onto an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    /// The image tiles infinitely.
+    Repeat,
+    /// The image tiles infinitely, with every other tile flipped so that edges line up.
+    Mirror,
+    /// The edge pixels extend infinitely.
+    Clamp,
+}
+
+impl WrapMode {
+    /// Maps an integer pixel coordinate into [0, size).
+    fn wrap(&self, x: isize, size: usize) -> usize {
+        let size = size as isize;
+        match *self {
+            WrapMode::Repeat => x.rem_euclid(size) as usize,
+            WrapMode::Mirror => {
+                let x = x.rem_euclid(2 * size);
+                (if x < size { x } else { 2 * size - 1 - x }) as usize
+            },
+            WrapMode::Clamp => core::clamp(x, 0, size - 1) as usize,
+        }
+    }
+}
+
+/// Decodes an sRGB-encoded value into linear space.
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    }
+    else {
+        f32::powf((x + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// A texture that looks up an image by the texture coordinates of the shading point, using
+/// bilinear filtering. The bottom-left of the image is at uv = (0, 0) and the top-right is at
+/// uv = (1, 1).
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear RGB pixels in rows from top to bottom.
+    pixels: std::vec::Vec<core::Vec>,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /// Creates a texture from linear RGB pixels, stored in rows from top to bottom.
+    pub fn new(width: usize, height: usize, pixels: std::vec::Vec<core::Vec>, wrap: WrapMode)
+        -> ImageTexture
+    {
+        assert!(width > 0 && height > 0, "image must not be empty");
+        assert!(pixels.len() == width * height, "expected {} pixels, got {}",
+                width * height, pixels.len());
+        ImageTexture {width, height, pixels, wrap}
+    }
+
+    /// Loads an image file. Floating-point formats (e.g. EXR and HDR) are assumed to be linear.
+    /// Integer formats (e.g. PNG and JPEG) are decoded from sRGB if srgb is set, which is
+    /// appropriate for colors, and are otherwise read as linear data, which is appropriate for
+    /// parameters like roughness.
+    pub fn from_file<P: AsRef<Path>>(path: P, wrap: WrapMode, srgb: bool)
+        -> Result<ImageTexture, String>
+    {
+        let img = match image::open(path.as_ref()) {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Couldn't load image {}: {}", path.as_ref().display(), e))
+        };
+        let is_float = matches!(img,
+                image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+        let decode = srgb && !is_float;
+
+        let rgb = img.to_rgb32f();
+        let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+        if width == 0 || height == 0 {
+            return Err(format!("Image {} is empty", path.as_ref().display()));
+        }
+
+        let pixels = rgb.pixels().map(|p| {
+            if decode {
+                core::Vec::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2]))
+            }
+            else {
+                core::Vec::new(p[0], p[1], p[2])
+            }
+        }).collect();
+        Ok(ImageTexture::new(width, height, pixels, wrap))
+    }
+
+    fn texel(&self, x: isize, y: isize) -> &core::Vec {
+        let col = self.wrap.wrap(x, self.width);
+        let row = self.wrap.wrap(y, self.height);
+        &self.pixels[core::index(row, col, self.width)]
+    }
+
+    /// Bilinearly filters the image at the given texture coordinates.
+    pub fn lookup(&self, u: f32, v: f32) -> core::Vec {
+        // Pixel centers are at half-integer coordinates.
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (f32::floor(x), f32::floor(y));
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = self.texel(x0, y0).lerp(self.texel(x0 + 1, y0), dx);
+        let bottom = self.texel(x0, y0 + 1).lerp(self.texel(x0 + 1, y0 + 1), dx);
+        top.lerp(&bottom, dy)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        self.lookup(surface_props.uv.x, surface_props.uv.y)
+    }
+}
+
+/// A material parameter that's either constant or varies over the surface according to a
+/// texture. Scalar parameters use the luminance of the texture.
+#[derive(Clone)]
+pub enum MaterialParam<T> {
+    Constant(T),
+    Texture(Arc<dyn Texture>),
+}
+
+impl<T> MaterialParam<T> {
+    pub fn is_constant(&self) -> bool {
+        match *self {
+            MaterialParam::Constant(_) => true,
+            MaterialParam::Texture(_) => false
+        }
+    }
+}
+
+impl From<core::Vec> for MaterialParam<core::Vec> {
+    fn from(val: core::Vec) -> MaterialParam<core::Vec> {
+        MaterialParam::Constant(val)
+    }
+}
+
+impl From<f32> for MaterialParam<f32> {
+    fn from(val: f32) -> MaterialParam<f32> {
+        MaterialParam::Constant(val)
+    }
+}
+
+impl<T> From<Arc<dyn Texture>> for MaterialParam<T> {
+    fn from(val: Arc<dyn Texture>) -> MaterialParam<T> {
+        MaterialParam::Texture(val)
+    }
+}
+
+impl MaterialParam<core::Vec> {
+    pub fn eval(&self, surface_props: &geom::SurfaceProperties) -> core::Vec {
+        match *self {
+            MaterialParam::Constant(x) => x,
+            MaterialParam::Texture(ref texture) => texture.eval(surface_props)
+        }
+    }
+}
+
+impl MaterialParam<f32> {
+    pub fn eval(&self, surface_props: &geom::SurfaceProperties) -> f32 {
+        match *self {
+            MaterialParam::Constant(x) => x,
+            MaterialParam::Texture(ref texture) => texture.eval(surface_props).luminance()
+        }
+    }
+}
+
+/// Perturbs the shading frame of a surface before lobes are evaluated.
+#[derive(Clone)]
+pub enum NormalMap {
+    /// A tangent-space normal map, where the red, green, and blue channels map from [0, 1] to
+    /// [-1, 1] along the tangent, binormal, and normal. Image textures should be loaded as linear
+    /// data.
+    TangentSpace(Arc<dyn Texture>),
+    /// A height field whose luminance displaces the surface along the normal. The slope of the
+    /// height field with respect to the texture coordinates is multiplied by the scale.
+    Bump(Arc<dyn Texture>, f32),
+}
+
+/// Step in texture coordinates used to differentiate bump maps.
+const BUMP_DELTA: f32 = 1.0 / 2048.0;
+
+impl NormalMap {
+    /// Returns the surface properties with the shading normal replaced by the mapped normal, and
+    /// the tangent and binormal made orthogonal to it. Returns the surface properties unchanged if
+    /// the mapped normal is degenerate or would flip the shading normal to the other side of the
+    /// geometric normal.
+    pub fn apply(&self, surface_props: &geom::SurfaceProperties) -> geom::SurfaceProperties {
+        let sp = surface_props;
+        let normal = match *self {
+            NormalMap::TangentSpace(ref texture) => {
+                let n = &(&texture.eval(sp) * 2.0) - &core::Vec::one();
+                &(&(n.x * &sp.tangent) + &(n.y * &sp.binormal)) + &(n.z * &sp.normal)
+            },
+            NormalMap::Bump(ref texture, scale) => {
+                let mut sp_du = sp.clone();
+                sp_du.uv.x += BUMP_DELTA;
+                let mut sp_dv = sp.clone();
+                sp_dv.uv.y += BUMP_DELTA;
+
+                let height = texture.eval(sp).luminance();
+                let dhdu = (texture.eval(&sp_du).luminance() - height) / BUMP_DELTA;
+                let dhdv = (texture.eval(&sp_dv).luminance() - height) / BUMP_DELTA;
+                &sp.normal - &(&(&(dhdu * &sp.tangent) + &(dhdv * &sp.binormal)) * scale)
+            }
+        };
+
+        let magnitude = normal.magnitude();
+        if !(magnitude > 0.0 && magnitude.is_finite()) {
+            return sp.clone();
+        }
+        let normal = &normal / magnitude;
+        if normal.dot(&sp.geom_normal) * sp.normal.dot(&sp.geom_normal) <= 0.0 {
+            return sp.clone();
+        }
+
+        // Keep the tangent as close as possible to the original so that anisotropy is preserved,
+        // and maintain tangent × binormal = normal.
+        let tangent = &sp.tangent - &(sp.tangent.dot(&normal) * &normal);
+        let (tangent, binormal) = if tangent.is_nearly_zero() {
+            normal.coord_system()
+        }
+        else {
+            let tangent = tangent.normalized();
+            (tangent, normal.cross(&tangent))
+        };
+
+        let mut result = sp.clone();
+        result.normal = normal;
+        result.tangent = tangent;
+        result.binormal = binormal;
+        result
+    }
+}
@@ -48,8 +48,65 @@ pub fn fresnel_dielectric(cos_theta_in: f32, ior: f32) -> f32 {
     }
 }
 
+/// Computes the reflectance of a conductor with complex index of refraction eta + ik, per color
+/// channel. This is the FrConductor function from PBRT 3e, assuming the incident medium is air.
+pub fn fresnel_conductor(cos_theta_in: f32, eta: &core::Vec, k: &core::Vec) -> core::Vec {
+    let cos_theta_in = core::clamp_unit(f32::abs(cos_theta_in));
+    core::Vec::new(
+            fresnel_conductor_channel(cos_theta_in, eta.x, k.x),
+            fresnel_conductor_channel(cos_theta_in, eta.y, k.y),
+            fresnel_conductor_channel(cos_theta_in, eta.z, k.z))
+}
+
+fn fresnel_conductor_channel(cos_theta_in: f32, eta: f32, k: f32) -> f32 {
+    let cos2_theta_in = cos_theta_in * cos_theta_in;
+    let sin2_theta_in = 1.0 - cos2_theta_in;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2_theta_in;
+    let a2_plus_b2 = f32::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2_plus_b2 + cos2_theta_in;
+    let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+    let t2 = 2.0 * cos_theta_in * a;
+    let r_perp = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2_theta_in * a2_plus_b2 + sin2_theta_in * sin2_theta_in;
+    let t4 = t2 * sin2_theta_in;
+    let r_parl = r_perp * (t3 - t4) / (t3 + t4);
+
+    0.5 * (r_parl + r_perp)
+}
+
 pub trait Fresnel : Sync + Send {
+    /// Returns the reflectance at the given cosine between the direction and the microfacet
+    /// normal. Negative cosines are seen from inside the surface, which only matters for
+    /// dielectrics.
     fn fresnel(&self, cos_theta: f32) -> core::Vec;
+
+    /// The cosine-weighted average of the reflectance over the outside hemisphere.
+    fn average(&self) -> core::Vec {
+        const NUM_STEPS: usize = 32;
+        let mut sum = core::Vec::zero();
+        for i in 0..NUM_STEPS {
+            let cos_theta = (i as f32 + 0.5) / NUM_STEPS as f32;
+            sum = &sum + &(&self.fresnel(cos_theta) * (2.0 * cos_theta / NUM_STEPS as f32));
+        }
+        sum
+    }
+
+    /// The complex index of refraction (eta + ik) per color channel of the reflecting material,
+    /// e.g. for use as the substrate under a thin film. The default finds the real index of a
+    /// dielectric with the same reflectance at normal incidence.
+    fn complex_ior(&self) -> (core::Vec, core::Vec) {
+        let r0 = self.fresnel(1.0);
+        let mut eta = core::Vec::zero();
+        for c in 0..3 {
+            let sqrt_r0 = f32::sqrt(core::clamp(r0[c], 0.0, 0.99));
+            eta[c] = (1.0 + sqrt_r0) / (1.0 - sqrt_r0);
+        }
+        (eta, core::Vec::zero())
+    }
 }
 
 // The Disney Fresnel is a blend of dielectric and metallic models.
@@ -91,6 +148,59 @@ impl Fresnel for DielectricFresnel {
     fn fresnel(&self, cos_theta: f32) -> core::Vec {
         &core::Vec::one() * fresnel_dielectric(cos_theta, self.ior)
     }
+
+    fn complex_ior(&self) -> (core::Vec, core::Vec) {
+        (&core::Vec::one() * self.ior, core::Vec::zero())
+    }
+}
+
+/// Fresnel reflectance of a conductor with per-channel complex index of refraction.
+/// The presets use the red, green, and blue values (at 650, 550, and 450 nm) of measured data.
+#[derive(Clone)]
+pub struct ConductorFresnel {
+    pub eta: core::Vec,
+    pub k: core::Vec,
+}
+
+impl ConductorFresnel {
+    pub fn new(eta: core::Vec, k: core::Vec) -> ConductorFresnel {
+        ConductorFresnel {eta, k}
+    }
+
+    pub fn gold() -> ConductorFresnel {
+        ConductorFresnel::new(
+                core::Vec::new(0.143, 0.374, 1.442), core::Vec::new(3.983, 2.385, 1.603))
+    }
+
+    pub fn silver() -> ConductorFresnel {
+        ConductorFresnel::new(
+                core::Vec::new(0.155, 0.117, 0.138), core::Vec::new(4.828, 3.122, 2.147))
+    }
+
+    pub fn copper() -> ConductorFresnel {
+        ConductorFresnel::new(
+                core::Vec::new(0.200, 0.924, 1.102), core::Vec::new(3.912, 2.452, 2.142))
+    }
+
+    pub fn aluminum() -> ConductorFresnel {
+        ConductorFresnel::new(
+                core::Vec::new(1.657, 0.880, 0.521), core::Vec::new(9.224, 6.270, 4.837))
+    }
+
+    pub fn chrome() -> ConductorFresnel {
+        ConductorFresnel::new(
+                core::Vec::new(3.105, 3.190, 2.345), core::Vec::new(3.300, 3.330, 3.040))
+    }
+}
+
+impl Fresnel for ConductorFresnel {
+    fn fresnel(&self, cos_theta: f32) -> core::Vec {
+        fresnel_conductor(cos_theta, &self.eta, &self.k)
+    }
+
+    fn complex_ior(&self) -> (core::Vec, core::Vec) {
+        (self.eta, self.k)
+    }
 }
 
 pub struct SchlickFresnel {
@@ -109,6 +219,208 @@ impl Fresnel for SchlickFresnel {
     }
 }
 
+/// The wavelength ranges, in nanometers, that the red, green, and blue channels stand for.
+const CHANNEL_WAVELENGTHS: [(f32, f32); 3] = [(600.0, 700.0), (500.0, 600.0), (400.0, 500.0)];
+
+/// The number of wavelengths per color channel at which thin-film interference is evaluated.
+/// Averaging several wavelengths keeps thick films from aliasing into noisy colors.
+const THIN_FILM_WAVELENGTHS: usize = 4;
+
+/// Fresnel reflectance of a surface coated with a thin transparent film, which produces
+/// iridescent colors by interference between light reflected from the top and the bottom of the
+/// film, as in soap bubbles, oil slicks, and anodized metals. This sums the multiple reflections
+/// inside the film (the Airy summation) for each polarization; see Belcour and Barla, "A Practical
+/// Extension to Microfacet Theory for the Modeling of Varying Iridescence" (SIGGRAPH 2017).
+///
+/// The thickness is in nanometers; films thinner than a few hundred nanometers show the
+/// strongest colors. The substrate is described by the complex index of refraction of the base
+/// Fresnel model that the film is over.
+#[derive(Clone)]
+pub struct ThinFilmFresnel {
+    thickness: f32,
+    film_ior: f32,
+    eta: core::Vec,
+    k: core::Vec,
+}
+
+impl ThinFilmFresnel {
+    pub fn new<Fr: Fresnel>(base: &Fr, thickness: f32, film_ior: f32) -> ThinFilmFresnel {
+        let (eta, k) = base.complex_ior();
+        ThinFilmFresnel {thickness: f32::max(thickness, 0.0), film_ior, eta, k}
+    }
+}
+
+impl Fresnel for ThinFilmFresnel {
+    fn fresnel(&self, cos_theta: f32) -> core::Vec {
+        let mut result = core::Vec::zero();
+        for c in 0..3 {
+            let (min, max) = CHANNEL_WAVELENGTHS[c];
+            let mut sum = 0.0;
+            for w in 0..THIN_FILM_WAVELENGTHS {
+                let wavelength = core::lerp(
+                        min, max, (w as f32 + 0.5) / THIN_FILM_WAVELENGTHS as f32);
+                sum += fresnel_thin_film(cos_theta, wavelength, self.thickness, self.film_ior,
+                        self.eta[c], self.k[c]);
+            }
+            result[c] = sum / THIN_FILM_WAVELENGTHS as f32;
+        }
+        result
+    }
+
+    fn complex_ior(&self) -> (core::Vec, core::Vec) {
+        (self.eta, self.k)
+    }
+}
+
+/// A complex number, for the amplitudes and phases of light waves in a thin film.
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        Complex {re, im}
+    }
+
+    fn real(re: f32) -> Complex {
+        Complex::new(re, 0.0)
+    }
+
+    fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// The principal square root, whose real part is non-negative.
+    fn sqrt(self) -> Complex {
+        let norm = f32::sqrt(self.norm_sqr());
+        let re = f32::sqrt(f32::max(0.0, 0.5 * (norm + self.re)));
+        let im = f32::sqrt(f32::max(0.0, 0.5 * (norm - self.re)));
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    /// Returns e^(i x).
+    fn exp_i(self) -> Complex {
+        let scale = f32::exp(-self.im);
+        Complex::new(scale * f32::cos(self.re), scale * f32::sin(self.re))
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im,
+                self.re * other.im + self.im * other.re)
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denom = other.norm_sqr();
+        Complex::new((self.re * other.re + self.im * other.im) / denom,
+                (self.im * other.re - self.re * other.im) / denom)
+    }
+}
+
+/// The amplitude reflection coefficients (s and p polarization) at the interface between two
+/// media, given their indices of refraction and the cosines of the angles of the light in them.
+fn fresnel_amplitudes(n_a: Complex, cos_a: Complex, n_b: Complex, cos_b: Complex)
+    -> (Complex, Complex)
+{
+    let r_perp = (n_a * cos_a - n_b * cos_b) / (n_a * cos_a + n_b * cos_b);
+    let r_parl = (n_b * cos_a - n_a * cos_b) / (n_b * cos_a + n_a * cos_b);
+    (r_perp, r_parl)
+}
+
+/// Computes the reflectance of a thin film over a substrate with complex index of refraction
+/// eta + ik, at a single wavelength. The film is assumed to be on the outside of the surface,
+/// with air above it; negative cosines are seen from inside a dielectric substrate.
+pub fn fresnel_thin_film(
+    cos_theta_in: f32, wavelength: f32, thickness: f32, film_ior: f32, eta: f32, k: f32) -> f32
+{
+    // Light from inside only makes sense for dielectric substrates, so ignore k there.
+    let (n_1, n_3) = if cos_theta_in < 0.0 && k == 0.0 {
+        (Complex::real(eta), Complex::real(1.0))
+    }
+    else {
+        (Complex::real(1.0), Complex::new(eta, k))
+    };
+    let n_2 = Complex::real(film_ior);
+
+    // Compute the cosines in the film and substrate using Snell's law; they become complex under
+    // total internal reflection and in absorbing media.
+    let cos_1 = core::clamp_unit(f32::abs(cos_theta_in));
+    let sin2_1 = 1.0 - cos_1 * cos_1;
+    let cos_in = |n: Complex| {
+        let ratio = n_1 / n;
+        (Complex::real(1.0) - ratio * ratio * Complex::real(sin2_1)).sqrt()
+    };
+    let cos_1 = Complex::real(cos_1);
+    let cos_2 = cos_in(n_2);
+    let cos_3 = cos_in(n_3);
+
+    let (r12_perp, r12_parl) = fresnel_amplitudes(n_1, cos_1, n_2, cos_2);
+    let (r23_perp, r23_parl) = fresnel_amplitudes(n_2, cos_2, n_3, cos_3);
+
+    // The phase difference between successive reflections from the bottom of the film.
+    let phase = Complex::real(4.0 * std::f32::consts::PI * thickness / wavelength) * n_2 * cos_2;
+    let shift = phase.exp_i();
+
+    let airy = |r12: Complex, r23: Complex| {
+        ((r12 + r23 * shift) / (Complex::real(1.0) + r12 * r23 * shift)).norm_sqr()
+    };
+    core::clamp_unit(0.5 * (airy(r12_perp, r23_perp) + airy(r12_parl, r23_parl)))
+}
+
+/// A homogeneous, isotropically-scattering medium inside a subsurface-scattering material.
+#[derive(Clone, Copy)]
+pub struct SubsurfaceMedium {
+    /// Extinction coefficient per color channel, in inverse scene units.
+    pub sigma_t: core::Vec,
+    /// Single-scattering albedo per color channel.
+    pub albedo: core::Vec,
+}
+
+impl SubsurfaceMedium {
+    /// Creates a medium whose multiple-scattering albedo is approximately the given color, with
+    /// light traveling about the given scatter distance per color channel before scattering.
+    /// This inverts the albedo using the fit in Chiang et al., "Practical and Controllable
+    /// Subsurface Scattering for Production Path Tracing" (SIGGRAPH 2016 Talks).
+    pub fn from_color_and_distance(color: &core::Vec, distance: &core::Vec) -> SubsurfaceMedium {
+        let mut sigma_t = core::Vec::zero();
+        let mut albedo = core::Vec::zero();
+        for c in 0..3 {
+            let a = core::clamp(color[c], 0.0, 0.999);
+            let d = f32::max(distance[c], 1e-4);
+            let x = 4.09712 + 4.20863 * a - f32::sqrt(9.59217 + 41.6808 * a + 17.7126 * a * a);
+            albedo[c] = 1.0 - x * x;
+            let s = 1.9 - a + 3.5 * (a - 0.8) * (a - 0.8);
+            sigma_t[c] = 1.0 / (d * s);
+        }
+        SubsurfaceMedium {sigma_t, albedo}
+    }
+}
+
 pub trait MicrofacetDistribution : Sync + Send{
     fn d(&self, half: &core::Vec) -> f32;
     fn g(&self, i: &core::Vec, o: &core::Vec) -> f32;
@@ -132,6 +444,11 @@ impl GgxDistribution {
         GgxDistribution {ax: ax, ay: ay}
     }
 
+    /// The isotropic roughness with the same projected area as this distribution.
+    pub fn alpha(&self) -> f32 {
+        f32::sqrt(self.ax * self.ay)
+    }
+
     fn lambda(&self, v: &core::Vec) -> f32 {
         let abs_tan_theta = f32::abs(v.tan_theta());
         if abs_tan_theta.is_finite() {
@@ -148,50 +465,6 @@ impl GgxDistribution {
     fn g1(&self, v: &core::Vec) -> f32 {
         1.0 / (1.0 + self.lambda(v))
     }
-
-    fn sample11(cos_theta: f32, u1: f32, u2: f32) -> (f32, f32) {
-        // Special case (normal incidence).
-        if cos_theta > 0.9999 {
-            let r = f32::sqrt(u1 / (1.0 - u1));
-            let phi = core::TWO_PI * u2;
-            (r * f32::cos(phi), r * f32::sin(phi))
-        }
-        else {
-            let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
-            let tan_theta = sin_theta / cos_theta;
-            let g1 = 2.0 / (1.0 + f32::sqrt(1.0 + tan_theta * tan_theta));
-
-            // Sample x-slope.
-            let a = 2.0 * u1 / g1 - 1.0;
-            let tmp = f32::min(1.0 / (a * a - 1.0), 1e10);
-            let b = tan_theta;
-            let d = f32::sqrt(f32::max(b * b * tmp * tmp - (a * a - b * b) * tmp, 0.0));
-            let slope_x_1 = b * tmp - d;
-            let slope_x_2 = b * tmp + d;
-            let slope_x = if a < 0.0 || slope_x_2 > 1.0 / tan_theta {
-                slope_x_1
-            }
-            else {
-                slope_x_2
-            };
-            debug_assert!(slope_x.is_finite());
-
-            // Sample y-slope.
-            let (s, u) = if u2 > 0.5 {
-                (1.0, 2.0 * (u2 - 0.5))
-            }
-            else {
-                (-1.0, 2.0 * (0.5 - u2))
-            };
-            let z =
-                    (u * (u * (u * 0.27385 - 0.73369) + 0.46341)) /
-                    (u * (u * (u * 0.093073 + 0.309420) - 1.000000) + 0.597999);
-            let slope_y = s * z * f32::sqrt(1.0 + slope_x * slope_x);
-            debug_assert!(slope_y.is_finite());
-
-            (slope_x, slope_y)
-        }
-    }
 }
 
 impl MicrofacetDistribution for GgxDistribution {
@@ -212,28 +485,39 @@ impl MicrofacetDistribution for GgxDistribution {
     }
 
     fn sample_half(&self, i: &core::Vec, rng: &mut rand::XorShiftRng) -> core::Vec {
+        // This samples the visible normals exactly, following Heitz, "Sampling the GGX
+        // Distribution of Visible Normals" (JCGT 2018).
         // Flip coordinates so that we're on the same side as the normal.
         let flip = i.z < 0.0;
         let i_flipped = if flip { -i } else { *i };
 
-        // 1. Stretch incoming vector.
+        // 1. Stretch incoming vector, so that the microfacets are a hemisphere.
         let i_stretched = core::Vec::new(
                 self.ax * i_flipped.x, self.ay * i_flipped.y, i_flipped.z).normalized();
 
-        // 2. Simulate P22.
-        let cos_theta = i_stretched.cos_theta();
-        let u1: f32 = rng.next_f32();
-        let u2: f32 = rng.next_f32();
-        let (slope_x, slope_y) = GgxDistribution::sample11(cos_theta, u1, u2);
-
-        // 3. Rotate and 4. Unstretch.
-        let cos_phi = i_stretched.cos_phi();
-        let sin_phi = i_stretched.sin_phi();
-        let slope_x_rot = self.ax * (cos_phi * slope_x - sin_phi * slope_y);
-        let slope_y_rot = self.ay * (sin_phi * slope_x + cos_phi * slope_y);
-
-        // 5. Compute normal.
-        let half = core::Vec::new(-slope_x_rot, -slope_y_rot, 1.0).normalized();
+        // 2. Build an orthonormal basis around it.
+        let len2 = i_stretched.x * i_stretched.x + i_stretched.y * i_stretched.y;
+        let t1 = if len2 > 0.0 {
+            &core::Vec::new(-i_stretched.y, i_stretched.x, 0.0) / f32::sqrt(len2)
+        }
+        else {
+            core::Vec::x_axis()
+        };
+        let t2 = i_stretched.cross(&t1);
+
+        // 3. Sample the projected area of the hemisphere, which is a disk with the half of it
+        // that faces away from the incoming vector squashed.
+        let r = f32::sqrt(rng.next_f32());
+        let phi = core::TWO_PI * rng.next_f32();
+        let p1 = r * f32::cos(phi);
+        let s = 0.5 * (1.0 + i_stretched.z);
+        let p2 = core::lerp(f32::sqrt(1.0 - p1 * p1), r * f32::sin(phi), s);
+
+        // 4. Project onto the hemisphere and 5. Unstretch.
+        let p3 = f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+        let normal = &(&(p1 * &t1) + &(p2 * &t2)) + &(p3 * &i_stretched);
+        let half = core::Vec::new(
+                self.ax * normal.x, self.ay * normal.y, f32::max(1e-6, normal.z)).normalized();
 
         // Flip coordinates back if necessary.
         if flip { -&half } else { half }
@@ -292,7 +576,7 @@ impl MicrofacetDistribution for Gtr1Distribution {
         let alpha2 = self.alpha * self.alpha;
         let phi = 2.0 * std::f32::consts::PI * rng.next_f32();
         let cos_theta = f32::sqrt(core::clamp_unit(
-                1.0 - f32::powf(alpha2, 1.0 - rng.next_f32()) / (1.0 - alpha2)));
+                (1.0 - f32::powf(alpha2, 1.0 - rng.next_f32())) / (1.0 - alpha2)));
         let h = core::Vec::from_spherical(cos_theta, phi);
         if h.is_local_same_hemisphere(i) {
             h
@@ -303,7 +587,65 @@ impl MicrofacetDistribution for Gtr1Distribution {
     }
 
     fn pdf(&self, _: &core::Vec, half: &core::Vec) -> f32 {
-        // Sampling exactly follows GTR1, so the pdf is the same as the value.
-        self.d(half)
+        // Sampling draws half vectors in proportion to D(h) cos(theta_h), which integrates to one.
+        self.d(half) * half.abs_cos_theta()
+    }
+}
+
+/// Piecewise Gaussian used by the CIE color matching function fit below.
+fn cie_gaussian(lambda: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+    f64::exp(-0.5 * t * t)
+}
+
+/// Returns the linear sRGB color of a blackbody radiator at the given temperature in Kelvin,
+/// normalized to unit luminance so that it can be used as a light's color.
+/// Integrates Planck's law against the CIE 1931 color matching functions, using the multi-lobe
+/// fit from Wyman, Sloan, and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions" (JCGT 2013).
+pub fn blackbody(kelvin: f32) -> core::Vec {
+    const PLANCK: f64 = 6.62606957e-34;
+    const BOLTZMANN: f64 = 1.3806488e-23;
+    const LIGHT_SPEED: f64 = 299792458.0;
+
+    if kelvin <= 0.0 {
+        return core::Vec::zero();
+    }
+
+    let temp = kelvin as f64;
+    let mut xyz = [0.0f64; 3];
+    let mut lambda_nm = 360.0;
+    while lambda_nm <= 830.0 {
+        let lambda = lambda_nm * 1e-9;
+        let radiance = (2.0 * PLANCK * LIGHT_SPEED * LIGHT_SPEED) /
+                (f64::powi(lambda, 5) *
+                f64::exp_m1((PLANCK * LIGHT_SPEED) / (lambda * BOLTZMANN * temp)));
+
+        xyz[0] += radiance * (
+                1.056 * cie_gaussian(lambda_nm, 599.8, 37.9, 31.0) +
+                0.362 * cie_gaussian(lambda_nm, 442.0, 16.0, 26.7) -
+                0.065 * cie_gaussian(lambda_nm, 501.1, 20.4, 26.2));
+        xyz[1] += radiance * (
+                0.821 * cie_gaussian(lambda_nm, 568.8, 46.9, 40.5) +
+                0.286 * cie_gaussian(lambda_nm, 530.9, 16.3, 31.1));
+        xyz[2] += radiance * (
+                1.217 * cie_gaussian(lambda_nm, 437.0, 11.8, 36.0) +
+                0.681 * cie_gaussian(lambda_nm, 459.0, 26.0, 13.8));
+        lambda_nm += 5.0;
+    }
+
+    // Only the chromaticity matters, so normalize before converting to avoid tiny values at low
+    // temperatures.
+    let sum = xyz[0] + xyz[1] + xyz[2];
+    if sum <= 0.0 {
+        return core::Vec::zero();
     }
+    let (x, y, z) = (xyz[0] / sum, xyz[1] / sum, xyz[2] / sum);
+
+    // XYZ to linear sRGB (D65 white point). Colors outside the gamut are clamped.
+    let rgb = core::Vec::new(
+            f32::max(0.0, (3.2406 * x - 1.5372 * y - 0.4986 * z) as f32),
+            f32::max(0.0, (-0.9689 * x + 1.8758 * y + 0.0415 * z) as f32),
+            f32::max(0.0, (0.0557 * x - 0.2040 * y + 1.0570 * z) as f32));
+    rgb.tint()
 }
@@ -16,10 +16,30 @@ const PIXEL_TYPE_FLOAT: i32 = 2;
 const COMPRESSION_NONE: u8 = 0;
 const LINE_ORDER_INCREASING_Y: u8 = 0;
 
+/// The image in the film that a channel comes from.
+enum ExrSource {
+    Main,
+    LightGroup(usize),
+    Layer(usize),
+}
+
+/// A channel in the output file, with the image it comes from and the component of the color.
+struct ExrChannel {
+    name: String,
+    source: ExrSource,
+    component: usize,
+}
+
+/// Writes the film to an uncompressed EXR file. The main image is written to the R, G, and B
+/// channels, and each light group and extra layer is written as its own layer, e.g. "key.R",
+/// "key.G", "key.B".
 pub struct ExrWriter {
     buffer: std::vec::Vec<u8>,
     width: usize,
     height: usize,
+    light_groups: std::vec::Vec<String>,
+    layers: std::vec::Vec<String>,
+    channels: std::vec::Vec<ExrChannel>,
     data_offset: usize,
     file: File
 }
@@ -30,6 +50,9 @@ impl ExrWriter {
             buffer: vec![],
             width: 0,
             height: 0,
+            light_groups: vec![],
+            layers: vec![],
+            channels: vec![],
             data_offset: 0,
             file: File::create(path).unwrap()
         }
@@ -45,17 +68,48 @@ impl ExrWriter {
         self.buffer.push(0);
     }
 
+    fn compute_channels(&mut self, film: &film::Film) {
+        self.channels.clear();
+        let components = ["R", "G", "B"];
+        for (component, suffix) in components.iter().enumerate() {
+            self.channels.push(ExrChannel {
+                name: String::from(*suffix),
+                source: ExrSource::Main,
+                component,
+            });
+            for (light_group, group_name) in film.light_groups.iter().enumerate() {
+                self.channels.push(ExrChannel {
+                    name: format!("{}.{}", group_name, suffix),
+                    source: ExrSource::LightGroup(light_group),
+                    component,
+                });
+            }
+            for (layer, layer_name) in film.layers.iter().enumerate() {
+                self.channels.push(ExrChannel {
+                    name: format!("{}.{}", layer_name, suffix),
+                    source: ExrSource::Layer(layer),
+                    component,
+                });
+            }
+        }
+
+        // The EXR format requires channels to be sorted by name.
+        self.channels.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
     fn write_channels_attr(&mut self) {
         self.write_str("channels");
         self.write_str("chlist");
 
-        let size: i32 =
-                2 * 3 +  // Three channels named B, G, R, plus a null-terminator for each.
-                16 * 3 + // Four ints (16 bytes) of data per channel.
-                1;       // One extra null byte.
-        self.buffer.write_i32::<LittleEndian>(size).unwrap();
+        let names: std::vec::Vec<String> = self.channels.iter().map(|c| c.name.clone()).collect();
+        let name_size: usize = names.iter().map(|name| name.len() + 1).sum();
+        let size =
+                name_size +                // Channel names, plus a null-terminator for each.
+                16 * self.channels.len() + // Four ints (16 bytes) of data per channel.
+                1;                         // One extra null byte.
+        self.buffer.write_i32::<LittleEndian>(size as i32).unwrap();
 
-        for channel in ["B", "G", "R"].iter() {
+        for channel in &names {
             self.write_str(channel);
             self.buffer.write_i32::<LittleEndian>(PIXEL_TYPE_FLOAT).unwrap();
             self.buffer.write_i32::<LittleEndian>(0).unwrap(); // pLinear and reserved
@@ -124,8 +178,8 @@ impl ExrWriter {
         let table_size = 8 * film.height; // 1 ulong (8 bytes) per line.
         let data_offset = self.buffer.len() + table_size;
 
-        // Scan line number (int); bytes in line (uint); RGB (3 floats * 4 bytes) per pixel.
-        let line_size = 4 + 4 + (film.width * 4 * 3);
+        // Scan line number (int); bytes in line (uint); 1 float (4 bytes) per channel per pixel.
+        let line_size = 4 + 4 + (film.width * 4 * self.channels.len());
 
         for y in 0..film.height {
             let line_offset = data_offset + y * line_size;
@@ -136,41 +190,53 @@ impl ExrWriter {
     }
 
     fn write_channels(&mut self, film: &film::Film) {
-        // Scan line number (int); bytes in line (uint); RGB (3 floats * 4 bytes) per pixel.
-        let line_size = 4 + 4 + (film.width * 4 * 3);
+        // Scan line number (int); bytes in line (uint); 1 float (4 bytes) per channel per pixel.
+        let line_size = 4 + 4 + (film.width * 4 * self.channels.len());
         let data_size = film.height * line_size;
 
         self.buffer.resize(self.data_offset + data_size, 0);
         let data = &mut self.buffer[self.data_offset..(self.data_offset + data_size)];
+        let channels = &self.channels;
 
         data.par_chunks_mut(line_size).enumerate().for_each(|(y, line)| {
             LittleEndian::write_i32(&mut line[0..4], y as i32); // Scan line number.
             LittleEndian::write_u32(&mut line[4..8], line_size as u32 - 8); // Bytes in line.
 
+            // Each channel's values for the whole line are stored contiguously.
             let first_pixel = core::index(film.height - y - 1, 0, film.width);
-            for i in 0..film.width {
-                let pixel = &film.pixels[first_pixel + i];
-                let val = [
-                    (pixel.accum.x / pixel.weight) as f32,
-                    (pixel.accum.y / pixel.weight) as f32,
-                    (pixel.accum.z / pixel.weight) as f32,
-                ];
-                let z = 8 + (0 * film.width + i) * 4;
-                let y = 8 + (1 * film.width + i) * 4;
-                let x = 8 + (2 * film.width + i) * 4;
-                LittleEndian::write_f32(&mut line[z..(z + 4)], val[2]);
-                LittleEndian::write_f32(&mut line[y..(y + 4)], val[1]);
-                LittleEndian::write_f32(&mut line[x..(x + 4)], val[0]);
+            for (c, channel) in channels.iter().enumerate() {
+                for i in 0..film.width {
+                    let pixel_index = first_pixel + i;
+                    // Extra layers are set directly, so only accumulations are divided by the
+                    // filter weight.
+                    let weight = film.pixels[pixel_index].weight;
+                    let val = match channel.source {
+                        ExrSource::Main =>
+                                film.pixels[pixel_index].accum[channel.component] / weight,
+                        ExrSource::LightGroup(light_group) => {
+                            film.light_group_accum(light_group, pixel_index)[channel.component] /
+                                    weight
+                        },
+                        ExrSource::Layer(layer) =>
+                                film.layer_color(layer, pixel_index)[channel.component]
+                    };
+                    let offset = 8 + (c * film.width + i) * 4;
+                    LittleEndian::write_f32(&mut line[offset..(offset + 4)], val);
+                }
             }
         });
     }
 
     pub fn update(&mut self, film: &film::Film) {
-        if self.width != film.width || self.height != film.height {
+        if self.width != film.width || self.height != film.height ||
+                self.light_groups != film.light_groups || self.layers != film.layers {
             // Re-initializate the buffer with the EXR file layout.
             self.buffer.clear();
             self.width = film.width;
             self.height = film.height;
+            self.light_groups = film.light_groups.clone();
+            self.layers = film.layers.clone();
+            self.compute_channels(film);
 
             // Begin header.
             self.write_header();
@@ -196,5 +262,6 @@ impl ExrWriter {
     pub fn write(&mut self) {
         self.file.seek(io::SeekFrom::Start(0)).unwrap();
         self.file.write_all(&self.buffer).unwrap();
+        self.file.set_len(self.buffer.len() as u64).unwrap(); // In case the layout shrank.
     }
 }
@@ -15,11 +15,13 @@ pub struct FilmSample {
     pub s: f32,
     // Row of the sample, in lens space. Samples may extend beyond [-1, 1] depending on filtering.
     pub t: f32,
+    // Portion of the color contributed by each of the film's light groups.
+    pub light_groups: std::vec::Vec<core::Vec>,
 }
 
 impl FilmSample {
     pub fn zero() -> FilmSample {
-        FilmSample {color: core::Vec::zero(), s: 0.0, t: 0.0}
+        FilmSample {color: core::Vec::zero(), s: 0.0, t: 0.0, light_groups: vec![]}
     }
 }
 
@@ -39,7 +41,18 @@ pub struct Film {
     pub width: usize,
     pub height: usize,
     pub samples: std::vec::Vec<FilmSample>,
-    pub pixels: std::vec::Vec<FilmPixel>
+    pub pixels: std::vec::Vec<FilmPixel>,
+    /// Names of the light groups that are accumulated separately.
+    pub light_groups: std::vec::Vec<String>,
+    /// Accumulated color for each light group, stored one image after another. Each pixel shares
+    /// its filter weight with the corresponding entry in pixels.
+    pub light_group_accums: std::vec::Vec<core::Vec>,
+    /// Names of extra layers whose colors are set directly instead of accumulated from samples,
+    /// e.g. for plots.
+    pub layers: std::vec::Vec<String>,
+    /// Color of each extra layer, stored one image after another. Unlike the main image and the
+    /// light groups, these colors aren't divided by the filter weight.
+    layer_colors: std::vec::Vec<core::Vec>,
 }
 
 impl Film {
@@ -48,16 +61,53 @@ impl Film {
             width: width,
             height: height,
             samples: vec![FilmSample::zero(); width * height],
-            pixels: vec![FilmPixel::zero(); width * height]
+            pixels: vec![FilmPixel::zero(); width * height],
+            light_groups: vec![],
+            light_group_accums: vec![],
+            layers: vec![],
+            layer_colors: vec![],
         }
     }
 
+    /// Adds an extra layer with the given name, initially black, and returns its index.
+    pub fn add_layer(&mut self, name: &str) -> usize {
+        self.layers.push(String::from(name));
+        self.layer_colors.resize(self.layers.len() * self.width * self.height, core::Vec::zero());
+        self.layers.len() - 1
+    }
+
+    /// Returns the color of the given extra layer at the given pixel index.
+    pub fn layer_color(&self, layer: usize, pixel_index: usize) -> &core::Vec {
+        &self.layer_colors[layer * self.width * self.height + pixel_index]
+    }
+
+    /// Returns a mutable reference to the color of the given extra layer at the given pixel index.
+    pub fn layer_color_mut(&mut self, layer: usize, pixel_index: usize) -> &mut core::Vec {
+        &mut self.layer_colors[layer * self.width * self.height + pixel_index]
+    }
+
+    /// Sets the light groups that the film accumulates. Changing the groups clears the
+    /// accumulated light group images, but not the main image.
+    pub fn set_light_groups(&mut self, light_groups: &[String]) {
+        if self.light_groups[..] != *light_groups {
+            self.light_groups = light_groups.to_vec();
+            self.light_group_accums =
+                    vec![core::Vec::zero(); light_groups.len() * self.width * self.height];
+        }
+    }
+
+    /// Returns the accumulated color of the given light group at the given pixel index.
+    pub fn light_group_accum(&self, light_group: usize, pixel_index: usize) -> &core::Vec {
+        &self.light_group_accums[light_group * self.width * self.height + pixel_index]
+    }
+
     pub fn compute_sample_points(&self, samples: &mut std::vec::Vec<FilmSample>) {
         let mut thread_rng = rand::thread_rng();
         let filter_range = Range::new(-FILTER_WIDTH, FILTER_WIDTH);
 
-        samples.clear();
-        samples.reserve_exact(self.width * self.height);
+        // Reuse the existing samples so that the light group storage isn't reallocated.
+        let num_light_groups = self.light_groups.len();
+        samples.resize(self.width * self.height, FilmSample::zero());
 
         let (widthf, heightf) = (self.width as f32, self.height as f32);
         for row_discr in 0..self.height {
@@ -70,7 +120,12 @@ impl Film {
 
                 let s = core::lerp(-1.0, 1.0, col_cont_jitter / widthf);
                 let t = core::lerp(-1.0, 1.0, row_cont_jitter / heightf);
-                samples.push(FilmSample {color: core::Vec::zero(), s: s, t: t});
+                let sample = &mut samples[core::index(row_discr, col_discr, self.width)];
+                sample.color = core::Vec::zero();
+                sample.s = s;
+                sample.t = t;
+                sample.light_groups.clear();
+                sample.light_groups.resize(num_light_groups, core::Vec::zero());
             }
         }
     }
@@ -100,7 +155,8 @@ impl Film {
 
             for y in (min_row)..(max_row + 1) {
                 for x in (min_col)..(max_col + 1) {
-                    let mut pixel = &mut self.pixels[core::index(y, x, self.width)];
+                    let pixel_index = core::index(y, x, self.width);
+                    let pixel = &mut self.pixels[pixel_index];
                     let weight = core::mitchell_filter2(
                             x as f32 - col_discr,
                             y as f32 - row_discr,
@@ -108,6 +164,12 @@ impl Film {
 
                     pixel.accum = &pixel.accum + &(&sample.color * weight);
                     pixel.weight += weight;
+
+                    let num_pixels = self.width * self.height;
+                    for (i, color) in sample.light_groups.iter().enumerate() {
+                        let accum = &mut self.light_group_accums[i * num_pixels + pixel_index];
+                        *accum = &*accum + &(color * weight);
+                    }
                 }
             }
         }
@@ -6,24 +6,93 @@ use std;
 use std::cell::RefCell;
 use rand;
 use rand::Rng;
+use rand::distributions::IndependentSample;
 
 // Sums the light reaching the eye by way of a given ray.
 // The implementation of integrators is flexible; they can always return the same result for
 // each ray, or they can perform Monte Carlo integration that takes many iterations to converge.
+// The light_groups slice has one entry per group in bvh.light_groups(); integrators add the
+// portion of the result that was emitted by each group's lights to its entry.
 pub trait Integrator : Sync + Send {
-    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng)
-        -> core::Vec;
+    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng,
+        light_groups: &mut [core::Vec]) -> core::Vec;
+}
+
+/// Adds a contribution emitted by the light on the given prim to that light's group.
+fn add_to_light_group(
+    light_groups: &mut [core::Vec], bvh: &geom::Bvh, prim_index: usize, contrib: &core::Vec)
+{
+    if let Some(group) = bvh.light_group(prim_index) {
+        light_groups[group] = &light_groups[group] + contrib;
+    }
+}
+
+/// Maximum number of scattering events in a subsurface random walk.
+const SUBSURFACE_MAX_BOUNCES: usize = 256;
+const SUBSURFACE_RUSSIAN_ROULETTE_BOUNCES: usize = 8;
+
+/// Walks through the interior medium of a prim, starting with a ray that enters its surface.
+/// Chromatic media are handled by sampling distances using a randomly-chosen color channel and
+/// weighting by the average pdf over all channels. Returns the ray from the last scattering point
+/// toward the point where the walk leaves the surface, along with the throughput of the walk.
+/// Returns None if the walk is absorbed, or escapes through something other than the prim's own
+/// surface.
+fn subsurface_walk(
+    medium: &material::SubsurfaceMedium, initial_ray: &core::Ray, prim_index: usize,
+    bvh: &geom::Bvh, rng: &mut rand::XorShiftRng) -> Option<(core::Ray, core::Vec)>
+{
+    let mut ray = initial_ray.clone();
+    let mut throughput = core::Vec::one();
+    let sigma_s = medium.sigma_t.comp_mult(&medium.albedo);
+    let uniform_sample_sphere = core::UniformSampleSphere {};
+    for bounce in 0..SUBSURFACE_MAX_BOUNCES {
+        let surface_dist = match bvh.intersect(&ray) {
+            geom::Intersection::Hit {dist, prim_index: hit_index, ..} if hit_index == prim_index => {
+                dist
+            },
+            _ => return None
+        };
+
+        let channel = rng.gen_range(0, 3);
+        let dist = -f32::ln(1.0 - rng.next_f32()) / medium.sigma_t[channel];
+        if dist >= surface_dist {
+            // Left the medium; the probability of this is the transmittance to the surface.
+            let transmittance = (&medium.sigma_t * -surface_dist).exp();
+            let pdf = (transmittance.x + transmittance.y + transmittance.z) / 3.0;
+            throughput = &throughput.comp_mult(&transmittance) / pdf;
+            return Some((ray, throughput));
+        }
+
+        // Scatter isotropically within the medium.
+        let transmittance = (&medium.sigma_t * -dist).exp();
+        let density = medium.sigma_t.comp_mult(&transmittance);
+        let pdf = (density.x + density.y + density.z) / 3.0;
+        throughput = &throughput.comp_mult(&sigma_s.comp_mult(&transmittance)) / pdf;
+        ray = core::Ray::new(ray.at(dist), uniform_sample_sphere.ind_sample(rng));
+
+        if bounce >= SUBSURFACE_RUSSIAN_ROULETTE_BOUNCES {
+            let prob_live = core::clamped_lerp(0.1, 1.0, throughput.luminance());
+            if rng.next_f32() < prob_live {
+                throughput = &throughput / prob_live;
+            }
+            else {
+                return None;
+            }
+        }
+    }
+
+    None
 }
 
 pub struct DisplayColorIntegrator {
 }
 
 impl Integrator for DisplayColorIntegrator {
-    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, _: &mut rand::XorShiftRng)
-        -> core::Vec
+    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, _: &mut rand::XorShiftRng,
+        _: &mut [core::Vec]) -> core::Vec
     {
         match bvh.intersect(initial_ray) {
-            geom::Intersection::Hit {dist: _, surface_props: _, prim_index} => {
+            geom::Intersection::Hit {prim_index, ..} => {
                 bvh[prim_index].material().display_color().clone()
             },
             geom::Intersection::NoHit => {
@@ -39,31 +108,135 @@ const RUSSIAN_ROULETTE_DEPTH_AGRESSIVE: usize = 20;
 pub struct PathTracerIntegrator {
 }
 
+impl PathTracerIntegrator {
+    /// Samples a point on a light as seen from the hit point and returns its contribution,
+    /// weighted with the power heuristic against BSDF sampling, along with the index of the light
+    /// prim. The result does not include the path throughput.
+    fn sample_direct(
+        point: &core::Vec, incoming_world: &core::Vec, bsdf: &material::Bsdf,
+        bvh: &geom::Bvh, rng: &mut rand::XorShiftRng) -> (core::Vec, usize)
+    {
+        let light_sample = bvh.sample_light_from(point, rng);
+        let prim_index = light_sample.prim_index;
+        if light_sample.pdf == 0.0 {
+            return (core::Vec::zero(), prim_index);
+        }
+
+        let to_light = &light_sample.point - point;
+        let dist = to_light.magnitude();
+        if dist == 0.0 {
+            return (core::Vec::zero(), prim_index);
+        }
+        let outgoing_world = &to_light / dist;
+
+        let emission = bvh.sampled_light_world(
+                prim_index, &-&outgoing_world, &light_sample.surface_props);
+        if emission.is_exactly_zero() {
+            return (core::Vec::zero(), prim_index);
+        }
+
+        let f = bsdf.f_world(incoming_world, &outgoing_world, true);
+        if f.is_exactly_zero() {
+            return (core::Vec::zero(), prim_index);
+        }
+
+        if !bvh.visibility(point, &light_sample.point) {
+            return (core::Vec::zero(), prim_index);
+        }
+
+        let bsdf_pdf = bsdf.pdf_world(incoming_world, &outgoing_world);
+        let weight = core::power_heuristic(1, light_sample.pdf, 1, bsdf_pdf);
+        let cos_theta = f32::abs(bsdf.surface_props().normal.dot(&outgoing_world));
+        let direct = &f.comp_mult(&emission) * (cos_theta * weight / light_sample.pdf);
+        (direct, prim_index)
+    }
+}
+
 impl Integrator for PathTracerIntegrator {
-    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng)
-        -> core::Vec
+    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng,
+        light_groups: &mut [core::Vec]) -> core::Vec
     {
         let mut depth = 0usize;
         let mut light = core::Vec::zero();
         let mut throughput = core::Vec::one();
         let mut current_ray = initial_ray.clone();
+
+        // The point and pdf of the previous scattering event, used to weight emission found by
+        // BSDF sampling against next-event estimation. The camera ray can't be light-sampled, so
+        // it's treated as if it were specular.
+        let mut prev_point = current_ray.origin;
+        let mut prev_pdf = 1.0;
+        let mut prev_specular = true;
+
+        // Whether the current ray is leaving the interior of a subsurface-scattering prim.
+        let mut subsurface_exit = false;
         while !throughput.is_exactly_zero() {
             match bvh.intersect(&current_ray) {
-                geom::Intersection::Hit {dist, surface_props, prim_index} => {
+                geom::Intersection::Hit {dist, surface_props, prim_index, component_index} => {
                     // Check for scattering (reflection/transmission).
                     // Note: the material pipeline expects the incoming direction to face away from
                     // the hit point (i.e. toward the previous hit point or eye).
                     let incoming_world = -&current_ray.direction;
-                    let prim = &bvh[prim_index];
-                    let sample = prim.material().sample_world(
-                            &incoming_world, &surface_props, true, rng);
+                    let hit_point = current_ray.at(dist);
+                    let mat = if subsurface_exit {
+                        material::Material::subsurface_exit()
+                    }
+                    else {
+                        bvh[prim_index].material()
+                    };
+                    let bsdf = mat.bsdf(surface_props, &incoming_world);
+                    let surface_props = bsdf.surface_props();
+                    let sample = bsdf.sample_world(&incoming_world, true, rng);
 
                     // Add illumination first, and then update throughput.
-                    light = &light + &throughput.comp_mult(&sample.emission);
+                    if !sample.emission.is_exactly_zero() {
+                        let weight = if prev_specular {
+                            1.0
+                        }
+                        else {
+                            let light_pdf = bvh.pdf_light_from(
+                                    &prev_point, prim_index, component_index, &hit_point,
+                                    surface_props);
+                            core::power_heuristic(1, prev_pdf, 1, light_pdf)
+                        };
+                        let contrib = &throughput.comp_mult(&sample.emission) * weight;
+                        add_to_light_group(light_groups, bvh, prim_index, &contrib);
+                        light = &light + &contrib;
+                    }
+
+                    // Next-event estimation. Specular-only materials can't be connected to lights.
+                    if bvh.num_lights() != 0 && bsdf.count_lobes(
+                            material::LobeKind::LOBE_DIFFUSE | material::LobeKind::LOBE_GLOSSY)
+                            != 0 {
+                        let (direct, light_index) = PathTracerIntegrator::sample_direct(
+                                &hit_point, &incoming_world, &bsdf, bvh, rng);
+                        let contrib = throughput.comp_mult(&direct);
+                        add_to_light_group(light_groups, bvh, light_index, &contrib);
+                        light = &light + &contrib;
+                    }
+
                     throughput = throughput.comp_mult(
                             &(&sample.radiance *
                             (f32::abs(surface_props.normal.dot(&sample.outgoing)) / sample.pdf)));
-                    current_ray = core::Ray::new(current_ray.at(dist), sample.outgoing).nudge();
+                    current_ray = core::Ray::new(hit_point, sample.outgoing).nudge();
+                    prev_point = hit_point;
+                    prev_pdf = sample.pdf;
+                    prev_specular = sample.kind.contains(material::LobeKind::LOBE_SPECULAR);
+
+                    // Continue from where the path leaves the surface if it entered the interior.
+                    subsurface_exit = false;
+                    if let Some(ref medium) = sample.subsurface {
+                        match subsurface_walk(medium, &current_ray, prim_index, bvh, rng) {
+                            Some((exit_ray, weight)) => {
+                                throughput = throughput.comp_mult(&weight);
+                                current_ray = exit_ray;
+                                subsurface_exit = true;
+                            },
+                            None => {
+                                throughput = core::Vec::zero();
+                            }
+                        }
+                    }
 
                     // Do Russian Roulette if this path is "old".
                     if depth > RUSSIAN_ROULETTE_DEPTH || throughput.is_nearly_zero() {
@@ -111,6 +284,8 @@ struct BdptVertex {
     pub point: core::Vec,
     // Surface properties at the intersection.
     pub surface_props: geom::SurfaceProperties,
+    // Lobes of the material at the intersection, if it scatters light.
+    pub bsdf: Option<material::DetachedBsdf>,
     // Radiance/importance throughput *before* interacting with the surface.
     pub throughput: core::Vec,
     // Emission, if the hit occurred on a light.
@@ -121,12 +296,27 @@ struct BdptVertex {
     pub connectible: bool,
     // Prim that was hit.
     pub prim_index: usize,
-    // Probability of obtaining this vertex from the previous vertex in the path.
-    pub pdf_forward: f32,
-    // Probability of hypothetically obtaining this vertex from the next vertex in the path, e.g.
-    // the probability if importance (light-to-camera) were sampled instead of radiance
-    // (camera-to-light) or vice versa.
-    pub pdf_reverse: f32,
+    // Whether the path left the interior of a subsurface-scattering prim at this vertex.
+    pub subsurface_exit: bool,
+}
+
+impl BdptVertex {
+    /// The material that scatters light at this vertex.
+    fn material<'a>(&self, bvh: &'a geom::Bvh) -> &'a material::Material {
+        if self.subsurface_exit {
+            material::Material::subsurface_exit()
+        }
+        else {
+            bvh[self.prim_index].material()
+        }
+    }
+
+    /// Whether the path continued from this vertex through a delta-distributed lobe, so that the
+    /// vertex can't be connected to in any strategy.
+    fn is_delta(&self) -> bool {
+        self.lobe_kind.intersects(
+                material::LobeKind::LOBE_SPECULAR | material::LobeKind::LOBE_SUBSURFACE)
+    }
 }
 
 type BdptPath = std::vec::Vec<BdptVertex>;
@@ -141,9 +331,10 @@ impl BdptIntegrator {
     {
         let mut throughput = initial_throughput.clone();
         let mut current_ray = initial_ray.clone();
+        let mut subsurface_exit = false;
         while !throughput.is_exactly_zero() && storage.len() < BDPT_MAX_DEPTH {
             match bvh.intersect(&current_ray) {
-                geom::Intersection::Hit {dist, surface_props, prim_index} => {
+                geom::Intersection::Hit {dist, surface_props, prim_index, ..} => {
                     let prev_throughput = throughput;
                     let hit_point = current_ray.at(dist);
 
@@ -151,13 +342,35 @@ impl BdptIntegrator {
                     // Note: the material pipeline expects the incoming direction to face away from
                     // the hit point (i.e. toward the previous hit point or eye).
                     let incoming_world = -&current_ray.direction;
-                    let prim = &bvh[prim_index];
-                    let sample = prim.material().sample_world(
-                            &incoming_world, &surface_props, camera_to_light, rng);
-                    let pdf_reverse = prim.material().pdf_world(
-                            &sample.outgoing, &incoming_world, &surface_props);
-                    let connectible = prim.material().count_lobes(
-                            material::LobeKind::LOBE_DIFFUSE | material::LobeKind::LOBE_GLOSSY) != 0;
+                    let mat = if subsurface_exit {
+                        material::Material::subsurface_exit()
+                    }
+                    else {
+                        bvh[prim_index].material()
+                    };
+                    let bsdf = mat.bsdf(surface_props, &incoming_world);
+                    let mut sample = bsdf.sample_world(&incoming_world, camera_to_light, rng);
+
+                    // View-dependent shaders see each vertex from the camera end of the path. On
+                    // light paths, that's the direction being sampled, so the lobes facing the
+                    // light only choose the direction, and the sample is weighted by the lobes
+                    // facing it. Delta lobes can't be reevaluated, so they keep their weight.
+                    let reevaluate = !camera_to_light && !mat.is_constant() &&
+                            sample.kind != material::LobeKind::LOBE_NONE &&
+                            !sample.kind.intersects(material::LobeKind::LOBE_SPECULAR |
+                            material::LobeKind::LOBE_SUBSURFACE);
+                    let bsdf = if reevaluate {
+                        let bsdf = bsdf.facing(&sample.outgoing);
+                        sample.radiance = bsdf.f_world(&incoming_world, &sample.outgoing, false);
+                        bsdf
+                    }
+                    else {
+                        bsdf
+                    };
+                    let surface_props = bsdf.surface_props().clone();
+                    let connectible = bsdf.count_lobes(
+                            material::LobeKind::LOBE_DIFFUSE | material::LobeKind::LOBE_GLOSSY)
+                            != 0;
 
                     throughput = throughput.comp_mult(
                             &(&sample.radiance *
@@ -167,30 +380,35 @@ impl BdptIntegrator {
                             &incoming_world, &sample.outgoing, &surface_props, camera_to_light);
                     current_ray = core::Ray::new(current_ray.at(dist), sample.outgoing).nudge();
 
-                    // Set the pdf_reverse of the last vertex (if one exists).
-                    match storage.last_mut() {
-                        Some(x) => {
-                            let convert_density = BdptIntegrator::convert_density(
-                                    &x.point, &hit_point, &surface_props);
-                            x.pdf_reverse = pdf_reverse * convert_density;
-                        },
-                        None => {}
-                    }
-
                     // Add to the random walk path.
                     storage.push(BdptVertex {
                         incoming_world: incoming_world,
                         point: hit_point,
                         surface_props: surface_props,
+                        bsdf: Some(bsdf.detach()),
                         throughput: prev_throughput,
                         emission: sample.emission,
                         lobe_kind: sample.kind,
                         connectible: connectible,
                         prim_index: prim_index,
-                        pdf_forward: sample.pdf,
-                        pdf_reverse: 1.0,
+                        subsurface_exit,
                     });
 
+                    // Continue from where the path leaves the surface if it entered the interior.
+                    subsurface_exit = false;
+                    if let Some(ref medium) = sample.subsurface {
+                        match subsurface_walk(medium, &current_ray, prim_index, bvh, rng) {
+                            Some((exit_ray, weight)) => {
+                                throughput = throughput.comp_mult(&weight);
+                                current_ray = exit_ray;
+                                subsurface_exit = true;
+                            },
+                            None => {
+                                throughput = core::Vec::zero();
+                            }
+                        }
+                    }
+
                     // Do Russian Roulette if this path is "old".
                     if storage.len() >= BDPT_RUSSIAN_ROULETTE_DEPTH || throughput.is_nearly_zero() {
                         let rv = rng.next_f32();
@@ -215,13 +433,13 @@ impl BdptIntegrator {
                         incoming_world: -&current_ray.direction,
                         point: core::Vec::zero(),
                         surface_props: geom::SurfaceProperties::zero(),
+                        bsdf: None,
                         throughput: throughput,
                         emission: core::Vec::zero(),
                         lobe_kind: material::LobeKind::LOBE_NONE,
                         connectible: false,
                         prim_index: std::usize::MAX,
-                        pdf_forward: 1.0,
-                        pdf_reverse: 1.0,
+                        subsurface_exit: false,
                     });
                 }
             }
@@ -304,19 +522,19 @@ impl BdptIntegrator {
             let camera_to_light = (&light_vertex.point - &camera_vertex.point).normalized();
             let light_to_camera = -&camera_to_light;
 
-            let camera_material = bvh[camera_vertex.prim_index].material();
-            let light_material = bvh[light_vertex.prim_index].material();
-            let connect_radiance = camera_material.f_world(
-                    &camera_vertex.incoming_world, &camera_to_light,
-                    &camera_vertex.surface_props, true);
-            let connect_emission = if light_len == 1 {
-                light_material.light_world(
-                        &light_to_camera, &light_vertex.surface_props)
-            }
-            else {
-                light_material.f_world(
-                        &light_vertex.incoming_world, &light_to_camera,
-                        &light_vertex.surface_props, false)
+            // Connectible vertices other than the one on the light have lobes.
+            let camera_bsdf = match camera_vertex.bsdf {
+                Some(ref bsdf) => bsdf.attach(camera_vertex.material(bvh)),
+                None => return core::Vec::zero()
+            };
+            let connect_radiance = camera_bsdf.f_world(
+                    &camera_vertex.incoming_world, &camera_to_light, true);
+            let connect_emission = match light_vertex.bsdf {
+                None => bvh.sampled_light_world(
+                        light_vertex.prim_index, &light_to_camera, &light_vertex.surface_props),
+                Some(ref light_bsdf) => light_bsdf.attach(light_vertex.material(bvh))
+                        .facing(&light_to_camera)
+                        .f_world(&light_vertex.incoming_world, &light_to_camera, false)
             };
 
             let g = f32::abs(camera_vertex.surface_props.normal.dot(&camera_to_light));
@@ -342,42 +560,66 @@ impl BdptIntegrator {
         }
     }
 
-    fn weight(&self, camera_len: usize, light_len: usize) -> f32 {
+    fn weight(&self,
+        camera_len: usize,
+        light_len: usize,
+        camera_storage: &BdptPath,
+        light_storage: &BdptPath) -> f32
+    {
         // There are path_len ways to make the path in this rendering system:
         // cam: 1              + light: (path_len - 1)
         // cam: 2              + light: (path_len - 2)
         // ...
         // cam: (path_len - 1) + light: 1
         // cam: path_len       + light: 0
+        // A strategy that connects two vertices can't make the path if the path passes through a
+        // delta lobe at either one, so the path is weighted uniformly over the rest. The vertices
+        // being connected in this strategy and the emitter at the end of the path aren't delta.
+        // Paths without delta lobes are weighted 1/path_len.
         let path_len = camera_len + light_len;
-        1.0 / path_len as f32
+        let is_delta = |index: usize| {
+            if index + 1 == camera_len || index == camera_len || index + 1 == path_len {
+                false
+            }
+            else if index < camera_len {
+                camera_storage[index].is_delta()
+            }
+            else {
+                light_storage[path_len - 1 - index].is_delta()
+            }
+        };
+
+        let connections = (0..(path_len - 1))
+                .filter(|&index| !is_delta(index) && !is_delta(index + 1))
+                .count();
+        1.0 / (connections + 1) as f32
     }
 }
 
 impl Integrator for BdptIntegrator {
-    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng)
-        -> core::Vec
+    fn integrate(&self, initial_ray: &core::Ray, bvh: &geom::Bvh, rng: &mut rand::XorShiftRng,
+        light_groups: &mut [core::Vec]) -> core::Vec
     {
         let mut light = core::Vec::zero();
         BDPT_CAMERA_STORAGE.with(|x| {
             BDPT_LIGHT_STORAGE.with(|y| {
-                let mut camera_storage = &mut x.borrow_mut();
+                let mut camera_storage = x.borrow_mut();
                 camera_storage.clear();
                 BdptIntegrator::random_walk(
                         initial_ray, &core::Vec::one(), true, bvh, rng, &mut camera_storage);
-                if camera_storage.len() == 0 {
+                if camera_storage.is_empty() {
                     return; // Camera ray escaped.
                 }
 
-                let mut light_storage = &mut y.borrow_mut();
+                let mut light_storage = y.borrow_mut();
                 let light_sample = bvh.sample_light(rng);
                 if light_sample.point_pdf == 0.0 || light_sample.dir_pdf == 0.0 {
                     return; // Can't divide by zero; chance of this happening is very low.
                 }
                 let light_dir = &light_sample.ray.direction;
-                let light_material = bvh[light_sample.prim_index].material();
                 let initial_emission =
-                        &light_material.light_world(light_dir, &light_sample.surface_props)
+                        &bvh.sampled_light_world(
+                                light_sample.prim_index, light_dir, &light_sample.surface_props)
                         * (f32::abs(light_sample.surface_props.geom_normal.dot(light_dir))
                         / (light_sample.point_pdf * light_sample.dir_pdf));
                 light_storage.clear();
@@ -385,13 +627,13 @@ impl Integrator for BdptIntegrator {
                     incoming_world: core::Vec::zero(),
                     point: light_sample.ray.origin,
                     surface_props: light_sample.surface_props,
+                    bsdf: None,
                     throughput: &core::Vec::one() / light_sample.point_pdf,
                     emission: core::Vec::zero(),
                     lobe_kind: material::LobeKind::LOBE_NONE,
                     connectible: true,
                     prim_index: light_sample.prim_index,
-                    pdf_forward: light_sample.point_pdf * light_sample.dir_pdf,
-                    pdf_reverse: 1.0,
+                    subsurface_exit: false,
                 });
                 BdptIntegrator::random_walk(
                         &light_sample.ray.nudge(), &initial_emission, false, bvh, rng,
@@ -405,9 +647,21 @@ impl Integrator for BdptIntegrator {
                         }
 
                         let l = self.connect(
-                                camera_len, light_len, camera_storage, light_storage, bvh);
-                        let w = self.weight(camera_len, light_len);
-                        light = &light + &(&l * w);
+                                camera_len, light_len, &camera_storage, &light_storage, bvh);
+                        let w = self.weight(
+                                camera_len, light_len, &camera_storage, &light_storage);
+                        let contrib = &l * w;
+                        if !contrib.is_exactly_zero() {
+                            // The emitter is the end of the camera path if there's no light path.
+                            let light_index = if light_len == 0 {
+                                camera_storage[camera_len - 1].prim_index
+                            }
+                            else {
+                                light_storage[0].prim_index
+                            };
+                            add_to_light_group(light_groups, bvh, light_index, &contrib);
+                        }
+                        light = &light + &contrib;
                     }
                 }
             });
@@ -7,5 +7,8 @@ pub use render::film::{FilmSample, FilmPixel, Film};
 mod integrators;
 pub use render::integrators::*;
 
+mod plot;
+pub use render::plot::{LobePlot, PDF_LAYER, HISTOGRAM_LAYER};
+
 mod stage;
 pub use render::stage::Stage;
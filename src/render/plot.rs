@@ -0,0 +1,137 @@
+use geom;
+use material;
+use render::film;
+
+use core;
+
+use std;
+use rand;
+
+/// Name of the film layer that holds the pdf of the plotted lobe.
+pub const PDF_LAYER: &str = "pdf";
+/// Name of the film layer that holds the density of the sampled outgoing directions.
+pub const HISTOGRAM_LAYER: &str = "histogram";
+
+/// Plots the scattering of a lobe or material from a single incoming direction onto a film, which
+/// can be written with an ExrWriter. The film is a latitude-longitude map of the outgoing
+/// directions in the lobe's local space, with the normal (+z) at the top row and the -z axis at
+/// the bottom row, so that transmission shows up too.
+/// The main image is f(), the "pdf" layer is pdf(), and the "histogram" layer is the density of
+/// the directions returned by sample_f(), which should match the pdf layer. Both densities are
+/// with respect to solid angle.
+pub struct LobePlot {
+    pub incoming: core::Vec,
+    pub camera_to_light: bool,
+    /// The height of the film in pixels; its width is twice the height.
+    pub height: usize,
+    pub num_samples: usize,
+}
+
+impl LobePlot {
+    /// Creates a plot with the incoming direction at the given angle from the normal, in degrees.
+    pub fn new(theta: f32) -> LobePlot {
+        let mut plot = LobePlot {
+            incoming: core::Vec::z_axis(),
+            camera_to_light: true,
+            height: 256,
+            num_samples: 1000000,
+        };
+        plot.set_theta(theta);
+        plot
+    }
+
+    /// Sets the incoming direction to the given angle from the normal, in degrees, in the xz-plane.
+    pub fn set_theta(&mut self, theta: f32) {
+        let theta = theta.to_radians();
+        self.incoming = core::Vec::new(f32::sin(theta), 0.0, f32::cos(theta));
+    }
+
+    pub fn lobe(&self, lobe: &dyn material::Lobe, rng: &mut rand::XorShiftRng) -> film::Film {
+        self.plot(
+                |o| lobe.f(&self.incoming, o, self.camera_to_light),
+                |o| lobe.pdf(&self.incoming, o),
+                |rng| {
+                    let sample = lobe.sample_f(&self.incoming, self.camera_to_light, rng);
+                    if sample.pdf > 0.0 { Some(sample.outgoing) } else { None }
+                },
+                rng)
+    }
+
+    /// Plots all of the lobes of the material together, with the pdf of the material's choice of
+    /// lobe. The material is evaluated at a surface whose local space is world space.
+    pub fn material(&self, material: &material::Material, rng: &mut rand::XorShiftRng)
+        -> film::Film
+    {
+        let surface_props = geom::SurfaceProperties::new(
+                core::Vec::zero(), core::Vec::zero(), core::Vec::z_axis(), core::Vec::x_axis(),
+                core::Vec::y_axis(), core::Vec::z_axis(), core::Vec::zero());
+        let bsdf = material.bsdf(surface_props, &self.incoming);
+        self.plot(
+                |o| bsdf.f_world(&self.incoming, o, self.camera_to_light),
+                |o| bsdf.pdf_world(&self.incoming, o),
+                |rng| {
+                    let sample = bsdf.sample_world(&self.incoming, self.camera_to_light, rng);
+                    if sample.kind == material::LobeKind::LOBE_NONE {
+                        None
+                    }
+                    else {
+                        Some(sample.outgoing)
+                    }
+                },
+                rng)
+    }
+
+    fn direction(&self, row: f32, col: f32) -> core::Vec {
+        let theta = std::f32::consts::PI * row / self.height as f32;
+        let phi = core::TWO_PI * col / (2 * self.height) as f32;
+        core::Vec::from_spherical(f32::cos(theta), phi)
+    }
+
+    fn plot<F, P, S>(&self, f: F, pdf: P, mut sample: S, rng: &mut rand::XorShiftRng)
+        -> film::Film
+        where F: Fn(&core::Vec) -> core::Vec,
+              P: Fn(&core::Vec) -> f32,
+              S: FnMut(&mut rand::XorShiftRng) -> Option<core::Vec>
+    {
+        let (width, height) = (2 * self.height, self.height);
+        let mut film = film::Film::new(width, height);
+        let pdf_layer = film.add_layer(PDF_LAYER);
+        let histogram_layer = film.add_layer(HISTOGRAM_LAYER);
+        let num_pixels = width * height;
+
+        // Film rows go from the bottom of the image to the top.
+        let pixel_index = |row: usize, col: usize| core::index(height - row - 1, col, width);
+
+        let mut counts = vec![0usize; num_pixels];
+        for _ in 0..self.num_samples {
+            if let Some(o) = sample(rng) {
+                let theta = f32::acos(core::clamp(o.z, -1.0, 1.0));
+                let mut phi = f32::atan2(o.y, o.x);
+                if phi < 0.0 {
+                    phi += core::TWO_PI;
+                }
+                let row = usize::min(
+                        (theta / std::f32::consts::PI * height as f32) as usize, height - 1);
+                let col = usize::min((phi / core::TWO_PI * width as f32) as usize, width - 1);
+                counts[pixel_index(row, col)] += 1;
+            }
+        }
+
+        let d_phi = core::TWO_PI / width as f32;
+        for row in 0..height {
+            let cos_theta_top = self.direction(row as f32, 0.0).z;
+            let cos_theta_bottom = self.direction((row + 1) as f32, 0.0).z;
+            let solid_angle = (cos_theta_top - cos_theta_bottom) * d_phi;
+            for col in 0..width {
+                let o = self.direction(row as f32 + 0.5, col as f32 + 0.5);
+                let index = pixel_index(row, col);
+                film.pixels[index] = film::FilmPixel {accum: f(&o), weight: 1.0};
+                *film.layer_color_mut(pdf_layer, index) = pdf(&o) * &core::Vec::one();
+                let density = counts[index] as f32 / (self.num_samples as f32 * solid_angle);
+                *film.layer_color_mut(histogram_layer, index) = density * &core::Vec::one();
+            }
+        }
+
+        film
+    }
+}
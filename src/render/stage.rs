@@ -3,6 +3,7 @@ use render::integrators;
 
 use core;
 use geom;
+use material;
 
 use std;
 use rayon::prelude::*;
@@ -14,6 +15,7 @@ pub struct Stage {
 
 impl Stage {
     pub fn new(prims: std::vec::Vec<Box<geom::Prim>>) -> Stage {
+        material::build_albedo_tables();
         Stage {
             bvh: geom::Bvh::build(prims),
             sample_storage: vec![]
@@ -25,12 +27,13 @@ impl Stage {
         integrator: &integrators::Integrator,
         film: &mut film::Film)
     {
+        film.set_light_groups(self.bvh.light_groups());
         film.compute_sample_points(&mut self.sample_storage);
         let bvh = &self.bvh;
         self.sample_storage.par_iter_mut().for_each(|sample| {
             let ray = camera.compute_ray(sample.s, sample.t);
             let mut rng = core::new_xor_shift_rng();
-            sample.color = integrator.integrate(&ray, bvh, &mut rng);
+            sample.color = integrator.integrate(&ray, bvh, &mut rng, &mut sample.light_groups);
         });
         film.report_samples(&self.sample_storage);
     }
@@ -0,0 +1,323 @@
+// Statistical helpers shared by the integration tests. The chi-square test follows the one in
+// PBRT 3e (src/tests/bsdfs.cpp), which in turn follows Mitsuba's.
+
+#![allow(dead_code)]
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::render;
+
+use rand;
+use rand::{Rng, SeedableRng};
+
+/// Resolution of the (theta, phi) grid over the sphere that the chi-square test bins samples in.
+const CHI2_THETA_RES: usize = 10;
+const CHI2_PHI_RES: usize = 2 * CHI2_THETA_RES;
+
+/// Cells with fewer expected samples than this are pooled together.
+const CHI2_MIN_EXPECTED: f64 = 5.0;
+
+/// The significance level of each test run. Tests that make many runs should divide this by the
+/// number of runs (the Bonferroni correction).
+pub const SIGNIFICANCE: f64 = 0.01;
+
+pub fn new_rng() -> rand::XorShiftRng {
+    rand::XorShiftRng::from_seed([0x8f1bbcdc, 0x2d6fa3c1, 0x5be0cd19, 0x1f83d9ab])
+}
+
+/// Returns the unit vector with the given spherical coordinates.
+pub fn direction(theta: f32, phi: f32) -> core::Vec {
+    core::Vec::new(
+            f32::sin(theta) * f32::cos(phi), f32::sin(theta) * f32::sin(phi), f32::cos(theta))
+}
+
+/// Returns a direction distributed uniformly over the sphere.
+pub fn random_direction(rng: &mut rand::XorShiftRng) -> core::Vec {
+    let cos_theta = 2.0 * rng.next_f32() - 1.0;
+    core::Vec::from_spherical(cos_theta, core::TWO_PI * rng.next_f32())
+}
+
+/// Integrates a function over the sphere, with respect to solid angle.
+pub fn integrate_sphere<F>(f: F) -> f64 where F: Fn(&core::Vec) -> f64 {
+    let res = 16;
+    let d_theta = std::f64::consts::PI / res as f64;
+    let d_phi = 2.0 * std::f64::consts::PI / (2 * res) as f64;
+    let mut sum = 0.0;
+    for t in 0..res {
+        for p in 0..(2 * res) {
+            sum += integrate_region(&f, (t as f64 * d_theta, (t + 1) as f64 * d_theta),
+                    (p as f64 * d_phi, (p + 1) as f64 * d_phi));
+        }
+    }
+    sum
+}
+
+/// Integrates a function over a (theta, phi) region of the sphere, with respect to solid angle.
+/// The region is subdivided adaptively, since the lobes can be much narrower than the region.
+pub fn integrate_region<F>(f: &F, theta: (f64, f64), phi: (f64, f64)) -> f64
+    where F: Fn(&core::Vec) -> f64
+{
+    let whole = integrate_midpoint(f, theta, phi);
+    integrate_adaptive(f, theta, phi, whole, INTEGRATION_MAX_DEPTH)
+}
+
+/// Number of points per axis used by each step of the adaptive integration.
+const INTEGRATION_POINTS: usize = 8;
+const INTEGRATION_MAX_DEPTH: usize = 6;
+const INTEGRATION_TOLERANCE: f64 = 1e-7;
+
+fn integrate_midpoint<F>(f: &F, theta: (f64, f64), phi: (f64, f64)) -> f64
+    where F: Fn(&core::Vec) -> f64
+{
+    let n = INTEGRATION_POINTS;
+    let d_theta = (theta.1 - theta.0) / n as f64;
+    let d_phi = (phi.1 - phi.0) / n as f64;
+    let mut sum = 0.0;
+    for t in 0..n {
+        let theta = theta.0 + (t as f64 + 0.5) * d_theta;
+        for p in 0..n {
+            let phi = phi.0 + (p as f64 + 0.5) * d_phi;
+            sum += f(&direction(theta as f32, phi as f32)) * f64::sin(theta);
+        }
+    }
+    sum * d_theta * d_phi
+}
+
+fn integrate_adaptive<F>(f: &F, theta: (f64, f64), phi: (f64, f64), whole: f64, depth: usize)
+    -> f64
+    where F: Fn(&core::Vec) -> f64
+{
+    let theta_mid = 0.5 * (theta.0 + theta.1);
+    let phi_mid = 0.5 * (phi.0 + phi.1);
+    let quadrants = [
+        ((theta.0, theta_mid), (phi.0, phi_mid)), ((theta.0, theta_mid), (phi_mid, phi.1)),
+        ((theta_mid, theta.1), (phi.0, phi_mid)), ((theta_mid, theta.1), (phi_mid, phi.1))];
+    let parts: std::vec::Vec<f64> = quadrants.iter()
+            .map(|&(theta, phi)| integrate_midpoint(f, theta, phi))
+            .collect();
+    let sum: f64 = parts.iter().sum();
+    if depth == 0 || f64::abs(sum - whole) <= INTEGRATION_TOLERANCE {
+        sum
+    }
+    else {
+        quadrants.iter().zip(parts.iter())
+                .map(|(&(theta, phi), &part)| integrate_adaptive(f, theta, phi, part, depth - 1))
+                .sum()
+    }
+}
+
+fn cell_index(dir: &core::Vec) -> usize {
+    let theta = f32::acos(core::clamp(dir.z, -1.0, 1.0));
+    let mut phi = f32::atan2(dir.y, dir.x);
+    if phi < 0.0 {
+        phi += core::TWO_PI;
+    }
+    let t = usize::min(
+            (theta / std::f32::consts::PI * CHI2_THETA_RES as f32) as usize, CHI2_THETA_RES - 1);
+    let p = usize::min((phi / core::TWO_PI * CHI2_PHI_RES as f32) as usize, CHI2_PHI_RES - 1);
+    t * CHI2_PHI_RES + p
+}
+
+/// Runs Pearson's chi-square test of the hypothesis that the directions returned by the sample
+/// function are distributed according to the pdf, which is with respect to solid angle. The
+/// sample function returns None for samples that fail; the pdf should then integrate to the
+/// probability that sampling succeeds. Returns an error describing the failure, if any.
+pub fn chi_square_test<S, P>(num_samples: usize, significance: f64, mut sample: S, pdf: P)
+    -> Result<(), String>
+    where S: FnMut() -> Option<core::Vec>, P: Fn(&core::Vec) -> f32
+{
+    let num_cells = CHI2_THETA_RES * CHI2_PHI_RES;
+    let mut observed = vec![0.0f64; num_cells];
+    for _ in 0..num_samples {
+        if let Some(dir) = sample() {
+            observed[cell_index(&dir)] += 1.0;
+        }
+    }
+
+    let mut expected = vec![0.0f64; num_cells];
+    let d_theta = std::f64::consts::PI / CHI2_THETA_RES as f64;
+    let d_phi = 2.0 * std::f64::consts::PI / CHI2_PHI_RES as f64;
+    for t in 0..CHI2_THETA_RES {
+        for p in 0..CHI2_PHI_RES {
+            let mass = integrate_region(&|dir: &core::Vec| pdf(dir) as f64,
+                    (t as f64 * d_theta, (t + 1) as f64 * d_theta),
+                    (p as f64 * d_phi, (p + 1) as f64 * d_phi));
+            expected[t * CHI2_PHI_RES + p] = mass * num_samples as f64;
+        }
+    }
+
+    // Pool the cells with few expected samples, so that the chi-square distribution applies.
+    let mut order: std::vec::Vec<usize> = (0..num_cells).collect();
+    order.sort_by(|&a, &b| expected[a].partial_cmp(&expected[b]).unwrap());
+    let (mut pooled_observed, mut pooled_expected, mut pooled_cells) = (0.0, 0.0, 0);
+    let mut chi2 = 0.0;
+    let mut dof = 0;
+    for &cell in &order {
+        if expected[cell] == 0.0 {
+            if observed[cell] > num_samples as f64 * 1e-5 {
+                return Err(format!(
+                        "{} samples in cell {}, where the pdf is zero", observed[cell], cell));
+            }
+        }
+        else if expected[cell] < CHI2_MIN_EXPECTED {
+            pooled_observed += observed[cell];
+            pooled_expected += expected[cell];
+            pooled_cells += 1;
+        }
+        else if pooled_expected > 0.0 && pooled_expected < CHI2_MIN_EXPECTED {
+            // Merge this cell into the pool so that the pool has enough expected samples.
+            pooled_observed += observed[cell];
+            pooled_expected += expected[cell];
+            pooled_cells += 1;
+        }
+        else {
+            let diff = observed[cell] - expected[cell];
+            chi2 += diff * diff / expected[cell];
+            dof += 1;
+        }
+    }
+    if pooled_cells > 0 && pooled_expected > 0.0 {
+        let diff = pooled_observed - pooled_expected;
+        chi2 += diff * diff / pooled_expected;
+        dof += 1;
+    }
+    if dof < 2 {
+        // Everything is in one cell; there's nothing to compare.
+        return Ok(());
+    }
+    dof -= 1;
+
+    let p_value = 1.0 - regularized_gamma_p(dof as f64 / 2.0, chi2 / 2.0);
+    if p_value < significance {
+        Err(format!("chi-square statistic {} with {} degrees of freedom has p-value {}",
+                chi2, dof, p_value))
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// The regularized lower incomplete gamma function P(a, x), which gives the cdf of the
+/// chi-square distribution. See Numerical Recipes, 3rd edition, section 6.2.
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 1000;
+    const EPSILON: f64 = 1e-12;
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let log_prefix = a * f64::ln(x) - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // Series expansion.
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        let mut ap = a;
+        for _ in 0..MAX_ITERATIONS {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+            if f64::abs(term) < f64::abs(sum) * EPSILON {
+                break;
+            }
+        }
+        sum * f64::exp(log_prefix)
+    }
+    else {
+        // Continued fraction for the complement, evaluated with the modified Lentz method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if f64::abs(d) < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if f64::abs(c) < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if f64::abs(delta - 1.0) < EPSILON {
+                break;
+            }
+        }
+        1.0 - f64::exp(log_prefix) * h
+    }
+}
+
+/// The natural log of the gamma function, using the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46, -86.505_320_329_416_77, 24.014_098_240_830_91,
+        -1.231_739_572_450_155, 0.001_208_650_973_866_179, -0.000_005_395_239_384_953];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * f64::ln(tmp);
+    let mut series = 1.000_000_000_190_015;
+    let mut y = x;
+    for c in &COEFFICIENTS {
+        y += 1.0;
+        series += c / y;
+    }
+    -tmp + f64::ln(2.506_628_274_631_000_5 * series / x)
+}
+
+/// Resolution of the images rendered to compare integrators.
+const RENDER_WIDTH: usize = 24;
+const RENDER_HEIGHT: usize = 16;
+
+/// Number of films that a render's iterations are split between. The spread of the films'
+/// averages gives the standard error of the render.
+const RENDER_BATCHES: usize = 20;
+
+/// Number of standard errors by which two renders of the same scene may differ.
+const RENDER_MAX_ERRORS: f32 = 5.0;
+
+/// The average luminance of a rendered image, with the standard error of the average.
+pub struct Luminance {
+    pub mean: f32,
+    pub std_error: f32,
+}
+
+impl Luminance {
+    /// Asserts that two renders of the same scene agree to within their noise.
+    pub fn assert_agrees(&self, other: &Luminance) {
+        let std_error = f32::sqrt(
+                self.std_error * self.std_error + other.std_error * other.std_error);
+        assert!(f32::abs(self.mean - other.mean) <= RENDER_MAX_ERRORS * std_error,
+                "{} vs {} (standard error {})", self.mean, other.mean, std_error);
+    }
+}
+
+/// Renders the prims from the default camera for the given number of iterations, which must be a
+/// multiple of RENDER_BATCHES, and returns the average luminance of the image.
+pub fn render_average_luminance(prims: std::vec::Vec<Box<dyn geom::Prim>>,
+    integrator: &dyn render::Integrator, iterations: usize) -> Luminance
+{
+    let batch_iterations = iterations / RENDER_BATCHES;
+    assert!(batch_iterations * RENDER_BATCHES == iterations, "iterations={}", iterations);
+    let mut stage = render::Stage::new(prims);
+    let camera = core::Camera::default();
+    let batches: std::vec::Vec<f32> = (0..RENDER_BATCHES)
+            .map(|_| {
+                let mut film = render::Film::new(RENDER_WIDTH, RENDER_HEIGHT);
+                for _ in 0..batch_iterations {
+                    stage.trace(&camera, integrator, &mut film);
+                }
+                let sum: f32 = film.pixels.iter()
+                        .map(|pixel| (&pixel.accum / pixel.weight).luminance())
+                        .sum();
+                sum / film.pixels.len() as f32
+            })
+            .collect();
+
+    let n = RENDER_BATCHES as f32;
+    let mean = batches.iter().sum::<f32>() / n;
+    let variance = batches.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / (n - 1.0);
+    Luminance {mean, std_error: f32::sqrt(variance / n)}
+}
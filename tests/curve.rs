@@ -0,0 +1,54 @@
+// Curves are hit as ribbons facing the ray, with the curve-aligned frame and the v coordinate
+// across the fiber that hair shading expects.
+
+extern crate gammaray;
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::geom::Prim;
+use gammaray::material;
+
+const RADIUS: f32 = 0.1;
+
+fn fiber() -> geom::Curve {
+    geom::Curve::new(material::Material::hair().build(),
+            core::Vec::new(-1.0, 0.0, 0.0), core::Vec::new(1.0, 0.0, 0.0), RADIUS)
+}
+
+fn approx_eq(a: &core::Vec, b: &core::Vec) -> bool {
+    (a - b).magnitude() < 1e-5
+}
+
+#[test]
+fn rays_hit_across_the_width_of_curves() {
+    let curve = fiber();
+    for &h in &[-0.9, -0.5, 0.0, 0.5, 0.9] {
+        // The direction isn't unit length, so the distance is in units of it.
+        let ray = core::Ray::new(
+                core::Vec::new(0.5, h * RADIUS, 5.0), core::Vec::new(0.0, 0.0, -2.0));
+        let (dist, surface_props) = curve.intersect_world(&ray, 0);
+        assert!(f32::abs(dist - 2.5) < 1e-5, "h={}: dist={}", h, dist);
+        assert!(approx_eq(&surface_props.tangent, &core::Vec::x_axis()));
+        assert!(approx_eq(&surface_props.normal, &core::Vec::z_axis()));
+        assert!(approx_eq(&surface_props.binormal, &core::Vec::y_axis()));
+        assert!(f32::abs(surface_props.uv.x - 0.75) < 1e-5, "h={}: u={}", h, surface_props.uv.x);
+        assert!(f32::abs(-1.0 + 2.0 * surface_props.uv.y - h) < 1e-4,
+                "h={}: v={}", h, surface_props.uv.y);
+    }
+}
+
+#[test]
+fn rays_miss_past_the_ends_and_sides_of_curves() {
+    let curve = fiber();
+    let down = core::Vec::new(0.0, 0.0, -1.0);
+    let misses = [
+        core::Ray::new(core::Vec::new(0.0, 1.1 * RADIUS, 5.0), down),
+        core::Ray::new(core::Vec::new(1.1, 0.0, 5.0), down),
+        core::Ray::new(core::Vec::new(0.0, 0.0, -5.0), down),
+        core::Ray::new(core::Vec::new(-5.0, 0.0, 0.0), core::Vec::x_axis()),
+    ];
+    for ray in &misses {
+        assert!(curve.intersect_world(ray, 0).0 == 0.0,
+                "ray from {} toward {} hit", ray.origin, ray.direction);
+    }
+}
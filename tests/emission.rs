@@ -0,0 +1,109 @@
+// Materials that both emit and scatter light: the emission must be seen directly and must light
+// the rest of the scene, and the integrators must agree on the result.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use common::Luminance;
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::SurfaceBuilder;
+use gammaray::render;
+
+const ITERATIONS: usize = 300;
+/// Lights out of view are only seen through the noisier indirect paths.
+const INDIRECT_ITERATIONS: usize = 2000;
+
+fn glossy_screen(emission: core::Vec) -> material::Material {
+    material::Material::disney()
+            .base_color(core::Vec::new(0.2, 0.2, 0.2))
+            .roughness(0.2)
+            .clearcoat(1.0)
+            .emission(emission)
+            .emission_intensity(2.0)
+            .build()
+}
+
+/// An emitter that doesn't scatter, partly masked out.
+fn masked_light(emission: core::Vec, opacity: f32) -> material::Material {
+    material::Material::disney()
+            .base_color(core::Vec::zero())
+            .ior(1.0)
+            .emission(emission)
+            .opacity(opacity)
+            .build()
+}
+
+/// Renders a glossy emissive sphere next to a diffuse sphere, lit by a small light, and returns
+/// the average luminance of the image.
+fn render(emission: core::Vec, integrator: &dyn render::Integrator) -> Luminance {
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(glossy_screen(emission),
+                core::Mat::translation(&core::Vec::new(-6.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse(core::Vec::new(0.8, 0.8, 0.8)),
+                core::Mat::translation(&core::Vec::new(8.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse_light(core::Vec::new(4.0, 4.0, 4.0)),
+                core::Mat::translation(&core::Vec::new(0.0, 14.0, -40.0)), 3.0)),
+    ];
+    common::render_average_luminance(prims, integrator, ITERATIONS)
+}
+
+/// Renders a diffuse sphere lit only by an emissive sphere out of view, and returns the average
+/// luminance of the image.
+fn render_lit_by(light: material::Material, integrator: &dyn render::Integrator) -> Luminance {
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(material::Material::diffuse(core::Vec::new(0.8, 0.8, 0.8)),
+                core::Mat::translation(&core::Vec::new(0.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(light,
+                core::Mat::translation(&core::Vec::new(0.0, 16.0, -45.0)), 4.0)),
+    ];
+    common::render_average_luminance(prims, integrator, INDIRECT_ITERATIONS)
+}
+
+#[test]
+fn material_emits_and_scatters() {
+    let material = glossy_screen(core::Vec::new(1.0, 0.5, 0.25));
+    assert!(material.has_light());
+    assert!(!glossy_screen(core::Vec::zero()).has_light());
+
+    let surface_props = geom::SurfaceProperties::new(
+            core::Vec::zero(), core::Vec::zero(), core::Vec::z_axis(), core::Vec::x_axis(),
+            core::Vec::y_axis(), core::Vec::z_axis(), core::Vec::zero());
+    let incoming = core::Vec::new(0.6, 0.0, 0.8);
+    let emission = material.light_world(&incoming, &surface_props);
+    assert!(emission.x == 2.0 && emission.y == 1.0 && emission.z == 0.5, "emission={}", emission);
+
+    let mut rng = common::new_rng();
+    let sample = material.sample_world(&incoming, &surface_props, true, &mut rng);
+    assert!(sample.kind != material::LobeKind::LOBE_NONE);
+    assert!(sample.emission.x == 2.0);
+    assert!(!material.f_world(&incoming, &core::Vec::new(-0.6, 0.0, 0.8), &surface_props, true)
+            .is_exactly_zero());
+}
+
+#[test]
+fn integrators_agree() {
+    let emission = core::Vec::new(1.0, 0.5, 0.25);
+    let path_tracer = render(emission, &render::PathTracerIntegrator {});
+    let bdpt = render(emission, &render::BdptIntegrator {});
+    let unlit = render(core::Vec::zero(), &render::PathTracerIntegrator {});
+    path_tracer.assert_agrees(&bdpt);
+    assert!(path_tracer.mean > unlit.mean * 1.2, "lit={} unlit={}", path_tracer.mean, unlit.mean);
+}
+
+#[test]
+fn masked_lights_emit_their_opacity() {
+    // Rays pass through half of the masked light, so it should light the scene like an opaque
+    // light of half the brightness, whether it's hit by chance or sampled directly.
+    let emission = core::Vec::new(40.0, 20.0, 10.0);
+    let integrators: [&dyn render::Integrator; 2] =
+            [&render::PathTracerIntegrator {}, &render::BdptIntegrator {}];
+    for integrator in &integrators {
+        let masked = render_lit_by(masked_light(emission, 0.5), *integrator);
+        let halved = render_lit_by(masked_light(&emission * 0.5, 1.0), *integrator);
+        masked.assert_agrees(&halved);
+    }
+}
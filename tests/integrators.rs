@@ -0,0 +1,40 @@
+// The path tracer and the bidirectional path tracer must converge to the same image, including
+// on paths that pass through delta lobes, which BDPT can't connect to.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use common::Luminance;
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::render;
+
+const ITERATIONS: usize = 4000;
+
+/// Renders a smooth glass sphere next to a diffuse sphere, lit by a small light, and returns the
+/// average luminance of the image.
+fn render(integrator: &dyn render::Integrator) -> Luminance {
+    let glass = material::Material::disney()
+            .roughness(0.0)
+            .specular_trans(1.0)
+            .build();
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(glass,
+                core::Mat::translation(&core::Vec::new(-6.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse(core::Vec::new(0.8, 0.8, 0.8)),
+                core::Mat::translation(&core::Vec::new(8.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse_light(core::Vec::new(4.0, 4.0, 4.0)),
+                core::Mat::translation(&core::Vec::new(0.0, 14.0, -40.0)), 3.0)),
+    ];
+    common::render_average_luminance(prims, integrator, ITERATIONS)
+}
+
+#[test]
+fn integrators_agree_through_specular_surfaces() {
+    let path_tracer = render(&render::PathTracerIntegrator {});
+    let bdpt = render(&render::BdptIntegrator {});
+    path_tracer.assert_agrees(&bdpt);
+}
@@ -0,0 +1,120 @@
+// Material library presets must survive a round trip through the text format, and mistakes in
+// library files must be reported rather than ignored.
+
+extern crate gammaray;
+
+use gammaray::material::{MaterialLibrary, MaterialPreset, PresetKind, PresetValue};
+
+#[test]
+fn studio_presets_build() {
+    let library = MaterialLibrary::studio();
+    for name in &["glass", "brushed_aluminum", "car_paint", "plastic", "rubber", "skin"] {
+        assert!(library.build(name).is_ok(), "{} didn't build", name);
+    }
+}
+
+#[test]
+fn text_round_trip() {
+    let library = MaterialLibrary::studio();
+    let text = library.to_string();
+    let parsed = MaterialLibrary::parse(&text).unwrap();
+    assert_eq!(parsed.names(), library.names());
+    assert_eq!(parsed.to_string(), text);
+}
+
+#[test]
+fn overrides_replace_parameters() {
+    let library = MaterialLibrary::studio();
+    let base_color = PresetValue::Color(gammaray::core::Vec::new(0.1, 0.2, 0.9));
+    let material = library.build_with("plastic", &[("base_color", base_color)]).unwrap();
+    assert_eq!(material.display_color().z, 0.9);
+    assert!(library.build_with("plastic", &[("gloss", PresetValue::Number(1.0))]).is_err());
+    assert!(library.build("velvet").is_err());
+}
+
+#[test]
+fn merged_libraries_resolve_bases() {
+    let mut library = MaterialLibrary::parse("
+        # A lacquer over a preset from another library.
+        [lacquered_aluminum]
+        type = layered
+        base = brushed_aluminum
+        tint = 0.9 0.8 0.6
+    ").unwrap();
+    assert!(library.build("lacquered_aluminum").is_err());
+    library.merge(MaterialLibrary::studio());
+    assert!(library.build("lacquered_aluminum").is_ok());
+
+    let mut cycle = MaterialLibrary::new();
+    cycle.insert("loop", MaterialPreset::new(PresetKind::Layered)
+            .set("base", PresetValue::Name(String::from("loop")))
+            .clone());
+    assert!(cycle.build("loop").is_err());
+}
+
+#[test]
+fn parse_errors() {
+    let bad = [
+        "type = disney",
+        "[glass]\nroughness = 0",
+        "[glass]\ntype = velvet",
+        "[glass]\ntype = disney\nroughness = 0\nroughness = 1",
+        "[glass]\ntype = disney\n[glass]\ntype = disney",
+        "[glass]\ntype = disney\nbase_color = 1 1",
+        "[glass]\ntype = disney\nroughness = true",
+        "[rim]\ntype = mix\na = 1\nb = 0",
+        "[rim]\ntype = clamp\ninput = 1\nlimit = 2",
+        "[glass]\ntype = disney\nshininess = 1",
+        "[my glass]\ntype = disney",
+    ];
+    for text in &bad {
+        assert!(MaterialLibrary::parse(text).is_err(), "parsed {:?}", text);
+    }
+
+    // Names are references to nodes, which may be merged in later.
+    let library = MaterialLibrary::parse("[glass]\ntype = disney\nroughness = smooth").unwrap();
+    assert!(library.build("glass").is_err());
+}
+
+#[test]
+fn node_graphs_build_and_round_trip() {
+    let text = "\
+[facing]
+type = facing_ratio
+
+[rim_color]
+type = mix
+a = 0.2 0.05 0.05
+b = 0.9 0.1 0.1
+t = facing
+
+[stripes]
+type = remap
+input = position_y
+from_min = -1
+from_max = 1
+
+[position_y]
+type = multiply
+a = position
+b = 0 1 0
+
+[position]
+type = position
+
+[rim_plastic]
+type = disney
+base_color = rim_color
+roughness = stripes
+";
+    let library = MaterialLibrary::parse(text).unwrap();
+    assert!(library.build("rim_plastic").is_ok());
+    assert!(library.build_node("stripes").is_ok());
+    assert_eq!(MaterialLibrary::parse(&library.to_string()).unwrap().to_string(),
+            library.to_string());
+
+    let mut cycle = MaterialLibrary::parse("[a]\ntype = clamp\ninput = b").unwrap();
+    cycle.merge(MaterialLibrary::parse("[b]\ntype = clamp\ninput = a").unwrap());
+    assert!(cycle.build_node("a").is_err());
+    assert!(library.build_node("missing").is_err());
+}
@@ -0,0 +1,132 @@
+// Area lights: integrating the radiance that lights in physical units emit over the surface and
+// over every direction must give back the power they were asked for, sampling must reach every
+// direction that a light emits in, and only lights that name a light group get their own output.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::Light;
+
+use std::sync::Arc;
+
+const AREA: f32 = 2.5;
+const TOLERANCE: f64 = 0.01;
+
+/// A rotationally symmetric profile whose intensity falls off linearly to zero at the horizon.
+const FALLOFF_PROFILE: &str = "IESNA:LM-63-2002
+TILT=NONE
+1 -1 1 3 1 1 2 0 0 0
+1 1 0
+0 45 90
+0
+100 50 0
+";
+
+/// The surface that the light is evaluated on, whose local space is world space.
+fn surface() -> geom::SurfaceProperties {
+    geom::SurfaceProperties::new(
+            core::Vec::zero(), core::Vec::zero(), core::Vec::z_axis(), core::Vec::x_axis(),
+            core::Vec::y_axis(), core::Vec::z_axis(), core::Vec::zero())
+}
+
+/// Integrates the luminance of the emitted radiance times the cosine to the normal over the
+/// sphere, and over the area of the light, which emits the same everywhere.
+fn emitted_power(light: &dyn Light) -> f64 {
+    let surface_props = surface();
+    let intensity = common::integrate_sphere(|dir| {
+        (light.l_world(dir, &surface_props).luminance() * f32::abs(dir.z)) as f64
+    });
+    intensity * AREA as f64
+}
+
+#[test]
+fn lights_emit_their_power() {
+    let profile = Arc::new(material::IesProfile::parse(FALLOFF_PROFILE).unwrap());
+    let color = core::Vec::new(1.0, 0.6, 0.2);
+    let cases = [
+        (material::LightUnits::Lumens, 1200.0, 1200.0 / material::LUMINOUS_EFFICACY),
+        (material::LightUnits::LuminousWatts, 3.0, 3.0),
+    ];
+    for &(units, intensity, expected) in &cases {
+        for &two_sided in &[false, true] {
+            for profile in &[None, Some(profile.clone())] {
+                let mut light = material::DiffuseAreaLight::new_with_options(
+                        color, intensity, units, two_sided, None, profile.clone());
+                assert!(emitted_power(&light) == 0.0,
+                        "{:?} emitted light before its area was known", units);
+
+                light.set_surface_area(AREA);
+                let power = emitted_power(&light);
+                assert!(f64::abs(power / expected as f64 - 1.0) < TOLERANCE,
+                        "{:?} two_sided={} profile={}: power={} expected={}",
+                        units, two_sided, profile.is_some(), power, expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn only_named_light_groups_get_layers() {
+    let key = material::Material::area_light().group("key").build();
+    let fill = material::Material::area_light().build();
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(
+                key, core::Mat::translation(&core::Vec::new(0.0, 4.0, 0.0)), 1.0)),
+        Box::new(geom::Sphere::new(
+                fill, core::Mat::translation(&core::Vec::new(0.0, -4.0, 0.0)), 1.0)),
+    ];
+    let bvh = geom::Bvh::build(prims);
+    assert_eq!(bvh.light_groups(), &[String::from("key")]);
+    let groups: Vec<usize> = (0..2).filter_map(|i| bvh.light_group(i)).collect();
+    assert_eq!(groups, vec![0]);
+}
+
+/// A dim spot with a bright, narrow ring at 45 degrees, which falls between the centers of the
+/// cells of the distribution that directions are sampled from.
+const RING_PROFILE: &str = "IESNA:LM-63-2002
+TILT=NONE
+1 -1 1 7 1 1 2 0 0 0
+1 1 0
+0 30 40 44.9 45 45.1 90
+0
+10 10 0 0 100 0 0
+";
+
+const RING_SAMPLES: usize = 200000;
+const RING_THETA_STEPS: usize = 200000;
+
+#[test]
+fn sampled_directions_cover_the_profile() {
+    let profile = Arc::new(material::IesProfile::parse(RING_PROFILE).unwrap());
+    let light = material::DiffuseAreaLight::new_with_options(
+            core::Vec::one(), 1.0, material::LightUnits::Radiance, false, None, Some(profile));
+    let surface_props = surface();
+    let emitted = |dir: &core::Vec| light.l_world(dir, &surface_props).luminance() as f64;
+
+    // The profile is rotationally symmetric, so integrate it finely over theta alone.
+    let d_theta = std::f64::consts::FRAC_PI_2 / RING_THETA_STEPS as f64;
+    let mut expected = 0.0;
+    for step in 0..RING_THETA_STEPS {
+        let theta = (step as f64 + 0.5) * d_theta;
+        let dir = common::direction(theta as f32, 0.0);
+        expected += emitted(&dir) * f64::cos(theta) * f64::sin(theta) * d_theta;
+    }
+    expected *= 2.0 * std::f64::consts::PI;
+
+    let mut rng = common::new_rng();
+    let mut estimate = 0.0;
+    for _ in 0..RING_SAMPLES {
+        let (dir, pdf) = light.sample_dir_world(&surface_props, &mut rng);
+        if pdf > 0.0 {
+            estimate += emitted(&dir) * dir.z as f64 / pdf as f64;
+        }
+    }
+    estimate /= RING_SAMPLES as f64;
+    assert!(f64::abs(estimate / expected - 1.0) < TOLERANCE,
+            "estimate={} expected={}", estimate, expected);
+}
@@ -0,0 +1,312 @@
+// Statistical validation of the lobes: importance sampling must match the pdf, the BSDFs must obey
+// the adjoint relation between the camera and light directions, and no lobe may scatter more light
+// than it receives.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::{Lobe, LobeKind, Material, SharedLobe};
+use gammaray::render;
+
+use std::sync::Arc;
+
+const CHI2_SAMPLES: usize = 100000;
+const CHECK_SAMPLES: usize = 10000;
+const FURNACE_SAMPLES: usize = 100000;
+const FURNACE_TOLERANCE: f32 = 0.03;
+const PDF_INTEGRAL_TOLERANCE: f64 = 0.01;
+const RECIPROCITY_PAIRS: usize = 2000;
+const RELATIVE_TOLERANCE: f32 = 1e-3;
+
+/// Cosines of the incoming directions to test with. The lobes are mostly used with the incoming
+/// direction above the surface, but transmissive lobes see both sides.
+const INCOMING_COS_THETA: [f32; 5] = [1.0, 0.7, 0.3, 0.05, -0.6];
+
+/// Blinn-Phong and Lambertian lobes tabulated in the MERL format; see MerlBrdf.
+fn merl_phong(exponent: f32, specular: f32, diffuse: f32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for dim in &[90i32, 90, 180] {
+        bytes.extend_from_slice(&dim.to_le_bytes());
+    }
+    let scales = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+    for scale in &scales {
+        for theta_half_index in 0..90 {
+            // The half angle is indexed by the square root of the angle.
+            let t = (theta_half_index as f32 + 0.5) / 90.0;
+            let theta_half = t * t * std::f32::consts::FRAC_PI_2;
+            let value = diffuse / std::f32::consts::PI +
+                    specular * (exponent + 2.0) / (8.0 * std::f32::consts::PI) *
+                    f32::powf(f32::cos(theta_half), exponent);
+            for _ in 0..(90 * 180) {
+                bytes.extend_from_slice(&(value as f64 / scale).to_le_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+struct Case {
+    name: String,
+    lobe: SharedLobe,
+    /// The direction in which the lobe conserves energy. Transmission conserves energy when light
+    /// is traced toward the camera, since radiance is scaled by the square of the relative eta in
+    /// the other direction; the hair lobe is normalized over light directions, like PBRT's.
+    camera_to_light: bool,
+}
+
+impl Case {
+    fn new(lobe: Box<dyn Lobe>) -> Case {
+        Case {name: format!("{}", lobe), lobe: SharedLobe::Owned(lobe), camera_to_light: false}
+    }
+}
+
+/// All of the lobes to test. White lobes are used where possible so that the furnace test is as
+/// strict as it can be.
+fn lobes() -> Vec<Case> {
+    let white = core::Vec::one();
+    let mut cases = vec![
+        Case::new(Box::new(material::PerfectDiffuse::new(white))),
+        Case::new(Box::new(material::OrenNayar::new(white, 30.0))),
+        Case::new(Box::new(material::DisneyDiffuseRefl::new(white, 0.3, 0.5, 0.5, 1.0))),
+        Case::new(Box::new(material::DisneySpecularRefl::new(white, 0.3, 1.5, 0.0, 1.0))),
+        Case::new(Box::new(
+                material::DisneySpecularRefl::new_aniso(white, 0.6, 0.8, 1.5, 0.0, 0.0))),
+        Case::new(Box::new(
+                material::ConductorRefl::new(material::ConductorFresnel::gold(), 0.4))),
+        Case::new(Box::new(material::DielectricRefl::new(0.5, 1.5))),
+        Case::new(Box::new(material::ThinFilmRefl::new_aniso(
+                material::ThinFilmFresnel::new(&material::DielectricFresnel::new(1.5), 400.0, 1.33),
+                0.4, 0.0))),
+        Case::new(Box::new(material::DisneyClearcoatRefl::new(1.0, 0.5))),
+        Case::new(Box::new(material::DisneySpecularTrans::new(white, 0.4, 1.5))),
+        Case::new(Box::new(material::DisneySpecularTrans::new_aniso(white, 0.8, 0.5, 1.33))),
+        Case::new(Box::new(material::DisneyThinTrans::new(white, 0.5, 1.5))),
+        Case::new(Box::new(material::DiffuseTrans::new(white))),
+        Case {
+            camera_to_light: true,
+            ..Case::new(Box::new(material::HairLobe::new(
+                    0.3, 1.55, core::Vec::new(0.1, 0.2, 0.4), 0.3, 0.3, 2.0)))
+        },
+        Case::new(Box::new(material::MeasuredRefl::new(
+                Arc::new(material::MerlBrdf::parse(&merl_phong(30.0, 0.5, 0.2)).unwrap())))),
+    ];
+
+    // The mix and coating lobes are private, so they're reached through their materials.
+    let diffuse = Arc::new(Material::diffuse(core::Vec::new(0.8, 0.5, 0.2)));
+    let conductor = Arc::new(Material::conductor().build());
+    let materials = vec![
+        Arc::new(Material::mix(
+                diffuse.clone(), conductor, material::MaterialParam::Constant(0.3))),
+        Arc::new(Material::layered(diffuse).roughness(0.3).build()),
+    ];
+    for material in &materials {
+        for lobe in Material::shared_lobes(material, &geom::SurfaceProperties::zero()) {
+            cases.push(Case {name: format!("{}", &*lobe), lobe, camera_to_light: false});
+        }
+    }
+    cases
+}
+
+fn is_delta(lobe: &dyn Lobe) -> bool {
+    lobe.kind().intersects(LobeKind::LOBE_SPECULAR | LobeKind::LOBE_SUBSURFACE)
+}
+
+fn incoming(cos_theta: f32) -> core::Vec {
+    // Avoid the plane of symmetry of the anisotropic lobes.
+    common::direction(f32::acos(cos_theta), 0.6)
+}
+
+fn max_component(v: &core::Vec) -> f32 {
+    f32::max(v.x, f32::max(v.y, v.z))
+}
+
+fn max_abs_component(v: &core::Vec) -> f32 {
+    f32::max(f32::abs(v.x), f32::max(f32::abs(v.y), f32::abs(v.z)))
+}
+
+fn approx_eq(a: &core::Vec, b: &core::Vec) -> bool {
+    let scale = f32::max(max_abs_component(a), max_abs_component(b));
+    max_abs_component(&(a - b)) <= RELATIVE_TOLERANCE * scale + 1e-6
+}
+
+#[test]
+fn sampling_matches_pdf() {
+    let mut rng = common::new_rng();
+    let lobes = lobes();
+    let runs = lobes.len() * INCOMING_COS_THETA.len();
+    let mut failures = vec![];
+    for case in &lobes {
+        let (name, lobe) = (&case.name, &case.lobe);
+        if is_delta(&**lobe) {
+            continue;
+        }
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            let result = common::chi_square_test(
+                    CHI2_SAMPLES, common::SIGNIFICANCE / runs as f64,
+                    || {
+                        let sample = lobe.sample_f(&i, true, &mut rng);
+                        if sample.pdf > 0.0 { Some(sample.outgoing) } else { None }
+                    },
+                    |o| lobe.pdf(&i, o));
+            if let Err(message) = result {
+                failures.push(format!("{} cos_theta={}: {}", name, cos_theta, message));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn sample_f_matches_f_and_pdf() {
+    let mut rng = common::new_rng();
+    for case in &lobes() {
+        let (name, lobe) = (&case.name, &case.lobe);
+        if is_delta(&**lobe) {
+            continue;
+        }
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            for &camera_to_light in &[true, false] {
+                for _ in 0..CHECK_SAMPLES {
+                    let sample = lobe.sample_f(&i, camera_to_light, &mut rng);
+                    if sample.pdf == 0.0 {
+                        continue;
+                    }
+                    let o = sample.outgoing;
+                    let pdf = lobe.pdf(&i, &o);
+                    assert!(f32::abs(pdf - sample.pdf) <= RELATIVE_TOLERANCE * sample.pdf,
+                            "{} i={} o={}: sampled pdf={} pdf={}", name, i, o, sample.pdf, pdf);
+                    let f = lobe.f(&i, &o, camera_to_light);
+                    assert!(approx_eq(&sample.result, &f),
+                            "{} i={} o={}: sampled f={} f={}", name, i, o, sample.result, f);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn reciprocity() {
+    let mut rng = common::new_rng();
+    for case in &lobes() {
+        let (name, lobe) = (&case.name, &case.lobe);
+        if is_delta(&**lobe) {
+            continue;
+        }
+        // Only reflection conserves radiance, so only reflection BSDFs are symmetric.
+        let symmetric = !lobe.kind().contains(LobeKind::LOBE_TRANSMISSION);
+        for _ in 0..RECIPROCITY_PAIRS {
+            let i = common::random_direction(&mut rng);
+            let o = common::random_direction(&mut rng);
+            let f = lobe.f(&i, &o, true);
+            let adjoint = lobe.f(&o, &i, false);
+            assert!(approx_eq(&f, &adjoint),
+                    "{} i={} o={}: f={} adjoint={}", name, i, o, f, adjoint);
+            if symmetric {
+                let swapped = lobe.f(&o, &i, true);
+                assert!(approx_eq(&f, &swapped),
+                        "{} i={} o={}: f={} swapped={}", name, i, o, f, swapped);
+            }
+        }
+    }
+}
+
+#[test]
+fn energy_conservation() {
+    let mut rng = common::new_rng();
+    for case in &lobes() {
+        let (name, lobe) = (&case.name, &case.lobe);
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            let mut sum = core::Vec::zero();
+            for _ in 0..FURNACE_SAMPLES {
+                let sample = lobe.sample_f(&i, case.camera_to_light, &mut rng);
+                if sample.pdf > 0.0 {
+                    let weight = sample.outgoing.abs_cos_theta() / sample.pdf;
+                    sum = &sum + &(weight * &sample.result);
+                }
+            }
+            let albedo = max_component(&sum) / FURNACE_SAMPLES as f32;
+            assert!(albedo <= 1.0 + FURNACE_TOLERANCE,
+                    "{} cos_theta={}: albedo={}", name, cos_theta, albedo);
+        }
+    }
+}
+
+#[test]
+fn pdf_integrates_to_at_most_one() {
+    for case in &lobes() {
+        let (name, lobe) = (&case.name, &case.lobe);
+        if is_delta(&**lobe) {
+            continue;
+        }
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            let integral = common::integrate_sphere(|o| lobe.pdf(&i, o) as f64);
+            assert!(integral <= 1.0 + PDF_INTEGRAL_TOLERANCE,
+                    "{} cos_theta={}: pdf integral={}", name, cos_theta, integral);
+        }
+    }
+}
+
+#[test]
+fn delta_lobes_only_sample() {
+    let white = core::Vec::one();
+    let medium = material::SubsurfaceMedium::from_color_and_distance(&white, &white);
+    let lobes: Vec<Box<dyn Lobe>> = vec![
+        Box::new(material::SmoothDielectric::new(white, white, 1.5)),
+        Box::new(material::PerfectMirror::new()),
+        Box::new(material::DisneyThinTrans::new(white, 0.0, 1.5)),
+        Box::new(material::DisneySubsurface::new(white, medium)),
+    ];
+    let mut rng = common::new_rng();
+    for lobe in &lobes {
+        assert!(is_delta(&**lobe), "{} isn't a delta lobe", lobe);
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            for _ in 0..CHECK_SAMPLES {
+                let o = common::random_direction(&mut rng);
+                assert!(lobe.f(&i, &o, true).is_exactly_zero(), "{} has nonzero f", lobe);
+                assert!(lobe.pdf(&i, &o) == 0.0, "{} has nonzero pdf", lobe);
+
+                let sample = lobe.sample_f(&i, true, &mut rng);
+                assert!(sample.pdf == 1.0 || sample.pdf == 0.0,
+                        "{} sampled with pdf={}", lobe, sample.pdf);
+            }
+        }
+    }
+}
+
+#[test]
+fn lobe_plot_histogram_matches_pdf() {
+    let mut plot = render::LobePlot::new(30.0);
+    plot.height = 16;
+    plot.num_samples = 400000;
+    let lobe = material::PerfectDiffuse::new(core::Vec::one());
+    let film = plot.lobe(&lobe, &mut common::new_rng());
+    let layer = |name: &str| film.layers.iter().position(|x| x == name).unwrap();
+    let (pdf_layer, histogram_layer) = (layer(render::PDF_LAYER), layer(render::HISTOGRAM_LAYER));
+
+    // Individual pixels are noisy, so compare the average density of each row.
+    let mut max_pdf = 0.0;
+    for row in 0..film.height {
+        let (mut pdf, mut histogram) = (0.0, 0.0);
+        for col in 0..film.width {
+            let index = core::index(row, col, film.width);
+            pdf += film.layer_color(pdf_layer, index).x;
+            histogram += film.layer_color(histogram_layer, index).x;
+        }
+        pdf /= film.width as f32;
+        histogram /= film.width as f32;
+        assert!(f32::abs(pdf - histogram) < 0.01, "row={} pdf={} histogram={}",
+                row, pdf, histogram);
+        max_pdf = f32::max(max_pdf, pdf);
+    }
+    assert!(max_pdf > 0.3, "max_pdf={}", max_pdf);
+}
@@ -0,0 +1,161 @@
+// Statistical validation of the microfacet distributions: the normals must be normalized, sampling
+// must match the pdf, and the masking-shadowing term must be symmetric and lose energy rather than
+// create it.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use gammaray::core;
+use gammaray::material;
+use gammaray::material::MicrofacetDistribution;
+
+const CHI2_SAMPLES: usize = 1000000;
+const INTEGRAL_TOLERANCE: f64 = 0.01;
+const FURNACE_TOLERANCE: f64 = 0.01;
+const RECIPROCITY_PAIRS: usize = 2000;
+
+const INCOMING_COS_THETA: [f32; 5] = [1.0, 0.7, 0.3, 0.05, -0.6];
+
+struct Case {
+    name: String,
+    distribution: Box<dyn MicrofacetDistribution>,
+    /// Whether only the microfacets visible from the incoming direction are sampled, as opposed
+    /// to all of the microfacets on the incoming direction's side of the surface.
+    visible_normals: bool,
+}
+
+fn ggx_cases() -> Vec<Case> {
+    let mut cases = vec![];
+    for &(roughness, anisotropic) in &[(0.2, 0.0), (0.5, 0.0), (0.5, 0.8), (1.0, 0.0)] {
+        cases.push(Case {
+            name: format!("GGX(roughness={}, anisotropic={})", roughness, anisotropic),
+            distribution: Box::new(material::GgxDistribution::new(roughness, anisotropic)),
+            visible_normals: true,
+        });
+    }
+    cases
+}
+
+fn gtr1_cases() -> Vec<Case> {
+    let mut cases = vec![];
+    for &gloss in &[0.0, 0.5] {
+        cases.push(Case {
+            name: format!("GTR1(gloss={})", gloss),
+            distribution: Box::new(material::Gtr1Distribution::new(gloss)),
+            visible_normals: false,
+        });
+    }
+    cases
+}
+
+fn cases() -> Vec<Case> {
+    let mut cases = ggx_cases();
+    cases.extend(gtr1_cases());
+    cases
+}
+
+fn incoming(cos_theta: f32) -> core::Vec {
+    common::direction(f32::acos(cos_theta), 0.6)
+}
+
+/// The pdf of the half vector, restricted to the half vectors that the distribution samples.
+fn half_pdf(case: &Case, i: &core::Vec, half: &core::Vec) -> f32 {
+    if i.z * half.z <= 0.0 || (case.visible_normals && i.dot(half) <= 0.0) {
+        0.0
+    }
+    else {
+        case.distribution.pdf(i, half)
+    }
+}
+
+/// Checks the sampled half vectors against the pdf, with a chi-square test for each of the
+/// incoming directions.
+fn assert_sampling_matches_pdf(cases: &[Case]) {
+    let mut rng = common::new_rng();
+    let runs = cases.len() * INCOMING_COS_THETA.len();
+    let mut failures = vec![];
+    for case in cases {
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            let result = common::chi_square_test(
+                    CHI2_SAMPLES, common::SIGNIFICANCE / runs as f64,
+                    || Some(case.distribution.sample_half(&i, &mut rng)),
+                    |half| half_pdf(case, &i, half));
+            if let Err(message) = result {
+                failures.push(format!("{} cos_theta={}: {}", case.name, cos_theta, message));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn distribution_is_normalized() {
+    for case in &cases() {
+        // The projected area of the microfacets equals the area of the surface.
+        let integral = common::integrate_sphere(|half| {
+            if half.z > 0.0 { (case.distribution.d(half) * half.z) as f64 } else { 0.0 }
+        });
+        assert!(f64::abs(integral - 1.0) < INTEGRAL_TOLERANCE,
+                "{}: projected area={}", case.name, integral);
+    }
+}
+
+#[test]
+fn pdf_integrates_to_one() {
+    for case in &cases() {
+        for &cos_theta in &INCOMING_COS_THETA {
+            let i = incoming(cos_theta);
+            let integral = common::integrate_sphere(|half| half_pdf(case, &i, half) as f64);
+            assert!(f64::abs(integral - 1.0) < INTEGRAL_TOLERANCE,
+                    "{} cos_theta={}: pdf integral={}", case.name, cos_theta, integral);
+        }
+    }
+}
+
+#[test]
+fn ggx_sampling_matches_pdf() {
+    assert_sampling_matches_pdf(&ggx_cases());
+}
+
+#[test]
+fn gtr1_sampling_matches_pdf() {
+    assert_sampling_matches_pdf(&gtr1_cases());
+}
+
+#[test]
+fn masking_is_reciprocal() {
+    let mut rng = common::new_rng();
+    for case in &cases() {
+        for _ in 0..RECIPROCITY_PAIRS {
+            let i = common::random_direction(&mut rng);
+            let o = common::random_direction(&mut rng);
+            let g = case.distribution.g(&i, &o);
+            let swapped = case.distribution.g(&o, &i);
+            assert!(f32::abs(g - swapped) <= 1e-5,
+                    "{} i={} o={}: g={} swapped={}", case.name, i, o, g, swapped);
+        }
+    }
+}
+
+#[test]
+fn weak_white_furnace() {
+    // With a perfectly reflective Fresnel term, the microfacets reflect at most all of the light;
+    // they'd reflect exactly all of it if masking were ignored on the outgoing side.
+    for case in &cases() {
+        for &cos_theta in &INCOMING_COS_THETA[..4] {
+            let i = incoming(cos_theta);
+            let albedo = common::integrate_sphere(|o| {
+                if o.z <= 0.0 {
+                    return 0.0;
+                }
+                let half = (&i + o).normalized();
+                (case.distribution.d(&half) * case.distribution.g(&i, o) / (4.0 * i.z)) as f64
+            });
+            assert!(albedo <= 1.0 + FURNACE_TOLERANCE,
+                    "{} cos_theta={}: albedo={}", case.name, cos_theta, albedo);
+        }
+    }
+}
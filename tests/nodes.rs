@@ -0,0 +1,171 @@
+// Shader graph nodes: each node must compute the value it's documented to, and graphs must vary
+// the lobes of a material with the shading point and the direction it's seen from.
+
+extern crate gammaray;
+extern crate rand;
+
+mod common;
+
+use common::Luminance;
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::{ShaderNode, Texture};
+use gammaray::render;
+
+use std::sync::Arc;
+
+const ITERATIONS: usize = 2000;
+
+fn surface_props() -> geom::SurfaceProperties {
+    geom::SurfaceProperties::new(
+            core::Vec::new(1.0, 2.0, 3.0), core::Vec::zero(), core::Vec::z_axis(),
+            core::Vec::x_axis(), core::Vec::y_axis(), core::Vec::z_axis(),
+            core::Vec::new(0.25, 0.75, 0.0))
+}
+
+fn constant(x: f32) -> Arc<ShaderNode> {
+    Arc::new(ShaderNode::Constant(x * &core::Vec::one()))
+}
+
+fn assert_close(actual: core::Vec, expected: core::Vec) {
+    assert!((&actual - &expected).magnitude() < 1e-5, "got {}, expected {}", actual, expected);
+}
+
+#[test]
+fn geometric_inputs() {
+    let sp = surface_props();
+    assert_close(ShaderNode::Normal.eval(&sp), core::Vec::z_axis());
+    assert_close(ShaderNode::Uv.eval(&sp), core::Vec::new(0.25, 0.75, 0.0));
+    assert_close(ShaderNode::Position.eval(&sp), core::Vec::new(1.0, 2.0, 3.0));
+}
+
+#[test]
+fn math_nodes() {
+    let sp = surface_props();
+    let position = Arc::new(ShaderNode::Position);
+    assert_close(ShaderNode::Mix(constant(0.0), constant(2.0), constant(0.25)).eval(&sp),
+            0.5 * &core::Vec::one());
+    assert_close(ShaderNode::Multiply(position.clone(), constant(2.0)).eval(&sp),
+            core::Vec::new(2.0, 4.0, 6.0));
+    let remap = ShaderNode::Remap {
+        input: position.clone(), from_min: 1.0, from_max: 3.0, to_min: 10.0, to_max: 0.0
+    };
+    assert_close(remap.eval(&sp), core::Vec::new(10.0, 5.0, 0.0));
+    let clamp = ShaderNode::Clamp {input: position, min: 1.5, max: 2.5};
+    assert_close(clamp.eval(&sp), core::Vec::new(1.5, 2.0, 2.5));
+}
+
+#[test]
+fn view_dependent_nodes() {
+    let mut sp = surface_props();
+    assert_close(ShaderNode::FacingRatio.eval(&sp), core::Vec::one());
+    sp.incoming = core::Vec::new(0.8, 0.0, 0.6);
+    assert_close(ShaderNode::FacingRatio.eval(&sp), 0.6 * &core::Vec::one());
+
+    let fresnel = ShaderNode::Fresnel(1.5);
+    sp.incoming = core::Vec::z_axis();
+    assert_close(fresnel.eval(&sp), 0.04 * &core::Vec::one());
+    sp.incoming = core::Vec::new(0.8, 0.0, 0.6);
+    let grazing = fresnel.eval(&sp);
+    assert!(grazing.x > 0.04, "fresnel={}", grazing);
+}
+
+#[test]
+fn texture_lookup_at_computed_uv() {
+    let pixels = vec![
+        core::Vec::new(1.0, 0.0, 0.0), core::Vec::new(0.0, 1.0, 0.0),
+        core::Vec::new(0.0, 0.0, 1.0), core::Vec::new(1.0, 1.0, 1.0),
+    ];
+    let texture: Arc<dyn Texture> = Arc::new(material::ImageTexture::new(
+            2, 2, pixels, material::WrapMode::Clamp));
+    let sp = surface_props();
+    // The top-left pixel is at uv = (0, 1).
+    let uv = Arc::new(ShaderNode::Constant(core::Vec::new(0.0, 1.0, 0.0)));
+    assert_close(ShaderNode::Texture(texture.clone(), Some(uv)).eval(&sp),
+            core::Vec::new(1.0, 0.0, 0.0));
+    assert_close(ShaderNode::Texture(texture.clone(), None).eval(&sp),
+            texture.eval(&sp));
+}
+
+#[test]
+fn graphs_drive_lobes_by_view_direction() {
+    // Rough when seen head-on and smooth at grazing angles.
+    let roughness: Arc<dyn Texture> = Arc::new(ShaderNode::Remap {
+        input: Arc::new(ShaderNode::FacingRatio),
+        from_min: 0.0, from_max: 1.0, to_min: 0.05, to_max: 0.9,
+    });
+    let graph = material::Material::disney()
+            .base_color(core::Vec::one())
+            .metallic(1.0)
+            .roughness_texture(roughness)
+            .build();
+    assert!(!graph.is_constant());
+
+    let sp = surface_props();
+    let peak = |cos_theta: f32, roughness: f32| {
+        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+        let i = core::Vec::new(sin_theta, 0.0, cos_theta);
+        let o = core::Vec::new(-sin_theta, 0.0, cos_theta);
+        let fixed = material::Material::disney()
+                .base_color(core::Vec::one())
+                .metallic(1.0)
+                .roughness(roughness)
+                .build();
+        (graph.f_world(&i, &o, &sp, true).x, fixed.f_world(&i, &o, &sp, true).x)
+    };
+    for &(cos_theta, roughness) in &[(1.0, 0.9), (0.3, 0.05 + 0.85 * 0.3)] {
+        let (graph_f, fixed_f) = peak(cos_theta, roughness);
+        assert!(f32::abs(graph_f - fixed_f) <= 1e-4 * fixed_f,
+                "cos_theta={}: graph={} fixed={}", cos_theta, graph_f, fixed_f);
+    }
+}
+
+#[test]
+fn bsdfs_see_graphs_from_the_viewer() {
+    // Bright only when seen head-on. On light paths, the viewer is the direction being scattered
+    // into, not the one that light arrives from.
+    let color: Arc<dyn Texture> = Arc::new(ShaderNode::Mix(
+            constant(0.0), constant(1.0), Arc::new(ShaderNode::FacingRatio)));
+    let graph = material::Material::matte().color_texture(color).build();
+
+    let i = core::Vec::z_axis();
+    let o = core::Vec::new(0.8, 0.0, 0.6);
+    let head_on = graph.bsdf(surface_props(), &i);
+    let oblique = head_on.facing(&o);
+    let pi = std::f32::consts::PI;
+    assert_close(head_on.f_world(&i, &o, true), (1.0 / pi) * &core::Vec::one());
+    assert_close(oblique.f_world(&i, &o, false), (0.6 / pi) * &core::Vec::one());
+
+    let mut sp = surface_props();
+    sp.incoming = o;
+    assert_close(graph.f_world(&i, &o, &sp, false), (0.6 / pi) * &core::Vec::one());
+}
+
+/// Renders a sphere that's only bright at grazing angles, lit head-on from behind the camera,
+/// next to a diffuse sphere that sees its bright edge, and returns the average luminance.
+fn render_facing_ratio(integrator: &dyn render::Integrator) -> Luminance {
+    let base_color: Arc<dyn Texture> = Arc::new(ShaderNode::Mix(
+            constant(0.95), constant(0.02), Arc::new(ShaderNode::FacingRatio)));
+    let graph = material::Material::disney()
+            .base_color_texture(base_color)
+            .roughness(0.5)
+            .build();
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(graph,
+                core::Mat::translation(&core::Vec::new(-7.0, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse(core::Vec::new(0.8, 0.8, 0.8)),
+                core::Mat::translation(&core::Vec::new(7.5, 0.0, -50.0)), 7.0)),
+        Box::new(geom::Sphere::new(
+                material::Material::diffuse_light(core::Vec::new(50.0, 50.0, 50.0)),
+                core::Mat::translation(&core::Vec::new(0.0, 0.0, 10.0)), 3.0)),
+    ];
+    common::render_average_luminance(prims, integrator, ITERATIONS)
+}
+
+#[test]
+fn integrators_agree_on_view_dependent_graphs() {
+    let path_tracer = render_facing_ratio(&render::PathTracerIntegrator {});
+    let bdpt = render_facing_ratio(&render::BdptIntegrator {});
+    path_tracer.assert_agrees(&bdpt);
+}
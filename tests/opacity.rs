@@ -0,0 +1,49 @@
+// Opacity masks: rays and shadow rays must continue through masked-out surfaces to whatever is
+// behind them.
+
+extern crate gammaray;
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::SurfaceBuilder;
+
+/// Builds a scene of a sphere with the given opacity in front of an opaque sphere, both on the
+/// -z axis.
+fn masked_in_front(opacity: f32) -> geom::Bvh {
+    let prims: Vec<Box<dyn geom::Prim>> = vec![
+        Box::new(geom::Sphere::new(
+                material::Material::matte().opacity(opacity).build(),
+                core::Mat::translation(&core::Vec::new(0.0, 0.0, -10.0)), 1.0)),
+        Box::new(geom::Sphere::new(material::Material::diffuse(core::Vec::one()),
+                core::Mat::translation(&core::Vec::new(0.0, 0.0, -20.0)), 1.0)),
+    ];
+    geom::Bvh::build(prims)
+}
+
+/// Returns the prim index and distance of the ray's hit, if any.
+fn hit(intersection: &geom::Intersection) -> Option<(usize, f32)> {
+    match *intersection {
+        geom::Intersection::Hit {dist, prim_index, ..} => Some((prim_index, dist)),
+        geom::Intersection::NoHit => None
+    }
+}
+
+#[test]
+fn intersect_passes_through_transparent_surfaces() {
+    let ray = core::Ray::new(core::Vec::zero(), core::Vec::new(0.0, 0.0, -1.0));
+
+    let bvh = masked_in_front(0.0);
+    for intersection in &[bvh.intersect(&ray), bvh.intersect_naive(&ray)] {
+        let (prim_index, dist) = hit(intersection).expect("ray should hit the far sphere");
+        assert!(prim_index == 1, "prim_index={}", prim_index);
+        assert!(core::is_close(dist, 19.0, 1e-3), "dist={}", dist);
+    }
+    assert!(bvh.visibility(&core::Vec::zero(), &core::Vec::new(0.0, 0.0, -19.0)));
+
+    let bvh = masked_in_front(1.0);
+    let (prim_index, dist) = hit(&bvh.intersect(&ray)).expect("ray should hit the near sphere");
+    assert!(prim_index == 0, "prim_index={}", prim_index);
+    assert!(core::is_close(dist, 9.0, 1e-3), "dist={}", dist);
+    assert!(!bvh.visibility(&core::Vec::zero(), &core::Vec::new(0.0, 0.0, -19.0)));
+}
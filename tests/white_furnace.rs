@@ -0,0 +1,105 @@
+// White furnace tests: a white lobe lit uniformly from every direction should scatter all of the
+// light it receives, even at high roughness, once multiple scattering is accounted for.
+
+extern crate gammaray;
+extern crate rand;
+
+use gammaray::core;
+use gammaray::geom;
+use gammaray::material;
+use gammaray::material::Lobe;
+
+use rand::SeedableRng;
+
+const NUM_SAMPLES: usize = 100000;
+const TOLERANCE: f32 = 0.03;
+
+/// Estimates the fraction of the light arriving from the direction with the given cosine that the
+/// lobes scatter in total. Light is traced toward the camera, where transmission conserves energy.
+fn albedo(lobes: &[&dyn Lobe], cos_theta: f32, rng: &mut rand::XorShiftRng) -> f32 {
+    let i = core::Vec::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+    let mut total = 0.0;
+    for lobe in lobes {
+        let mut sum = 0.0;
+        for _ in 0..NUM_SAMPLES {
+            let sample = lobe.sample_f(&i, false, rng);
+            if sample.pdf > 0.0 {
+                sum += sample.result.x * sample.outgoing.abs_cos_theta() / sample.pdf;
+            }
+        }
+        total += sum / NUM_SAMPLES as f32;
+    }
+    total
+}
+
+/// Like albedo, but for all the lobes of a material, which chooses between them itself. The
+/// material is evaluated at a surface whose local space is world space.
+fn material_albedo(material: &material::Material, cos_theta: f32, rng: &mut rand::XorShiftRng)
+    -> f32
+{
+    let i = core::Vec::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+    let surface_props = geom::SurfaceProperties::new(
+            core::Vec::zero(), core::Vec::zero(), core::Vec::z_axis(), core::Vec::x_axis(),
+            core::Vec::y_axis(), core::Vec::z_axis(), core::Vec::zero());
+    let bsdf = material.bsdf(surface_props, &i);
+    let mut sum = 0.0;
+    for _ in 0..NUM_SAMPLES {
+        let sample = bsdf.sample_world(&i, false, rng);
+        if sample.pdf > 0.0 && sample.kind != material::LobeKind::LOBE_NONE {
+            sum += sample.radiance.x * sample.outgoing.abs_cos_theta() / sample.pdf;
+        }
+    }
+    sum / NUM_SAMPLES as f32
+}
+
+fn new_rng() -> rand::XorShiftRng {
+    rand::XorShiftRng::from_seed([0x2f6b1c3d, 0x5e8a9b07, 0x71c4d2e9, 0x0b3f6a85])
+}
+
+#[test]
+fn rough_metal() {
+    let mut rng = new_rng();
+    for &roughness in &[0.2, 0.5, 0.8, 1.0] {
+        let metal = material::DisneySpecularRefl::new(core::Vec::one(), roughness, 1.5, 0.0, 1.0);
+        for &cos_theta in &[1.0, 0.7, 0.4, 0.1] {
+            let albedo = albedo(&[&metal], cos_theta, &mut rng);
+            assert!(f32::abs(albedo - 1.0) < TOLERANCE,
+                    "roughness={} cos_theta={} albedo={}", roughness, cos_theta, albedo);
+        }
+    }
+}
+
+#[test]
+fn rough_glass() {
+    let mut rng = new_rng();
+    for &ior in &[1.33, 1.5, 2.4] {
+        for &roughness in &[0.2, 0.5, 0.8, 1.0] {
+            let refl = material::DisneySpecularRefl::new(
+                    core::Vec::one(), roughness, ior, 0.0, 0.0);
+            let trans = material::DisneySpecularTrans::new(core::Vec::one(), roughness, ior);
+            for &cos_theta in &[1.0, 0.7, 0.4, 0.1, -1.0, -0.7, -0.4] {
+                let albedo = albedo(&[&refl, &trans], cos_theta, &mut rng);
+                assert!(f32::abs(albedo - 1.0) < TOLERANCE,
+                        "ior={} roughness={} cos_theta={} albedo={}",
+                        ior, roughness, cos_theta, albedo);
+            }
+        }
+    }
+}
+
+#[test]
+fn smooth_disney_glass() {
+    let mut rng = new_rng();
+    for &ior in &[1.33, 1.5, 2.4] {
+        let glass = material::Material::disney()
+                .roughness(0.0)
+                .specular_trans(1.0)
+                .ior(ior)
+                .build();
+        for &cos_theta in &[1.0, 0.7, 0.4, 0.1, -1.0, -0.7, -0.4] {
+            let albedo = material_albedo(&glass, cos_theta, &mut rng);
+            assert!(f32::abs(albedo - 1.0) < TOLERANCE,
+                    "ior={} cos_theta={} albedo={}", ior, cos_theta, albedo);
+        }
+    }
+}